@@ -1,5 +1,5 @@
 use actix_web::{get, App, HttpRequest, HttpServer, Responder};
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use dev_certs::Certs;
 
 #[get("/")]
 async fn index(_req: HttpRequest) -> impl Responder {
@@ -8,14 +8,11 @@ async fn index(_req: HttpRequest) -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // load TLS keys
-    // to create a self-signed temporary cert for testing:
-    // `openssl req -x509 -newkey rsa:4096 -nodes -keyout key.pem -out cert.pem -days 365 -subj '/CN=localhost'`
-    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-    builder
-        .set_private_key_file("key.pem", SslFiletype::PEM)
-        .unwrap();
-    builder.set_certificate_chain_file("cert.pem").unwrap();
+    // load TLS keys: the PEM files named by TLS_CERT and TLS_KEY, or a
+    // development certificate for localhost generated (once) by dev-certs
+    let builder = Certs::from_env_or_dev()?
+        .ssl_acceptor()
+        .map_err(std::io::Error::other)?;
 
     HttpServer::new(|| App::new().service(index))
         .bind_openssl("127.0.0.1:8080", builder)?
@@ -23,10 +20,5 @@ async fn main() -> std::io::Result<()> {
         .await
 }
 
-// To create the key.pem and cert.pem use the command
-// $ openssl req -x509 -newkey rsa:4096 -keyout key.pem -out cert.pem -days 365 -sha256 -subj "/C=CN/ST=Fujian/L=Xiamen/O=TVlinux/OU=Org/CN=muro.lxd"
-// pass: 123456
-// To remove the password, then copy nopass.pem to key.pem
-// $ openssl rsa -in key.pem -out nopass.pem
-
-// curl "https://127.0.0.1:8080/"
+// curl --cacert ~/.cache/dev-certs/ca.pem "https://127.0.0.1:8080/"
+// TLS_CERT=cert.pem TLS_KEY=key.pem cargo run
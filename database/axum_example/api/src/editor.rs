@@ -0,0 +1,135 @@
+//! The gate in front of everything that is not public: writing posts, and
+//! reading drafts and archived posts, both in the HTML editor and through
+//! `/api/posts`.
+//!
+//! Editors sign in with HTTP Basic auth, any user name and the password in
+//! `EDITOR_PASSWORD`, which browsers prompt for by themselves. Until that is
+//! set the blog only shows its published posts.
+
+use std::env;
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::AppState;
+
+#[derive(Clone, Default)]
+pub struct Gate {
+    password: Option<Arc<str>>,
+}
+
+impl Gate {
+    pub fn new(password: &str) -> Gate {
+        Gate {
+            password: Some(password.into()),
+        }
+    }
+
+    pub fn from_env() -> Gate {
+        match env::var("EDITOR_PASSWORD") {
+            Ok(password) if !password.is_empty() => Gate::new(&password),
+            _ => {
+                tracing::warn!("EDITOR_PASSWORD is not set, only published posts can be read");
+                Gate::default()
+            }
+        }
+    }
+
+    /// Whether `headers` carry the editor's credentials.
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        let Some(password) = &self.password else {
+            return false;
+        };
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| {
+                let split = decoded.iter().position(|&b| b == b':')?;
+                Some(constant_time_eq(&decoded[split + 1..], password.as_bytes()))
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Extracting this fails with [`Unauthorized`] unless the request comes from
+/// an editor.
+pub struct Editor;
+
+#[async_trait]
+impl FromRequestParts<AppState> for Editor {
+    type Rejection = Unauthorized;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if state.gate.allows(&parts.headers) {
+            Ok(Editor)
+        } else {
+            Err(Unauthorized)
+        }
+    }
+}
+
+/// A 401 that makes browsers ask for the editor's password.
+pub struct Unauthorized;
+
+impl IntoResponse for Unauthorized {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(
+                header::WWW_AUTHENTICATE,
+                r#"Basic realm="editor", charset="UTF-8""#,
+            )],
+            "Editor sign-in required",
+        )
+            .into_response()
+    }
+}
+
+/// Compares every byte, however early the passwords differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn basic(credentials: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Basic {}", STANDARD.encode(credentials));
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn checks_the_password() {
+        let gate = Gate::new("hunter2");
+        assert!(gate.allows(&basic("anyone:hunter2")));
+        assert!(gate.allows(&basic(":hunter2")));
+        assert!(!gate.allows(&basic("anyone:hunter3")));
+        assert!(!gate.allows(&basic("hunter2")));
+        assert!(!gate.allows(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn refuses_everyone_without_a_password() {
+        let gate = Gate::default();
+        assert!(!gate.allows(&basic("anyone:")));
+        assert!(!gate.allows(&HeaderMap::new()));
+    }
+}
@@ -32,6 +32,14 @@ where
 pub type PostResponse = (StatusCode, HeaderMap);
 
 pub fn post_response<T>(cookies: &mut Cookies, data: T) -> PostResponse
+where
+    T: Serialize,
+{
+    post_response_to(cookies, data, "/")
+}
+
+/// Like [`post_response`], but redirects to `location` instead of the index.
+pub fn post_response_to<T>(cookies: &mut Cookies, data: T, location: &str) -> PostResponse
 where
     T: Serialize,
 {
@@ -45,7 +53,10 @@ where
     cookies.add(cookie);
 
     let mut header = HeaderMap::new();
-    header.insert(header::LOCATION, HeaderValue::from_static("/"));
+    header.insert(
+        header::LOCATION,
+        HeaderValue::from_str(location).unwrap_or(HeaderValue::from_static("/")),
+    );
 
     (StatusCode::SEE_OTHER, header)
 }
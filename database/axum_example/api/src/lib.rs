@@ -1,21 +1,27 @@
+mod editor;
 mod flash;
+mod markdown;
+mod rest;
 
 use axum::{
     extract::{Form, Path, Query, State},
-    http::StatusCode,
-    response::Html,
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
     routing::{get, get_service, post},
     Router,
 };
 use axum_example_service::{
-    sea_orm::{Database, DatabaseConnection},
-    Mutation as MutationCore, Query as QueryCore,
+    sea_orm::{Database, DatabaseConnection, DbErr, TryIntoModel},
+    tag_names, Mutation as MutationCore, PostDetails, Query as QueryCore,
 };
-use entity::post;
-use flash::{get_flash_cookie, post_response, PostResponse};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use editor::{Editor, Gate, Unauthorized};
+use entity::post::{self, PostStatus};
+use flash::{get_flash_cookie, post_response, post_response_to, PostResponse};
 use migration::{Migrator, MigratorTrait};
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::time::Duration;
 use tera::Tera;
 use tower_cookies::{CookieManagerLayer, Cookies};
 use tower_http::services::ServeDir;
@@ -35,17 +41,30 @@ async fn start() -> anyhow::Result<()> {
         .await
         .expect("Database connection failed");
     Migrator::up(&conn, None).await.unwrap();
+    tokio::spawn(publish_scheduled_posts(conn.clone()));
 
-    let templates = Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*"))
+    let mut templates = Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*"))
         .expect("Tera initialization failed");
-
-    let state = AppState { templates, conn };
+    // Tera only escapes `.html` templates by default; rendered post bodies
+    // are sanitised and opt out with `| safe`.
+    templates.autoescape_on(vec![".html.tera"]);
+
+    let state = AppState {
+        templates,
+        conn,
+        gate: Gate::from_env(),
+    };
 
     let app = Router::new()
         .route("/", get(list_posts).post(create_post))
         .route("/:id", get(edit_post).post(update_post))
         .route("/new", get(new_post))
+        .route("/preview", post(preview_post))
+        .route("/highlight.css", get(highlight_css))
+        .route("/:id/comments", post(create_comment))
+        .route("/tags/:name", get(list_posts_by_tag))
         .route("/delete/:id", post(delete_post))
+        .nest("/api/posts", rest::router())
         .nest_service(
             "/static",
             get_service(ServeDir::new(concat!(
@@ -68,16 +87,116 @@ async fn start() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// How often scheduled drafts are checked for publication.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Publishes drafts once their `published_at` has passed, for as long as the
+/// server runs.
+async fn publish_scheduled_posts(conn: DatabaseConnection) {
+    let mut interval = tokio::time::interval(PUBLISH_INTERVAL);
+    loop {
+        interval.tick().await;
+        match MutationCore::publish_scheduled_posts(&conn, Utc::now()).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("published {count} scheduled post(s)"),
+            Err(err) => tracing::error!("could not publish scheduled posts: {err}"),
+        }
+    }
+}
+
 #[derive(Clone)]
 struct AppState {
     templates: Tera,
     conn: DatabaseConnection,
+    gate: Gate,
 }
 
 #[derive(Deserialize)]
 struct Params {
     page: Option<u64>,
     posts_per_page: Option<u64>,
+    /// Which posts to list, published ones by default.
+    status: Option<PostStatus>,
+}
+
+/// The fields of the new and edit forms.
+#[derive(Deserialize)]
+struct PostForm {
+    title: String,
+    text: String,
+    #[serde(default)]
+    author: String,
+    /// Comma separated.
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    status: PostStatus,
+    /// From a `datetime-local` input, in UTC; may be empty.
+    #[serde(default)]
+    published_at: String,
+}
+
+impl PostForm {
+    fn published_at(&self) -> Result<Option<DateTime<Utc>>, (StatusCode, &'static str)> {
+        let value = self.published_at.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+            .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+            .map(|at| Some(at.and_utc()))
+            .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, "Invalid publication date"))
+    }
+
+    /// The post to hand to the service; the timestamps it sets itself.
+    fn into_model(
+        self,
+        id: i32,
+        author_id: Option<i32>,
+    ) -> Result<post::Model, (StatusCode, &'static str)> {
+        let now = Utc::now();
+        Ok(post::Model {
+            id,
+            published_at: self.published_at()?,
+            title: self.title,
+            text: self.text,
+            author_id,
+            status: self.status,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct CommentForm {
+    #[serde(default)]
+    author: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct PreviewForm {
+    text: String,
+}
+
+/// A post along with its body rendered from Markdown.
+#[derive(Serialize)]
+struct RenderedPost {
+    #[serde(flatten)]
+    post: PostDetails,
+    html: String,
+}
+
+impl From<PostDetails> for RenderedPost {
+    fn from(post: PostDetails) -> Self {
+        let html = markdown::render(&post.post.text);
+        Self { post, html }
+    }
+}
+
+fn render_posts(posts: Vec<PostDetails>) -> Vec<RenderedPost> {
+    posts.into_iter().map(RenderedPost::from).collect()
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -89,20 +208,30 @@ struct FlashData {
 async fn list_posts(
     state: State<AppState>,
     Query(params): Query<Params>,
+    editor: Option<Editor>,
     cookies: Cookies,
-) -> Result<Html<String>, (StatusCode, &'static str)> {
+) -> Result<Html<String>, Response> {
     let page = params.page.unwrap_or(1);
     let posts_per_page = params.posts_per_page.unwrap_or(5);
+    let status = params.status.unwrap_or(PostStatus::Published);
+    if status != PostStatus::Published && editor.is_none() {
+        return Err(Unauthorized.into_response());
+    }
 
-    let (posts, num_pages) = QueryCore::find_posts_in_page(&state.conn, page, posts_per_page)
+    let (posts, num_pages) =
+        QueryCore::find_posts_in_page(&state.conn, status, page, posts_per_page)
+            .await
+            .expect("Cannot find posts in page");
+    let posts = QueryCore::find_post_details(&state.conn, posts)
         .await
-        .expect("Cannot find posts in page");
+        .expect("Cannot find post authors and tags");
 
     let mut ctx = tera::Context::new();
-    ctx.insert("posts", &posts);
+    ctx.insert("posts", &render_posts(posts));
     ctx.insert("page", &page);
     ctx.insert("posts_per_page", &posts_per_page);
     ctx.insert("num_pages", &num_pages);
+    ctx.insert("status", &status);
 
     if let Some(value) = get_flash_cookie::<FlashData>(&cookies) {
         ctx.insert("flash", &value);
@@ -111,12 +240,15 @@ async fn list_posts(
     let body = state
         .templates
         .render("index.html.tera", &ctx)
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Template error"))?;
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response())?;
 
     Ok(Html(body))
 }
 
-async fn new_post(state: State<AppState>) -> Result<Html<String>, (StatusCode, &'static str)> {
+async fn new_post(
+    _: Editor,
+    state: State<AppState>,
+) -> Result<Html<String>, (StatusCode, &'static str)> {
     let ctx = tera::Context::new();
     let body = state
         .templates
@@ -126,16 +258,69 @@ async fn new_post(state: State<AppState>) -> Result<Html<String>, (StatusCode, &
     Ok(Html(body))
 }
 
+async fn list_posts_by_tag(
+    state: State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Html<String>, (StatusCode, &'static str)> {
+    let posts = QueryCore::find_posts_by_tag(&state.conn, &name)
+        .await
+        .expect("Cannot find posts by tag");
+    let posts = QueryCore::find_post_details(&state.conn, posts)
+        .await
+        .expect("Cannot find post authors and tags");
+
+    let mut ctx = tera::Context::new();
+    ctx.insert("tag", &name);
+    ctx.insert("posts", &render_posts(posts));
+
+    let body = state
+        .templates
+        .render("tag.html.tera", &ctx)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Template error"))?;
+
+    Ok(Html(body))
+}
+
+/// Renders the body being edited, for the preview pane of the post forms.
+async fn preview_post(form: Form<PreviewForm>) -> Html<String> {
+    Html(markdown::render(&form.text))
+}
+
+async fn highlight_css() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/css")], markdown::stylesheet())
+}
+
+/// The id of the user called `name`, or `None` for a blank name.
+async fn author_id(conn: &DatabaseConnection, name: &str) -> Result<Option<i32>, DbErr> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        MutationCore::find_or_create_user(conn, name).await?.id,
+    ))
+}
+
 async fn create_post(
+    _: Editor,
     state: State<AppState>,
     mut cookies: Cookies,
-    form: Form<post::Model>,
+    form: Form<PostForm>,
 ) -> Result<PostResponse, (StatusCode, &'static str)> {
     let form = form.0;
+    let author_id = author_id(&state.conn, &form.author)
+        .await
+        .expect("could not find author");
+
+    let tags = tag_names(&form.tags);
 
-    MutationCore::create_post(&state.conn, form)
+    let post = MutationCore::create_post(&state.conn, form.into_model(0, author_id)?)
         .await
+        .and_then(TryIntoModel::try_into_model)
         .expect("could not insert post");
+    MutationCore::set_post_tags(&state.conn, post.id, &tags)
+        .await
+        .expect("could not tag post");
 
     let data = FlashData {
         kind: "success".to_owned(),
@@ -145,37 +330,72 @@ async fn create_post(
     Ok(post_response(&mut cookies, data))
 }
 
+/// The post's page, with its comments and the edit form. Anyone may see
+/// published posts; the rest only editors.
 async fn edit_post(
     state: State<AppState>,
     Path(id): Path<i32>,
-) -> Result<Html<String>, (StatusCode, &'static str)> {
-    let post: post::Model = QueryCore::find_post_by_id(&state.conn, id)
+    editor: Option<Editor>,
+    cookies: Cookies,
+) -> Result<Html<String>, Response> {
+    let (post, comments) = QueryCore::find_post_with_comments(&state.conn, id)
         .await
         .expect("could not find post")
         .unwrap_or_else(|| panic!("could not find post with id {id}"));
+    if post.post.status != PostStatus::Published && editor.is_none() {
+        return Err(Unauthorized.into_response());
+    }
+    let tags: Vec<&str> = post.tags.iter().map(|tag| tag.name.as_str()).collect();
 
     let mut ctx = tera::Context::new();
+    ctx.insert("preview", &markdown::render(&post.post.text));
     ctx.insert("post", &post);
+    ctx.insert("tags", &tags.join(", "));
+    ctx.insert("comments", &comments);
+    // Earlier versions may hold text that was taken down, so only editors
+    // see the history.
+    if editor.is_some() {
+        let revisions = QueryCore::find_post_revisions(&state.conn, id)
+            .await
+            .expect("could not find post history");
+        ctx.insert("revisions", &revisions);
+    }
+
+    if let Some(value) = get_flash_cookie::<FlashData>(&cookies) {
+        ctx.insert("flash", &value);
+    }
 
     let body = state
         .templates
         .render("edit.html.tera", &ctx)
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Template error"))?;
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Template error").into_response())?;
 
     Ok(Html(body))
 }
 
 async fn update_post(
+    _: Editor,
     state: State<AppState>,
     Path(id): Path<i32>,
     mut cookies: Cookies,
-    form: Form<post::Model>,
+    form: Form<PostForm>,
 ) -> Result<PostResponse, (StatusCode, String)> {
     let form = form.0;
+    let author_id = author_id(&state.conn, &form.author)
+        .await
+        .expect("could not find author");
 
-    MutationCore::update_post_by_id(&state.conn, id, form)
+    let tags = tag_names(&form.tags);
+    let post = form
+        .into_model(id, author_id)
+        .map_err(|(status, message)| (status, message.to_owned()))?;
+
+    MutationCore::update_post_by_id(&state.conn, id, post)
         .await
         .expect("could not edit post");
+    MutationCore::set_post_tags(&state.conn, id, &tags)
+        .await
+        .expect("could not tag post");
 
     let data = FlashData {
         kind: "success".to_owned(),
@@ -186,6 +406,7 @@ async fn update_post(
 }
 
 async fn delete_post(
+    _: Editor,
     state: State<AppState>,
     Path(id): Path<i32>,
     mut cookies: Cookies,
@@ -202,6 +423,44 @@ async fn delete_post(
     Ok(post_response(&mut cookies, data))
 }
 
+async fn create_comment(
+    state: State<AppState>,
+    Path(id): Path<i32>,
+    editor: Option<Editor>,
+    mut cookies: Cookies,
+    form: Form<CommentForm>,
+) -> Result<PostResponse, Response> {
+    let form = form.0;
+    if form.text.trim().is_empty() {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "Comment is empty").into_response());
+    }
+    let Some(post) = QueryCore::find_post_by_id(&state.conn, id)
+        .await
+        .expect("could not find post")
+    else {
+        return Err((StatusCode::NOT_FOUND, "Post not found").into_response());
+    };
+    // Only editors can see posts that are not published, so only they can
+    // comment on them.
+    if post.status != PostStatus::Published && editor.is_none() {
+        return Err(Unauthorized.into_response());
+    }
+    let author_id = author_id(&state.conn, &form.author)
+        .await
+        .expect("could not find author");
+
+    MutationCore::create_comment(&state.conn, id, author_id, &form.text)
+        .await
+        .expect("could not add comment");
+
+    let data = FlashData {
+        kind: "success".to_owned(),
+        message: "Comment succcessfully added".to_owned(),
+    };
+
+    Ok(post_response_to(&mut cookies, data, &format!("/{id}")))
+}
+
 pub fn main() {
     let result = start();
 
@@ -0,0 +1,170 @@
+//! Rendering of post bodies, which are written in Markdown.
+//!
+//! Everything a post renders to goes through [`ammonia`] last, so raw HTML in
+//! a post cannot inject scripts or event handlers. Fenced code blocks are
+//! highlighted with [`syntect`] using CSS classes rather than inline styles,
+//! which keeps `style` attributes out of the sanitiser's allow list; the
+//! matching stylesheet is served from [`stylesheet`].
+
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// Prefix of every class the highlighter emits, and the only classes the
+/// sanitiser lets through.
+const CLASS_PREFIX: &str = "hl-";
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed {
+    prefix: CLASS_PREFIX,
+};
+const THEME: &str = "InspiredGitHub";
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+static STYLESHEET: LazyLock<String> = LazyLock::new(|| {
+    let themes = ThemeSet::load_defaults();
+    css_for_theme_with_class_style(&themes.themes[THEME], CLASS_STYLE)
+        .expect("the bundled theme converts to CSS")
+});
+
+static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tag_attributes("pre", ["class"])
+        .add_tag_attributes("code", ["class"])
+        .add_tag_attributes("span", ["class"])
+        .attribute_filter(|_, attribute, value| match attribute {
+            "class" => {
+                let classes: Vec<&str> = value
+                    .split_whitespace()
+                    .filter(|class| class.starts_with(CLASS_PREFIX))
+                    .collect();
+                (!classes.is_empty()).then(|| Cow::Owned(classes.join(" ")))
+            }
+            _ => Some(Cow::Borrowed(value)),
+        });
+    builder
+});
+
+/// Renders a post body to HTML that is safe to embed in a page as is.
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut events = Vec::new();
+    // The language and source of the code block being collected, if any.
+    let mut code_block: Option<(String, String)> = None;
+
+    for event in Parser::new_ext(markdown, options) {
+        match (event, &mut code_block) {
+            (Event::Start(Tag::CodeBlock(kind)), None) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_owned()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((language, String::new()));
+            }
+            (Event::Text(text), Some((_, source))) => source.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some((language, source))) => {
+                events.push(Event::Html(highlight(language, source).into()));
+                code_block = None;
+            }
+            (event, _) => events.push(event),
+        }
+    }
+
+    let mut unsanitized = String::new();
+    html::push_html(&mut unsanitized, events.into_iter());
+    SANITIZER.clean(&unsanitized).to_string()
+}
+
+/// The CSS for the classes [`render`] puts on highlighted code.
+pub fn stylesheet() -> &'static str {
+    &STYLESHEET
+}
+
+/// Highlights `source` as `language`, or as plain text if the language is
+/// unknown or blank.
+fn highlight(language: &str, source: &str) -> String {
+    let syntax = SYNTAXES
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(source) {
+        if generator
+            .parse_html_for_line_which_includes_newline(line)
+            .is_err()
+        {
+            return format!("<pre><code>{}</code></pre>", escape(source));
+        }
+    }
+    format!(
+        "<pre class=\"{CLASS_PREFIX}code\"><code>{}</code></pre>",
+        generator.finalize()
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_markdown() {
+        assert_eq!(
+            render("Hello *world*\n\n- one\n- two"),
+            "<p>Hello <em>world</em></p>\n<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn strips_scripts_and_handlers() {
+        let html = render(
+            "<script>alert(1)</script>\n\n<img src=x onerror=\"alert(2)\">\n\n[link](javascript:alert(3))",
+        );
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("onerror"), "{html}");
+        assert!(!html.contains("javascript:"), "{html}");
+    }
+
+    #[test]
+    fn drops_foreign_classes() {
+        let html = render("<span class=\"hl-keyword evil\">x</span>");
+        assert!(html.contains("class=\"hl-keyword\""), "{html}");
+        assert!(!html.contains("evil"), "{html}");
+    }
+
+    #[test]
+    fn highlights_fenced_code() {
+        let html = render("```rust\nfn main() {}\n```\n");
+        assert!(html.starts_with("<pre class=\"hl-code\"><code>"), "{html}");
+        assert!(
+            html.contains("<span class=\"hl-storage hl-type hl-function hl-rust\">fn</span>"),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn escapes_unknown_languages() {
+        let html = render("```nonsense\n<b>&</b>\n```\n");
+        assert!(html.contains("&lt;b&gt;&amp;&lt;/b&gt;"), "{html}");
+        assert!(!html.contains("<b>"), "{html}");
+    }
+}
@@ -0,0 +1,289 @@
+//! `/api/posts`: the posts as JSON, for clients other than the browser.
+//!
+//! Requests must accept `application/json` (406 otherwise) and send JSON
+//! bodies (415 otherwise). Errors come back as `{"error": "..."}`.
+//!
+//! Anyone may read published posts. Drafts and archived posts, which lists
+//! show when `?status=` asks for them, and every write need the editor's
+//! credentials, see [`editor`](crate::editor); to anyone else a post that is
+//! not published does not exist.
+
+use axum::{
+    extract::{rejection::JsonRejection, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use axum_example_service::{
+    sea_orm::{DbErr, TryIntoModel},
+    Mutation as MutationCore, Query as QueryCore,
+};
+use chrono::{DateTime, Utc};
+use entity::post::{self, PostStatus};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{editor::Editor, AppState, Params};
+
+const MAX_POSTS_PER_PAGE: u64 = 100;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_posts).post(create_post))
+        .route("/:id", get(get_post).put(update_post).delete(delete_post))
+        .layer(middleware::from_fn(require_json))
+}
+
+#[derive(Serialize)]
+struct PostPage {
+    posts: Vec<post::Model>,
+    page: u64,
+    posts_per_page: u64,
+    num_pages: u64,
+    num_posts: u64,
+}
+
+#[derive(Deserialize)]
+struct PostInput {
+    title: String,
+    text: String,
+    /// Defaults to draft.
+    #[serde(default)]
+    status: PostStatus,
+    /// Defaults to now when publishing, or to the date a post that is
+    /// already published has.
+    #[serde(default)]
+    published_at: Option<DateTime<Utc>>,
+}
+
+impl PostInput {
+    fn into_model(self) -> Result<post::Model, ApiError> {
+        if self.title.trim().is_empty() {
+            return Err(ApiError::Invalid("title must not be empty".to_owned()));
+        }
+        // The service sets the timestamps.
+        let now = Utc::now();
+        Ok(post::Model {
+            id: 0,
+            title: self.title,
+            text: self.text,
+            author_id: None,
+            status: self.status,
+            created_at: now,
+            updated_at: now,
+            published_at: self.published_at,
+        })
+    }
+}
+
+enum ApiError {
+    NotFound,
+    Unauthorized,
+    BadRequest(String),
+    Invalid(String),
+    Rejected(JsonRejection),
+    NotAcceptable,
+    Db(DbErr),
+}
+
+impl From<DbErr> for ApiError {
+    fn from(err: DbErr) -> Self {
+        ApiError::Db(err)
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::Rejected(rejection)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::NotFound => (StatusCode::NOT_FOUND, "post not found".to_owned()),
+            ApiError::Unauthorized => {
+                let mut response = (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({ "error": "editor credentials required" })),
+                )
+                    .into_response();
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="editor", charset="UTF-8""#),
+                );
+                return response;
+            }
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::Invalid(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+            ApiError::Rejected(rejection) => (rejection.status(), rejection.body_text()),
+            ApiError::NotAcceptable => (
+                StatusCode::NOT_ACCEPTABLE,
+                "this resource is only available as application/json".to_owned(),
+            ),
+            ApiError::Db(err) => {
+                tracing::error!("database error: {err}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "database error".to_owned(),
+                )
+            }
+        };
+        (status, Json(json!({ "error": message }))).into_response()
+    }
+}
+
+async fn require_json(req: Request, next: Next) -> Response {
+    if accepts_json(req.headers()) {
+        next.run(req).await
+    } else {
+        ApiError::NotAcceptable.into_response()
+    }
+}
+
+/// Whether the `Accept` header, if any, allows a JSON response.
+fn accepts_json(headers: &HeaderMap) -> bool {
+    let mut ranges = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .peekable();
+    if ranges.peek().is_none() {
+        return true;
+    }
+    ranges.any(|range| {
+        let mut parts = range.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        quality > 0.0
+            && matches!(
+                media_type.as_str(),
+                "application/json" | "application/*" | "*/*"
+            )
+    })
+}
+
+async fn list_posts(
+    state: State<AppState>,
+    Query(params): Query<Params>,
+    editor: Option<Editor>,
+) -> Result<Json<PostPage>, ApiError> {
+    let page = params.page.unwrap_or(1);
+    let posts_per_page = params.posts_per_page.unwrap_or(5);
+    let status = params.status.unwrap_or(PostStatus::Published);
+    if status != PostStatus::Published && editor.is_none() {
+        return Err(ApiError::Unauthorized);
+    }
+    if page == 0 {
+        return Err(ApiError::BadRequest("page starts at 1".to_owned()));
+    }
+    if !(1..=MAX_POSTS_PER_PAGE).contains(&posts_per_page) {
+        return Err(ApiError::BadRequest(format!(
+            "posts_per_page must be between 1 and {MAX_POSTS_PER_PAGE}"
+        )));
+    }
+
+    let (posts, totals) =
+        QueryCore::find_posts_in_page_with_total(&state.conn, status, page, posts_per_page).await?;
+
+    Ok(Json(PostPage {
+        posts,
+        page,
+        posts_per_page,
+        num_pages: totals.number_of_pages,
+        num_posts: totals.number_of_items,
+    }))
+}
+
+async fn get_post(
+    state: State<AppState>,
+    Path(id): Path<i32>,
+    editor: Option<Editor>,
+) -> Result<Json<post::Model>, ApiError> {
+    QueryCore::find_post_by_id(&state.conn, id)
+        .await?
+        .filter(|post| post.status == PostStatus::Published || editor.is_some())
+        .map(Json)
+        .ok_or(ApiError::NotFound)
+}
+
+async fn create_post(
+    state: State<AppState>,
+    editor: Option<Editor>,
+    input: Result<Json<PostInput>, JsonRejection>,
+) -> Result<(StatusCode, HeaderMap, Json<post::Model>), ApiError> {
+    editor.ok_or(ApiError::Unauthorized)?;
+    let Json(input) = input?;
+    let post = MutationCore::create_post(&state.conn, input.into_model()?)
+        .await?
+        .try_into_model()?;
+
+    let mut headers = HeaderMap::new();
+    let location = format!("/api/posts/{}", post.id);
+    if let Ok(location) = HeaderValue::from_str(&location) {
+        headers.insert(header::LOCATION, location);
+    }
+
+    Ok((StatusCode::CREATED, headers, Json(post)))
+}
+
+async fn update_post(
+    state: State<AppState>,
+    Path(id): Path<i32>,
+    editor: Option<Editor>,
+    input: Result<Json<PostInput>, JsonRejection>,
+) -> Result<Json<post::Model>, ApiError> {
+    editor.ok_or(ApiError::Unauthorized)?;
+    let Json(input) = input?;
+    let mut form = input.into_model()?;
+    let Some(existing) = QueryCore::find_post_by_id(&state.conn, id).await? else {
+        return Err(ApiError::NotFound);
+    };
+    form.author_id = existing.author_id;
+
+    let post = MutationCore::update_post_by_id(&state.conn, id, form).await?;
+
+    Ok(Json(post))
+}
+
+async fn delete_post(
+    state: State<AppState>,
+    Path(id): Path<i32>,
+    editor: Option<Editor>,
+) -> Result<StatusCode, ApiError> {
+    editor.ok_or(ApiError::Unauthorized)?;
+    if QueryCore::find_post_by_id(&state.conn, id).await?.is_none() {
+        return Err(ApiError::NotFound);
+    }
+
+    MutationCore::delete_post(&state.conn, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn negotiates_json() {
+        assert!(accepts_json(&HeaderMap::new()));
+        assert!(accepts_json(&accept("application/json")));
+        assert!(accepts_json(&accept("text/html, */*;q=0.8")));
+        assert!(accepts_json(&accept("Application/JSON; charset=utf-8")));
+        assert!(!accepts_json(&accept("text/html")));
+        assert!(!accepts_json(&accept("application/json;q=0, text/html")));
+    }
+}
@@ -1 +1,6 @@
+pub mod comment;
 pub mod post;
+pub mod post_revision;
+pub mod post_tag;
+pub mod tag;
+pub mod user;
@@ -1,12 +1,26 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20220120_000001_create_post_table;
+mod m20240301_000001_create_user_table;
+mod m20240301_000002_add_post_author;
+mod m20240301_000003_create_comment_table;
+mod m20240301_000004_create_tag_tables;
+mod m20240315_000001_add_post_workflow;
+mod m20240315_000002_create_post_revision_table;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220120_000001_create_post_table::Migration)]
+        vec![
+            Box::new(m20220120_000001_create_post_table::Migration),
+            Box::new(m20240301_000001_create_user_table::Migration),
+            Box::new(m20240301_000002_add_post_author::Migration),
+            Box::new(m20240301_000003_create_comment_table::Migration),
+            Box::new(m20240301_000004_create_tag_tables::Migration),
+            Box::new(m20240315_000001_add_post_workflow::Migration),
+            Box::new(m20240315_000002_create_post_revision_table::Migration),
+        ]
     }
 }
@@ -0,0 +1,65 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Comments::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Comments::Id))
+                    .col(integer(Comments::PostId))
+                    .col(integer_null(Comments::AuthorId))
+                    .col(text(Comments::Text))
+                    .col(timestamp_with_time_zone(Comments::CreatedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-comments-post_id")
+                            .from(Comments::Table, Comments::PostId)
+                            .to(Posts::Table, Posts::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-comments-author_id")
+                            .from(Comments::Table, Comments::AuthorId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Comments::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Comments {
+    Table,
+    Id,
+    PostId,
+    AuthorId,
+    Text,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Posts {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
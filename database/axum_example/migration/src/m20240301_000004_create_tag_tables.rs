@@ -0,0 +1,75 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Tags::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Tags::Id))
+                    .col(string_uniq(Tags::Name))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(PostTags::Table)
+                    .if_not_exists()
+                    .col(integer(PostTags::PostId))
+                    .col(integer(PostTags::TagId))
+                    .primary_key(Index::create().col(PostTags::PostId).col(PostTags::TagId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-post_tags-post_id")
+                            .from(PostTags::Table, PostTags::PostId)
+                            .to(Posts::Table, Posts::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-post_tags-tag_id")
+                            .from(PostTags::Table, PostTags::TagId)
+                            .to(Tags::Table, Tags::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PostTags::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Tags::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Tags {
+    Table,
+    Id,
+    Name,
+}
+
+#[derive(DeriveIden)]
+enum PostTags {
+    Table,
+    PostId,
+    TagId,
+}
+
+#[derive(DeriveIden)]
+enum Posts {
+    Table,
+    Id,
+}
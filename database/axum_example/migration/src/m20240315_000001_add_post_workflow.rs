@@ -0,0 +1,100 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX_NAME: &str = "idx-posts-status-published_at";
+
+/// Placeholder for the timestamps of existing rows until they are set below;
+/// SQLite only accepts constant defaults when adding columns.
+const EPOCH: &str = "2000-01-01 00:00:00";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // One column per statement, as SQLite wants. Posts written before
+        // the workflow existed were public, so they start out published.
+        for column in [
+            string_len(Posts::Status, 16)
+                .default("published")
+                .to_owned(),
+            timestamp_with_time_zone(Posts::CreatedAt)
+                .default(EPOCH)
+                .to_owned(),
+            timestamp_with_time_zone(Posts::UpdatedAt)
+                .default(EPOCH)
+                .to_owned(),
+            timestamp_with_time_zone_null(Posts::PublishedAt),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Posts::Table)
+                        .add_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Posts::Table)
+                    .values([
+                        (Posts::CreatedAt, Expr::current_timestamp().into()),
+                        (Posts::UpdatedAt, Expr::current_timestamp().into()),
+                        (Posts::PublishedAt, Expr::current_timestamp().into()),
+                    ])
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(INDEX_NAME)
+                    .table(Posts::Table)
+                    .col(Posts::Status)
+                    .col(Posts::PublishedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(INDEX_NAME)
+                    .table(Posts::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        for column in [
+            Posts::Status,
+            Posts::CreatedAt,
+            Posts::UpdatedAt,
+            Posts::PublishedAt,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Posts::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Posts {
+    Table,
+    Status,
+    CreatedAt,
+    UpdatedAt,
+    PublishedAt,
+}
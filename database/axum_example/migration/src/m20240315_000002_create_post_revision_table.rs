@@ -0,0 +1,54 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PostRevisions::Table)
+                    .if_not_exists()
+                    .col(pk_auto(PostRevisions::Id))
+                    .col(integer(PostRevisions::PostId))
+                    .col(string(PostRevisions::Title))
+                    .col(text(PostRevisions::Text))
+                    .col(string_len(PostRevisions::Status, 16))
+                    .col(timestamp_with_time_zone(PostRevisions::EditedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-post_revisions-post_id")
+                            .from(PostRevisions::Table, PostRevisions::PostId)
+                            .to(Posts::Table, Posts::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PostRevisions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PostRevisions {
+    Table,
+    Id,
+    PostId,
+    Title,
+    Text,
+    Status,
+    EditedAt,
+}
+
+#[derive(DeriveIden)]
+enum Posts {
+    Table,
+    Id,
+}
@@ -1,40 +1,133 @@
-use ::entity::{post, post::Entity as Post};
-use sea_orm::*;
+use ::entity::{
+    comment,
+    post::{self, Entity as Post, PostStatus},
+    post_revision::{self, Entity as PostRevision},
+    post_tag::{self, Entity as PostTag},
+    tag::{self, Entity as Tag},
+    user::{self, Entity as User},
+};
+use chrono::Utc;
+use sea_orm::{prelude::DateTimeUtc, sea_query::Expr, *};
 
 pub struct Mutation;
 
+/// Splits a comma separated list of tags into distinct, lowercase names.
+pub fn tag_names(input: &str) -> Vec<String> {
+    let mut names: Vec<String> = input
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The status and publication time to store for a post saved at `now`.
+///
+/// Publishing without a date publishes now; publishing with a date still in
+/// the future keeps the post a draft until
+/// [`Mutation::publish_scheduled_posts`] picks it up. A draft only keeps a
+/// date that is still to come, so unpublishing a post, which still carries
+/// the date it was published, does not get it republished.
+pub fn schedule(
+    status: PostStatus,
+    published_at: Option<DateTimeUtc>,
+    now: DateTimeUtc,
+) -> (PostStatus, Option<DateTimeUtc>) {
+    match (status, published_at) {
+        (PostStatus::Published, None) => (PostStatus::Published, Some(now)),
+        (PostStatus::Published, Some(at)) if at > now => (PostStatus::Draft, Some(at)),
+        (PostStatus::Draft, Some(at)) if at <= now => (PostStatus::Draft, None),
+        _ => (status, published_at),
+    }
+}
+
 impl Mutation {
+    /// Creates the post; its `created_at` and `updated_at` are set to now.
     pub async fn create_post(
         db: &DbConn,
         form_data: post::Model,
     ) -> Result<post::ActiveModel, DbErr> {
+        let now = Utc::now();
+        let (status, published_at) = schedule(form_data.status, form_data.published_at, now);
+
         post::ActiveModel {
             title: Set(form_data.title.to_owned()),
             text: Set(form_data.text.to_owned()),
+            author_id: Set(form_data.author_id),
+            status: Set(status),
+            created_at: Set(now),
+            updated_at: Set(now),
+            published_at: Set(published_at),
             ..Default::default()
         }
         .save(db)
         .await
     }
 
+    /// Updates the post, keeping the version it replaces as a revision.
     pub async fn update_post_by_id(
         db: &DbConn,
         id: i32,
         form_data: post::Model,
     ) -> Result<post::Model, DbErr> {
-        let post: post::ActiveModel = Post::find_by_id(id)
-            .one(db)
+        let txn = db.begin().await?;
+        let post = Post::find_by_id(id)
+            .one(&txn)
             .await?
-            .ok_or(DbErr::Custom("Cannot find post.".to_owned()))
-            .map(Into::into)?;
+            .ok_or(DbErr::Custom("Cannot find post.".to_owned()))?;
 
-        post::ActiveModel {
-            id: post.id,
+        let now = Utc::now();
+        PostRevision::insert(post_revision::ActiveModel {
+            post_id: Set(post.id),
+            title: Set(post.title.clone()),
+            text: Set(post.text.clone()),
+            status: Set(post.status),
+            edited_at: Set(now),
+            ..Default::default()
+        })
+        .exec_without_returning(&txn)
+        .await?;
+
+        // A published post saved without a date keeps the one it has, rather
+        // than being published again now.
+        let published_at = match (post.status, form_data.status) {
+            (PostStatus::Published, PostStatus::Published) => {
+                form_data.published_at.or(post.published_at)
+            }
+            _ => form_data.published_at,
+        };
+        let (status, published_at) = schedule(form_data.status, published_at, now);
+        let post = post::ActiveModel {
+            id: Unchanged(post.id),
             title: Set(form_data.title.to_owned()),
             text: Set(form_data.text.to_owned()),
+            author_id: Set(form_data.author_id),
+            status: Set(status),
+            created_at: Unchanged(post.created_at),
+            updated_at: Set(now),
+            published_at: Set(published_at),
         }
-        .update(db)
-        .await
+        .update(&txn)
+        .await?;
+
+        txn.commit().await?;
+        Ok(post)
+    }
+
+    /// Publishes the drafts whose `published_at` has come. If ok, returns how
+    /// many there were.
+    pub async fn publish_scheduled_posts(db: &DbConn, now: DateTimeUtc) -> Result<u64, DbErr> {
+        let result = Post::update_many()
+            .col_expr(post::Column::Status, Expr::value(PostStatus::Published))
+            .col_expr(post::Column::UpdatedAt, Expr::value(now))
+            .filter(post::Column::Status.eq(PostStatus::Draft))
+            .filter(post::Column::PublishedAt.lte(now))
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected)
     }
 
     pub async fn delete_post(db: &DbConn, id: i32) -> Result<DeleteResult, DbErr> {
@@ -50,4 +143,82 @@ impl Mutation {
     pub async fn delete_all_posts(db: &DbConn) -> Result<DeleteResult, DbErr> {
         Post::delete_many().exec(db).await
     }
+
+    /// The user called `name`, created on first use.
+    pub async fn find_or_create_user(db: &DbConn, name: &str) -> Result<user::Model, DbErr> {
+        if let Some(user) = User::find()
+            .filter(user::Column::Name.eq(name))
+            .one(db)
+            .await?
+        {
+            return Ok(user);
+        }
+
+        user::ActiveModel {
+            name: Set(name.to_owned()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+    }
+
+    /// Replaces the tags of the post with `names`, creating missing tags.
+    pub async fn set_post_tags(
+        db: &DbConn,
+        post_id: i32,
+        names: &[String],
+    ) -> Result<Vec<tag::Model>, DbErr> {
+        let txn = db.begin().await?;
+
+        PostTag::delete_many()
+            .filter(post_tag::Column::PostId.eq(post_id))
+            .exec(&txn)
+            .await?;
+
+        let mut tags = Vec::with_capacity(names.len());
+        for name in names {
+            let tag = match Tag::find()
+                .filter(tag::Column::Name.eq(name.as_str()))
+                .one(&txn)
+                .await?
+            {
+                Some(tag) => tag,
+                None => {
+                    tag::ActiveModel {
+                        name: Set(name.to_owned()),
+                        ..Default::default()
+                    }
+                    .insert(&txn)
+                    .await?
+                }
+            };
+            post_tag::ActiveModel {
+                post_id: Set(post_id),
+                tag_id: Set(tag.id),
+            }
+            .insert(&txn)
+            .await?;
+            tags.push(tag);
+        }
+
+        txn.commit().await?;
+        Ok(tags)
+    }
+
+    pub async fn create_comment(
+        db: &DbConn,
+        post_id: i32,
+        author_id: Option<i32>,
+        text: &str,
+    ) -> Result<comment::Model, DbErr> {
+        comment::ActiveModel {
+            post_id: Set(post_id),
+            author_id: Set(author_id),
+            text: Set(text.to_owned()),
+            created_at: Set(chrono::Utc::now()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+    }
 }
@@ -1,21 +1,47 @@
-use ::entity::{post, post::Entity as Post};
+use ::entity::{
+    comment,
+    post::{self, Entity as Post, PostStatus},
+    post_revision,
+    post_tag::Entity as PostTag,
+    tag::{self, Entity as Tag},
+    user::{self, Entity as User},
+};
 use sea_orm::*;
+use serde::Serialize;
 
 pub struct Query;
 
+/// A post with its author and tags, as the templates show it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PostDetails {
+    #[serde(flatten)]
+    pub post: post::Model,
+    pub author: Option<user::Model>,
+    pub tags: Vec<tag::Model>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CommentDetails {
+    #[serde(flatten)]
+    pub comment: comment::Model,
+    pub author: Option<user::Model>,
+}
+
 impl Query {
     pub async fn find_post_by_id(db: &DbConn, id: i32) -> Result<Option<post::Model>, DbErr> {
         Post::find_by_id(id).one(db).await
     }
 
-    /// If ok, returns (post models, num pages).
+    /// If ok, returns (post models, num pages) of the posts with `status`.
     pub async fn find_posts_in_page(
         db: &DbConn,
+        status: PostStatus,
         page: u64,
         posts_per_page: u64,
     ) -> Result<(Vec<post::Model>, u64), DbErr> {
         // Setup paginator
         let paginator = Post::find()
+            .filter(post::Column::Status.eq(status))
             .order_by_asc(post::Column::Id)
             .paginate(db, posts_per_page);
         let num_pages = paginator.num_pages().await?;
@@ -23,4 +49,84 @@ impl Query {
         // Fetch paginated posts
         paginator.fetch_page(page - 1).await.map(|p| (p, num_pages))
     }
+
+    /// Like [`Query::find_posts_in_page`], but also counts the posts.
+    /// If ok, returns (post models, num posts and pages).
+    pub async fn find_posts_in_page_with_total(
+        db: &DbConn,
+        status: PostStatus,
+        page: u64,
+        posts_per_page: u64,
+    ) -> Result<(Vec<post::Model>, ItemsAndPagesNumber), DbErr> {
+        let paginator = Post::find()
+            .filter(post::Column::Status.eq(status))
+            .order_by_asc(post::Column::Id)
+            .paginate(db, posts_per_page);
+        let totals = paginator.num_items_and_pages().await?;
+
+        paginator.fetch_page(page - 1).await.map(|p| (p, totals))
+    }
+
+    /// The published posts tagged `name`, oldest first.
+    pub async fn find_posts_by_tag(db: &DbConn, name: &str) -> Result<Vec<post::Model>, DbErr> {
+        Post::find()
+            .inner_join(Tag)
+            .filter(tag::Column::Name.eq(name))
+            .filter(post::Column::Status.eq(PostStatus::Published))
+            .order_by_asc(post::Column::Id)
+            .all(db)
+            .await
+    }
+
+    /// Loads the authors and tags of `posts` with one query each.
+    pub async fn find_post_details(
+        db: &DbConn,
+        posts: Vec<post::Model>,
+    ) -> Result<Vec<PostDetails>, DbErr> {
+        let authors = posts.load_one(User, db).await?;
+        let tags = posts.load_many_to_many(Tag, PostTag, db).await?;
+
+        Ok(posts
+            .into_iter()
+            .zip(authors)
+            .zip(tags)
+            .map(|((post, author), tags)| PostDetails { post, author, tags })
+            .collect())
+    }
+
+    /// If ok, returns the post with its author and tags, and its comments,
+    /// oldest first.
+    pub async fn find_post_with_comments(
+        db: &DbConn,
+        id: i32,
+    ) -> Result<Option<(PostDetails, Vec<CommentDetails>)>, DbErr> {
+        let Some(post) = Post::find_by_id(id).one(db).await? else {
+            return Ok(None);
+        };
+
+        let comments = post
+            .find_related(comment::Entity)
+            .order_by_asc(comment::Column::Id)
+            .find_also_related(User)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|(comment, author)| CommentDetails { comment, author })
+            .collect();
+        let post = Self::find_post_details(db, vec![post]).await?.remove(0);
+
+        Ok(Some((post, comments)))
+    }
+
+    /// The earlier versions of the post, newest first.
+    pub async fn find_post_revisions(
+        db: &DbConn,
+        post_id: i32,
+    ) -> Result<Vec<post_revision::Model>, DbErr> {
+        post_revision::Entity::find()
+            .filter(post_revision::Column::PostId.eq(post_id))
+            .order_by_desc(post_revision::Column::Id)
+            .all(db)
+            .await
+    }
 }
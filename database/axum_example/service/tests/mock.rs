@@ -1,8 +1,10 @@
 mod prepare;
 
-use axum_example_service::{Mutation, Query};
-use entity::post;
-use prepare::prepare_mock_db;
+use axum_example_service::{schedule, tag_names, Mutation, Query};
+use chrono::{Duration, Utc};
+use entity::post::PostStatus;
+use prepare::{prepare_mock_db, published_post};
+use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, TryIntoModel, Value};
 
 #[tokio::test]
 async fn main() {
@@ -21,50 +23,51 @@ async fn main() {
     }
 
     {
-        let post = Mutation::create_post(
-            db,
-            post::Model {
-                id: 0,
-                title: "Title D".to_owned(),
-                text: "Text D".to_owned(),
-            },
-        )
-        .await
-        .unwrap();
+        let post = Mutation::create_post(db, published_post(0, "Title D", "Text D"))
+            .await
+            .unwrap();
 
         assert_eq!(
-            post,
-            post::ActiveModel {
-                id: sea_orm::ActiveValue::Unchanged(6),
-                title: sea_orm::ActiveValue::Unchanged("Title D".to_owned()),
-                text: sea_orm::ActiveValue::Unchanged("Text D".to_owned())
-            }
+            post.try_into_model().unwrap(),
+            published_post(6, "Title D", "Text D")
         );
     }
 
     {
-        let post = Mutation::update_post_by_id(
-            db,
-            1,
-            post::Model {
-                id: 1,
-                title: "New Title A".to_owned(),
-                text: "New Text A".to_owned(),
-            },
-        )
-        .await
-        .unwrap();
+        let post =
+            Mutation::update_post_by_id(db, 1, published_post(1, "New Title A", "New Text A"))
+                .await
+                .unwrap();
 
+        assert_eq!(post, published_post(1, "New Title A", "New Text A"));
+    }
+
+    {
+        let now = Utc::now();
+        let later = now + Duration::hours(1);
+
+        assert_eq!(
+            schedule(PostStatus::Published, None, now),
+            (PostStatus::Published, Some(now))
+        );
         assert_eq!(
-            post,
-            post::Model {
-                id: 1,
-                title: "New Title A".to_owned(),
-                text: "New Text A".to_owned(),
-            }
+            schedule(PostStatus::Published, Some(later), now),
+            (PostStatus::Draft, Some(later))
+        );
+        assert_eq!(
+            schedule(PostStatus::Draft, Some(later), now),
+            (PostStatus::Draft, Some(later))
+        );
+        assert_eq!(
+            schedule(PostStatus::Draft, Some(now - Duration::hours(1)), now),
+            (PostStatus::Draft, None)
         );
     }
 
+    {
+        assert_eq!(tag_names(" Rust, web,rust ,, "), ["rust", "web"]);
+    }
+
     {
         let result = Mutation::delete_post(db, 5).await.unwrap();
 
@@ -77,3 +80,69 @@ async fn main() {
         assert_eq!(result.rows_affected, 5);
     }
 }
+
+#[tokio::test]
+async fn unpublishing_clears_the_publication_date() {
+    let published = published_post(1, "Title A", "Text A");
+    // The edit form sends the date the post was published back unchanged.
+    let draft = entity::post::Model {
+        status: PostStatus::Draft,
+        ..published.clone()
+    };
+    let unpublished = entity::post::Model {
+        published_at: None,
+        ..draft.clone()
+    };
+    let db = MockDatabase::new(DatabaseBackend::Postgres)
+        .append_query_results([[published], [unpublished.clone()]])
+        .append_exec_results([MockExecResult {
+            last_insert_id: 1,
+            rows_affected: 1,
+        }])
+        .into_connection();
+
+    let post = Mutation::update_post_by_id(&db, 1, draft).await.unwrap();
+    assert_eq!(post, unpublished);
+
+    let log = db.into_transaction_log();
+    let update = log
+        .iter()
+        .flat_map(|txn| txn.statements())
+        .find(|stmt| stmt.sql.starts_with("UPDATE"))
+        .expect("the post is updated");
+    let values = &update.values.as_ref().unwrap().0;
+    assert!(
+        values.contains(&Value::ChronoDateTimeUtc(None)),
+        "{values:?}"
+    );
+}
+
+#[tokio::test]
+async fn editing_a_published_post_keeps_its_date() {
+    let published = published_post(1, "Title A", "Text A");
+    // Clients such as `PUT /api/posts/1` may leave the date out.
+    let edit = entity::post::Model {
+        title: "New Title A".to_owned(),
+        published_at: None,
+        ..published.clone()
+    };
+    let db = MockDatabase::new(DatabaseBackend::Postgres)
+        .append_query_results([[published.clone()], [published.clone()]])
+        .append_exec_results([MockExecResult {
+            last_insert_id: 1,
+            rows_affected: 1,
+        }])
+        .into_connection();
+
+    Mutation::update_post_by_id(&db, 1, edit).await.unwrap();
+
+    let log = db.into_transaction_log();
+    let update = log
+        .iter()
+        .flat_map(|txn| txn.statements())
+        .find(|stmt| stmt.sql.starts_with("UPDATE"))
+        .expect("the post is updated");
+    let values = &update.values.as_ref().unwrap().0;
+    let published_at = Value::ChronoDateTimeUtc(published.published_at.map(Box::new));
+    assert!(values.contains(&published_at), "{values:?}");
+}
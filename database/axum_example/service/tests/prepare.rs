@@ -1,42 +1,40 @@
-use ::entity::post;
+#![cfg(feature = "mock")]
+
+use ::entity::post::{self, PostStatus};
+use chrono::{TimeZone, Utc};
 use sea_orm::*;
 
-#[cfg(feature = "mock")]
+/// A published post as the mock database returns it.
+pub fn published_post(id: i32, title: &str, text: &str) -> post::Model {
+    let at = Utc.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+    post::Model {
+        id,
+        title: title.to_owned(),
+        text: text.to_owned(),
+        author_id: None,
+        status: PostStatus::Published,
+        created_at: at,
+        updated_at: at,
+        published_at: Some(at),
+    }
+}
+
 pub fn prepare_mock_db() -> DatabaseConnection {
     MockDatabase::new(DatabaseBackend::Postgres)
         .append_query_results([
-            [post::Model {
-                id: 1,
-                title: "Title A".to_owned(),
-                text: "Text A".to_owned(),
-            }],
-            [post::Model {
-                id: 5,
-                title: "Title C".to_owned(),
-                text: "Text C".to_owned(),
-            }],
-            [post::Model {
-                id: 6,
-                title: "Title D".to_owned(),
-                text: "Text D".to_owned(),
-            }],
-            [post::Model {
-                id: 1,
-                title: "Title A".to_owned(),
-                text: "Text A".to_owned(),
-            }],
-            [post::Model {
-                id: 1,
-                title: "New Title A".to_owned(),
-                text: "New Text A".to_owned(),
-            }],
-            [post::Model {
-                id: 5,
-                title: "Title C".to_owned(),
-                text: "Text C".to_owned(),
-            }],
+            [published_post(1, "Title A", "Text A")],
+            [published_post(5, "Title C", "Text C")],
+            [published_post(6, "Title D", "Text D")],
+            [published_post(1, "Title A", "Text A")],
+            [published_post(1, "New Title A", "New Text A")],
+            [published_post(5, "Title C", "Text C")],
         ])
         .append_exec_results([
+            // The revision saved by the update.
+            MockExecResult {
+                last_insert_id: 1,
+                rows_affected: 1,
+            },
             MockExecResult {
                 last_insert_id: 6,
                 rows_affected: 1,
@@ -0,0 +1,82 @@
+//! 比较两种调度方式：
+//!
+//! * `submit`：调用者一口气提交很多小任务，再等它们全部结束。
+//! * `fan_out`：少量任务在工作线程里再提交很多小任务，这是 work-stealing
+//!   最擅长的情况。
+//!
+//! 运行：`cargo bench --bench pool`
+
+use std::{hint::black_box, sync::mpsc};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tcp_demo::{Scheduler, ThreadPool};
+
+const THREADS: usize = 4;
+const JOBS: usize = 10_000;
+const OUTER: usize = 64;
+const INNER: usize = 256;
+
+fn pool(scheduler: Scheduler) -> ThreadPool {
+    ThreadPool::builder()
+        .threads(THREADS)
+        .scheduler(scheduler)
+        .queue_capacity(JOBS + OUTER * INNER)
+        .build()
+}
+
+fn work(n: usize) -> usize {
+    (0..64).fold(n, |acc, i| acc.wrapping_mul(31).wrapping_add(i))
+}
+
+fn submit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("submit");
+    group.throughput(Throughput::Elements(JOBS as u64));
+
+    for scheduler in [Scheduler::Shared, Scheduler::WorkStealing] {
+        let pool = pool(scheduler);
+        group.bench_function(BenchmarkId::from_parameter(format!("{scheduler:?}")), |b| {
+            b.iter(|| {
+                let handles: Vec<_> = (0..JOBS)
+                    .map(|n| pool.execute(move || work(n)).unwrap())
+                    .collect();
+                for handle in handles {
+                    black_box(handle.join().unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn fan_out(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fan_out");
+    group.throughput(Throughput::Elements((OUTER * INNER) as u64));
+
+    for scheduler in [Scheduler::Shared, Scheduler::WorkStealing] {
+        let pool = pool(scheduler);
+        group.bench_function(BenchmarkId::from_parameter(format!("{scheduler:?}")), |b| {
+            b.iter(|| {
+                let (tx, rx) = mpsc::channel();
+                pool.scope(|s| {
+                    for outer in 0..OUTER {
+                        let (pool, tx) = (&pool, tx.clone());
+                        s.execute(move || {
+                            for inner in 0..INNER {
+                                let tx = tx.clone();
+                                pool.execute(move || tx.send(work(outer * INNER + inner)))
+                                    .unwrap();
+                            }
+                        })
+                        .unwrap();
+                    }
+                });
+                drop(tx);
+                black_box(rx.iter().count());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, submit, fan_out);
+criterion_main!(benches);
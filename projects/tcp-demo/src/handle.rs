@@ -0,0 +1,175 @@
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// 提交到线程池的任务的句柄，用来取回任务的返回值。
+///
+/// 既可以用 [`JoinHandle::join`] 阻塞等待，也可以在异步代码里直接 `.await`。
+/// 丢弃句柄不会取消任务，只是不再关心它的结果。
+pub struct JoinHandle<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// 任务没能给出返回值的原因。
+pub enum JoinError {
+    /// 任务 panic 了，携带 panic 的负载。
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// 任务还没开始执行就被丢弃了，比如 `shutdown_timeout` 超时后队列里剩下的任务。
+    Cancelled,
+}
+
+struct Inner<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+}
+
+struct State<T> {
+    result: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+}
+
+/// 任务这一端，负责把结果交给 [`JoinHandle`]。
+///
+/// 没有调用 `complete` 就被丢弃时，句柄会收到 [`JoinError::Cancelled`]。
+pub(crate) struct Completer<T> {
+    inner: Option<Arc<Inner<T>>>,
+}
+
+pub(crate) fn pair<T>() -> (Completer<T>, JoinHandle<T>) {
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State {
+            result: None,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    let completer = Completer {
+        inner: Some(Arc::clone(&inner)),
+    };
+    (completer, JoinHandle { inner })
+}
+
+impl<T> Inner<T> {
+    fn finish(&self, result: Result<T, JoinError>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Completer<T> {
+    pub(crate) fn complete(mut self, result: Result<T, JoinError>) {
+        if let Some(inner) = self.inner.take() {
+            inner.finish(result);
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.finish(Err(JoinError::Cancelled));
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// 阻塞当前线程直到任务结束。
+    pub fn join(self) -> Result<T, JoinError> {
+        let mut state = self.inner.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.inner.done.wait(state).unwrap();
+        }
+    }
+
+    /// 任务是否已经结束（包括 panic 和被取消）。
+    pub fn is_finished(&self) -> bool {
+        self.inner.state.lock().unwrap().result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.inner.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+impl JoinError {
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(_))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    /// 取出 panic 的负载，可以交给 `std::panic::resume_unwind` 继续 panic。
+    ///
+    /// # Panics
+    ///
+    /// 任务不是因为 panic 结束时会 panic。
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self {
+            JoinError::Panicked(payload) => payload,
+            JoinError::Cancelled => panic!("`into_panic` called on a cancelled job"),
+        }
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Panicked(_) => write!(f, "Panicked({:?})", self.to_string()),
+            JoinError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Panicked(payload) => {
+                let msg = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("Box<dyn Any>");
+                write!(f, "job panicked: {msg}")
+            }
+            JoinError::Cancelled => write!(f, "job was cancelled before it ran"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
@@ -0,0 +1,115 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{Request, Response};
+
+/// 把请求的路径映射到文档根目录下的文件。
+///
+/// 只回 `GET` 和 `HEAD`。路径里有 `..` 之类的段，或者解析符号链接以后跑到
+/// 根目录外面去的请求一律回 404，不会泄露根目录以外的文件。
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+    not_found: Option<String>,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: "index.html".to_string(),
+            not_found: None,
+        }
+    }
+
+    /// 请求目录时返回的文件，默认是 `index.html`。
+    pub fn index(mut self, name: impl Into<String>) -> StaticFiles {
+        self.index = name.into();
+        self
+    }
+
+    /// 找不到文件时用根目录下的这个文件作为 404 页面。
+    pub fn not_found(mut self, name: impl Into<String>) -> StaticFiles {
+        self.not_found = Some(name.into());
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn serve(&self, request: &Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::text(405, "method not allowed\n").header("Allow", "GET, HEAD");
+        }
+        match self.resolve(&request.path).map(|path| self.read(&path)) {
+            Some(Ok(response)) => response,
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("failed to read {}: {e}", request.path);
+                Response::text(500, "internal server error\n")
+            }
+            _ => self.not_found_page(),
+        }
+    }
+
+    /// 用根目录下的文件 `name` 作为响应，状态码是 `status`。
+    pub fn file(&self, status: u16, name: &str) -> Response {
+        match self.read(&self.root.join(name)) {
+            Ok(response) => Response { status, ..response },
+            Err(e) => {
+                eprintln!("failed to read {name}: {e}");
+                Response::text(500, "internal server error\n")
+            }
+        }
+    }
+
+    /// 把请求路径换成文件路径，不合法的路径返回 `None`。
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut file = self.root.clone();
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            if segment == "." || segment == ".." || segment.contains('\\') {
+                return None;
+            }
+            file.push(segment);
+        }
+        if file.is_dir() {
+            file.push(&self.index);
+        }
+
+        // 符号链接可能指向根目录外面。
+        let root = self.root.canonicalize().ok()?;
+        let file = file.canonicalize().ok()?;
+        (file.starts_with(root) && file.is_file()).then_some(file)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Response> {
+        let body = fs::read(path)?;
+        Ok(Response::new(200)
+            .header("Content-Type", content_type(path))
+            .body(body))
+    }
+
+    fn not_found_page(&self) -> Response {
+        match &self.not_found {
+            Some(name) => self.file(404, name),
+            None => Response::text(404, "not found\n"),
+        }
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,13 @@
+//! 基于 [`ThreadPool`](crate::ThreadPool) 的小型 HTTP/1.1 服务器。
+
+mod files;
+mod request;
+mod response;
+mod router;
+mod server;
+
+pub use files::StaticFiles;
+pub use request::{ParseError, Request};
+pub use response::Response;
+pub use router::Router;
+pub use server::{Server, Shutdown};
@@ -0,0 +1,240 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read, Take},
+};
+
+/// 请求行加所有请求头最多多少字节。
+const MAX_HEAD: usize = 8 * 1024;
+
+/// 请求体最多多少字节。
+const MAX_BODY: usize = 1024 * 1024;
+
+/// 解析好的 HTTP/1.x 请求。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// 不带查询字符串的路径，比如 `/index.html`。
+    pub path: String,
+    /// `?` 后面的部分，没有的话是 `None`。
+    pub query: Option<String>,
+    /// `HTTP/1.0` 或者 `HTTP/1.1`。
+    pub version: String,
+    /// 按出现的顺序保存，名字保持原样，查找时不区分大小写。
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// 请求不合法，或者读请求的时候出错了。
+#[derive(Debug)]
+pub enum ParseError {
+    /// 请求格式不对，应该回 400。
+    Malformed(&'static str),
+    /// 请求头太长，应该回 431。
+    HeadTooLarge,
+    /// 请求体太长，应该回 413。
+    BodyTooLarge,
+    /// 不支持的请求，比如分块传输的请求体，应该回 501。
+    Unsupported(&'static str),
+    /// 读的时候出错了，或者请求没发完连接就断了。
+    Io(io::Error),
+}
+
+impl Request {
+    /// 从 `reader` 读一个请求。还没读到任何数据连接就关了的话返回 `None`。
+    pub fn read(reader: &mut impl BufRead) -> Result<Option<Request>, ParseError> {
+        let mut head = reader.take(MAX_HEAD as u64);
+
+        let mut line = String::new();
+        // 请求之前的空行可以忽略（RFC 9112 2.2）。
+        loop {
+            line.clear();
+            if read_line(&mut head, &mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if parts.next().is_none() => {
+                (method, target, version)
+            }
+            _ => return Err(ParseError::Malformed("invalid request line")),
+        };
+        if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err(ParseError::Malformed("invalid method"));
+        }
+        if !target.starts_with('/') {
+            return Err(ParseError::Malformed("invalid request target"));
+        }
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(ParseError::Unsupported("unsupported HTTP version"));
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+
+        let mut request = Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query,
+            version: version.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+
+        loop {
+            line.clear();
+            if read_line(&mut head, &mut line)? == 0 {
+                return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .filter(|(name, _)| is_token(name))
+                .ok_or(ParseError::Malformed("invalid header"))?;
+            request
+                .headers
+                .push((name.to_string(), value.trim().to_string()));
+        }
+
+        if request.header("transfer-encoding").is_some() {
+            return Err(ParseError::Unsupported("chunked request bodies"));
+        }
+        if let Some(length) = request.header("content-length") {
+            let length: usize = length
+                .parse()
+                .map_err(|_| ParseError::Malformed("invalid content-length"))?;
+            if length > MAX_BODY {
+                return Err(ParseError::BodyTooLarge);
+            }
+            let mut body = vec![0; length];
+            head.into_inner()
+                .read_exact(&mut body)
+                .map_err(ParseError::Io)?;
+            request.body = body;
+        }
+
+        Ok(Some(request))
+    }
+
+    /// 名字为 `name` 的第一个请求头，不区分大小写。
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// 响应之后是否可以在同一个连接上接着读下一个请求。
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").map(str::to_ascii_lowercase);
+        match connection.as_deref() {
+            Some("close") => false,
+            Some("keep-alive") => true,
+            _ => self.version == "HTTP/1.1",
+        }
+    }
+}
+
+/// 读一行，去掉结尾的 `\r\n` 或者 `\n`，返回读了多少字节。
+fn read_line<R: BufRead>(reader: &mut Take<R>, line: &mut String) -> Result<usize, ParseError> {
+    let n = match reader.read_line(line) {
+        Ok(n) => n,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            return Err(ParseError::Malformed("request head is not UTF-8"))
+        }
+        Err(e) => return Err(ParseError::Io(e)),
+    };
+    if n > 0 && !line.ends_with('\n') {
+        // 读到了 `MAX_HEAD` 的上限，或者连接在一行的中间断了。
+        return Err(if reader.limit() == 0 {
+            ParseError::HeadTooLarge
+        } else {
+            ParseError::Io(io::ErrorKind::UnexpectedEof.into())
+        });
+    }
+    let trimmed = line.trim_end_matches('\n').trim_end_matches('\r').len();
+    line.truncate(trimmed);
+    Ok(n)
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed(reason) => write!(f, "bad request: {reason}"),
+            ParseError::HeadTooLarge => f.write_str("request head too large"),
+            ParseError::BodyTooLarge => f.write_str("request body too large"),
+            ParseError::Unsupported(what) => write!(f, "not implemented: {what}"),
+            ParseError::Io(e) => write!(f, "failed to read request: {e}"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Option<Request>, ParseError> {
+        Request::read(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn parses_headers_and_body() {
+        let request = parse(
+            "\r\nPOST /echo?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/echo");
+        assert_eq!(request.query.as_deref(), Some("x=1"));
+        assert_eq!(request.header("HOST"), Some("localhost"));
+        assert_eq!(request.body, b"hello");
+        assert!(request.keep_alive());
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        assert!(parse("").unwrap().is_none());
+        assert!(matches!(
+            parse("GET /\r\n\r\n"),
+            Err(ParseError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nno colon\r\n\r\n"),
+            Err(ParseError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"),
+            Err(ParseError::Io(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Err(ParseError::Unsupported(_))
+        ));
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD));
+        assert!(matches!(parse(&long), Err(ParseError::HeadTooLarge)));
+    }
+}
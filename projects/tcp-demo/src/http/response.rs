@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+
+/// 要发回去的响应。`Content-Length` 和 `Connection` 在写出去的时候才加上。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// 没有响应体的响应。
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.into())
+    }
+
+    pub fn html(status: u16, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(body.into())
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// 把响应写到 `w`。`head` 为真时只写状态行和响应头（用来回 `HEAD` 请求），
+    /// `keep_alive` 为假时告诉客户端这个连接要关了。
+    pub fn write_to(&self, w: &mut impl Write, head: bool, keep_alive: bool) -> io::Result<()> {
+        let mut buf = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            buf.push_str(&format!("{name}: {value}\r\n"));
+        }
+        buf.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        if !keep_alive {
+            buf.push_str("Connection: close\r\n");
+        }
+        buf.push_str("\r\n");
+
+        w.write_all(buf.as_bytes())?;
+        if !head {
+            w.write_all(&self.body)?;
+        }
+        w.flush()
+    }
+}
+
+/// 状态码对应的原因短语。
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
@@ -0,0 +1,101 @@
+use super::{Request, Response};
+
+type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// 按方法和路径把请求分给处理函数的路由表。
+///
+/// 路径要完全相同才算匹配；`HEAD` 请求会交给同一路径的 `GET` 处理函数，
+/// 服务器写响应的时候不带响应体。路径匹配上了但方法不对的回 405，什么都没
+/// 匹配上的交给 [`Router::fallback`]，默认回 404。
+///
+/// ```
+/// use tcp_demo::http::{Request, Response, Router};
+///
+/// let router = Router::new()
+///     .get("/", |_| Response::text(200, "hello"))
+///     .post("/echo", |req| Response::new(200).body(req.body.clone()));
+/// ```
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Handler,
+}
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Handler,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            fallback: Box::new(|_| Response::text(404, "not found\n")),
+        }
+    }
+
+    /// 添加一条路由。同一个方法和路径添加多次的话，先添加的生效。
+    pub fn route<F>(mut self, method: &str, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<F>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route("POST", path, handler)
+    }
+
+    /// 没有路由匹配时的处理函数，比如 [`StaticFiles`](super::StaticFiles)。
+    pub fn fallback<F>(mut self, handler: F) -> Router
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.fallback = Box::new(handler);
+        self
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        let method = match request.method.as_str() {
+            "HEAD" => "GET",
+            method => method,
+        };
+
+        let mut allowed = Vec::new();
+        for route in self.routes.iter().filter(|r| r.path == request.path) {
+            if route.method == method {
+                return (route.handler)(request);
+            }
+            allowed.push(route.method.as_str());
+        }
+        if allowed.is_empty() {
+            return (self.fallback)(request);
+        }
+
+        if allowed.contains(&"GET") {
+            allowed.push("HEAD");
+        }
+        Response::text(405, "method not allowed\n").header("Allow", allowed.join(", "))
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    io::{self, BufReader},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{ParseError, Request, Response, Router};
+use crate::ThreadPool;
+
+/// 读一个请求最多等多久。
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 空闲的连接多久检查一次服务器是不是要关了。
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 用线程池处理连接的 HTTP/1.1 服务器。
+///
+/// 每个连接交给线程池里的一个线程，支持 keep-alive。[`Server::run`] 一直运行
+/// 到 [`Shutdown::shutdown`] 被调用为止，然后不再接受新连接，等正在处理的
+/// 请求都写完响应才返回；空闲的 keep-alive 连接会被直接关掉。
+pub struct Server {
+    listener: TcpListener,
+    router: Arc<Router>,
+    /// 没有指定的话在 [`Server::run`] 里创建 4 个线程的线程池。
+    pool: Option<ThreadPool>,
+    idle_timeout: Duration,
+    shutdown: Shutdown,
+}
+
+/// 用来让 [`Server::run`] 停下来，可以在别的线程或者信号处理函数里调用。
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl Server {
+    /// 监听 `addr`，默认用 4 个线程处理连接。
+    pub fn bind(addr: impl ToSocketAddrs, router: Router) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = Shutdown {
+            requested: Arc::default(),
+            addr: listener.local_addr()?,
+        };
+        Ok(Server {
+            listener,
+            router: Arc::new(router),
+            pool: None,
+            idle_timeout: Duration::from_secs(5),
+            shutdown,
+        })
+    }
+
+    /// 用 `pool` 处理连接。
+    pub fn pool(mut self, pool: ThreadPool) -> Server {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// keep-alive 连接空闲多久以后关掉，默认 5 秒。
+    pub fn idle_timeout(mut self, timeout: Duration) -> Server {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.shutdown.addr
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// 接受连接直到服务器被关闭，然后等线程池里的连接都处理完。
+    pub fn run(self) -> io::Result<()> {
+        let Server {
+            listener,
+            router,
+            pool,
+            idle_timeout,
+            shutdown,
+        } = self;
+        let pool = pool.unwrap_or_else(|| ThreadPool::new(4));
+
+        for stream in listener.incoming() {
+            if shutdown.is_requested() {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("failed to accept connection: {e}");
+                    continue;
+                }
+            };
+
+            let (router, shutdown) = (Arc::clone(&router), shutdown.clone());
+            if let Err(e) = pool.execute(move || serve(stream, &router, &shutdown, idle_timeout)) {
+                eprintln!("dropping connection: {e}");
+            }
+        }
+
+        drop(listener);
+        pool.shutdown();
+        Ok(())
+    }
+}
+
+impl Shutdown {
+    pub fn shutdown(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // `accept` 会一直阻塞，连一下自己把它叫醒。
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// 处理一个连接上的所有请求。
+fn serve(stream: TcpStream, router: &Router, shutdown: &Shutdown, idle_timeout: Duration) {
+    let _ = stream.set_nodelay(true);
+    let mut reader = BufReader::new(&stream);
+    let mut writer = &stream;
+
+    loop {
+        // 上一个请求后面可能已经跟着下一个请求了。
+        if reader.buffer().is_empty() && !wait_for_request(&stream, shutdown, idle_timeout) {
+            return;
+        }
+        if stream.set_read_timeout(Some(REQUEST_TIMEOUT)).is_err() {
+            return;
+        }
+
+        let request = match Request::read(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(e) => {
+                if let Some(response) = error_response(&e) {
+                    let _ = response.write_to(&mut writer, false, false);
+                }
+                return;
+            }
+        };
+
+        let response = panic::catch_unwind(AssertUnwindSafe(|| router.handle(&request)))
+            .unwrap_or_else(|_| Response::text(500, "internal server error\n"));
+        let keep_alive = request.keep_alive() && !shutdown.is_requested();
+        let head = request.method == "HEAD";
+        if response.write_to(&mut writer, head, keep_alive).is_err() || !keep_alive {
+            return;
+        }
+    }
+}
+
+/// 等下一个请求的第一个字节。连接关了、空闲太久或者服务器要关了的时候返回
+/// `false`。
+fn wait_for_request(stream: &TcpStream, shutdown: &Shutdown, idle_timeout: Duration) -> bool {
+    if stream.set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+        return false;
+    }
+    let deadline = Instant::now() + idle_timeout;
+    loop {
+        match stream.peek(&mut [0]) {
+            Ok(n) => return n > 0,
+            Err(e) if timed_out(&e) => {
+                if shutdown.is_requested() || Instant::now() >= deadline {
+                    return false;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+/// 读请求出错时回的响应，连接已经断了的话返回 `None`。
+fn error_response(error: &ParseError) -> Option<Response> {
+    let status = match error {
+        ParseError::Malformed(_) => 400,
+        ParseError::HeadTooLarge => 431,
+        ParseError::BodyTooLarge => 413,
+        ParseError::Unsupported(_) => 501,
+        ParseError::Io(e) if timed_out(e) => 408,
+        ParseError::Io(_) => return None,
+    };
+    Some(Response::text(status, format!("{error}\n")))
+}
+
+/// 读超时在不同平台上的错误类型不一样。
+fn timed_out(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
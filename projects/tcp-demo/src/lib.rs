@@ -1,14 +1,224 @@
+pub mod http;
+
+mod handle;
+mod options;
+mod queue;
+mod scope;
+mod timer;
+mod token;
+mod worker;
+
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    error::Error,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::Ordering, Arc, OnceLock},
     thread,
+    time::{Duration, Instant},
 };
 
+pub use handle::{JoinError, JoinHandle};
+pub use options::{JobOptions, Priority};
+pub use queue::Scheduler;
+pub use scope::Scope;
+use timer::Timer;
+pub use token::CancellationToken;
+use worker::{Config, Rejection, Shared};
+
+/// 队列默认最多排多少个任务。
+const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// 多出来的线程默认空闲多久后退出。
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(10);
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+    /// 延迟任务用的定时器，第一次用到时才创建。
+    timer: OnceLock<Arc<Timer>>,
+    /// 已经关闭过了，`Drop` 不用再做什么。
+    finished: bool,
+}
+
+/// 执行任务。把结果交给句柄之前会先用任务的结果调用传入的回调，这样句柄
+/// 返回的时候统计信息已经更新好了。
+type JobFn<'a> = Box<dyn FnOnce(&mut dyn FnMut(Outcome)) + Send + 'a>;
+
+type Job = JobFn<'static>;
+
+/// 任务是怎么结束的。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Completed,
+    Panicked,
+    /// 开始之前令牌就被取消了，任务没有执行。
+    Cancelled,
+}
+
+/// 把 `f` 包装成任务，返回任务和取结果的句柄。
+fn job<'a, F, T>(f: F, token: Option<CancellationToken>) -> (JobFn<'a>, JoinHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'a,
+    T: Send + 'a,
+{
+    let (completer, handle) = handle::pair();
+    let job = Box::new(move |done: &mut dyn FnMut(Outcome)| {
+        if token.is_some_and(|token| token.is_cancelled()) {
+            // 丢弃 `completer`，句柄会收到 `JoinError::Cancelled`。
+            done(Outcome::Cancelled);
+            return;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => {
+                done(Outcome::Completed);
+                completer.complete(Ok(value));
+            }
+            Err(payload) => {
+                done(Outcome::Panicked);
+                completer.complete(Err(JoinError::Panicked(payload)));
+            }
+        }
+    });
+    (job, handle)
+}
+
+/// 每隔 `period` 执行一次 `f` 的任务，这一次应该在 `at` 执行。
+fn recurring<F>(
+    timer: Arc<Timer>,
+    period: Duration,
+    at: Instant,
+    token: CancellationToken,
+    mut f: F,
+) -> Job
+where
+    F: FnMut() + Send + 'static,
+{
+    Box::new(move |done: &mut dyn FnMut(Outcome)| {
+        if token.is_cancelled() {
+            done(Outcome::Cancelled);
+            return;
+        }
+        // panic 只算这一次失败，之后照常执行。
+        match panic::catch_unwind(AssertUnwindSafe(&mut f)) {
+            Ok(()) => done(Outcome::Completed),
+            Err(_) => done(Outcome::Panicked),
+        }
+
+        // 执行得太慢错过的那几次就跳过，不会连着补上。
+        let next = (at + period).max(Instant::now());
+        let job = recurring(Arc::clone(&timer), period, next, token, f);
+        // 定时器已经关闭说明线程池正在关闭，不再重复了。
+        let _ = timer.schedule(next, Priority::Normal, job);
+    })
+}
+
+/// 创建 [`ThreadPool`] 的配置。
+#[derive(Debug, Clone)]
+pub struct Builder {
+    scheduler: Scheduler,
+    min_threads: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    queue_capacity: usize,
+    thread_name: String,
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        Builder {
+            scheduler: Scheduler::default(),
+            min_threads: threads,
+            max_threads: threads,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            thread_name: "pool-worker".to_string(),
+        }
+    }
 }
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// 固定的工作线程数量，默认是 CPU 核数。
+    pub fn threads(mut self, threads: usize) -> Builder {
+        self.min_threads = threads;
+        self.max_threads = threads;
+        self
+    }
+
+    /// 最少保留的线程数，线程池创建时就会启动这么多线程。
+    pub fn min_threads(mut self, threads: usize) -> Builder {
+        self.min_threads = threads;
+        self
+    }
+
+    /// 最多的线程数。所有线程都在忙而且还有任务排队时会加线程，直到这个数。
+    pub fn max_threads(mut self, threads: usize) -> Builder {
+        self.max_threads = threads;
+        self
+    }
+
+    /// 线程数多于 `min_threads` 时，空闲的线程等这么久没有任务就退出，
+    /// 默认 10 秒。
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Builder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// 任务的调度方式，默认是 [`Scheduler::Shared`]。
+    pub fn scheduler(mut self, scheduler: Scheduler) -> Builder {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// 队列里最多排多少个还没开始执行的任务，默认 1024。
+    pub fn queue_capacity(mut self, capacity: usize) -> Builder {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// 工作线程名字的前缀，线程名是 `{prefix}-{id}`。
+    pub fn thread_name(mut self, prefix: impl Into<String>) -> Builder {
+        self.thread_name = prefix.into();
+        self
+    }
+
+    /// 创建线程池。
+    ///
+    /// # Panics
+    ///
+    /// 线程数或队列容量为 0、`min_threads` 大于 `max_threads`，或者创建线程
+    /// 失败时会 panic。
+    pub fn build(self) -> ThreadPool {
+        assert!(
+            self.min_threads > 0,
+            "a thread pool needs at least one thread"
+        );
+        assert!(
+            self.min_threads <= self.max_threads,
+            "min_threads must not be greater than max_threads"
+        );
+        assert!(self.queue_capacity > 0, "the queue capacity must not be 0");
+
+        let shared = Arc::new(Shared::new(Config {
+            scheduler: self.scheduler,
+            min_threads: self.min_threads,
+            max_threads: self.max_threads,
+            keep_alive: self.keep_alive,
+            capacity: self.queue_capacity,
+            thread_name: self.thread_name,
+        }));
+        let pool = ThreadPool {
+            shared,
+            timer: OnceLock::new(),
+            finished: false,
+        };
+        pool.shared.start().expect("failed to spawn worker thread");
+        pool
+    }
+}
 
 impl ThreadPool {
     /// 创建线程池。
@@ -19,74 +229,613 @@ impl ThreadPool {
     ///
     /// `new` 函数在 size 为 0 时会 panic。
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        Builder::new().threads(size).build()
+    }
+
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// 把任务放进队列，返回可以取回结果的句柄。
+    ///
+    /// 队列满了会阻塞，直到有工作线程取走任务。
+    pub fn execute<F, T>(&self, f: F) -> Result<JoinHandle<T>, ExecuteError<F>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit(JobOptions::default(), f, true)
+    }
+
+    /// 和 [`ThreadPool::execute`] 一样，但队列满了不等待，直接把任务退回来。
+    pub fn try_execute<F, T>(&self, f: F) -> Result<JoinHandle<T>, ExecuteError<F>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit(JobOptions::default(), f, false)
+    }
+
+    /// 按 `options` 指定的优先级、执行时间和取消令牌提交任务。
+    ///
+    /// 延迟执行的任务到点以后才放进队列，在那之前不占队列的容量，到点的时候
+    /// 也不会因为队列满了而等待。
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use tcp_demo::{JobOptions, Priority, ThreadPool};
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// let options = JobOptions::new()
+    ///     .priority(Priority::High)
+    ///     .delay(Duration::from_millis(10));
+    /// let handle = pool.execute_with(options, || 42).unwrap();
+    /// assert_eq!(handle.join().unwrap(), 42);
+    /// ```
+    pub fn execute_with<F, T>(
+        &self,
+        options: JobOptions,
+        f: F,
+    ) -> Result<JoinHandle<T>, ExecuteError<F>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit(options, f, true)
+    }
+
+    /// 和 [`ThreadPool::execute_with`] 一样，但队列满了不等待。
+    pub fn try_execute_with<F, T>(
+        &self,
+        options: JobOptions,
+        f: F,
+    ) -> Result<JoinHandle<T>, ExecuteError<F>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit(options, f, false)
+    }
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
+    fn submit<F, T>(
+        &self,
+        options: JobOptions,
+        f: F,
+        wait: bool,
+    ) -> Result<JoinHandle<T>, ExecuteError<F>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let JobOptions {
+            priority,
+            not_before,
+            token,
+        } = options;
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        if let Some(at) = not_before.filter(|&at| at > Instant::now()) {
+            let timer = self.timer();
+            let (job, handle) = job(f, token);
+            // 定时器只在线程池关闭时才关闭，这里不会失败；万一失败了，丢弃
+            // 任务会让句柄收到 `JoinError::Cancelled`。
+            let _ = timer.schedule(at, priority, job);
+            return Ok(handle);
         }
 
-        ThreadPool {
-            workers,
-            sender: Some(sender),
+        if let Err(rejection) = self.shared.reserve(wait) {
+            return Err(rejection.with(f));
         }
+        let (job, handle) = job(f, token);
+        self.shared.push(job, priority);
+        Ok(handle)
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// 从现在起每隔 `period` 执行一次 `f`，直到返回的令牌被取消或者线程池
+    /// 关闭。同一个 `f` 不会同时执行两次，某一次执行得太久的话，错过的那几次
+    /// 会被跳过。
+    ///
+    /// # Panics
+    ///
+    /// `period` 为 0 时会 panic。
+    pub fn schedule_every<F>(&self, period: Duration, f: F) -> CancellationToken
     where
-        F: FnOnce() + Send + 'static,
+        F: FnMut() + Send + 'static,
     {
-        let job = Box::new(f);
+        assert!(!period.is_zero(), "the period must not be 0");
+
+        let token = CancellationToken::new();
+        let timer = self.timer();
+        let at = Instant::now() + period;
+        let job = recurring(Arc::clone(timer), period, at, token.clone(), f);
+        let _ = timer.schedule(at, Priority::Normal, job);
+        token
+    }
+
+    fn timer(&self) -> &Arc<Timer> {
+        self.timer
+            .get_or_init(|| Timer::start(&self.shared).expect("failed to spawn timer thread"))
+    }
+
+    /// 创建一个作用域，在里面提交的任务可以借用当前栈上的数据。所有这些任务
+    /// 都结束之后才返回，即使 `f` panic 了也一样。
+    ///
+    /// ```
+    /// let pool = tcp_demo::ThreadPool::new(4);
+    /// let mut words = vec!["a".to_string(), "b".to_string()];
+    /// pool.scope(|s| {
+    ///     for word in &mut words {
+    ///         s.execute(move || word.push('!')).unwrap();
+    ///     }
+    /// });
+    /// assert_eq!(words, ["a!", "b!"]);
+    /// ```
+    pub fn scope<'scope, F, R>(&'scope self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'scope>) -> R,
+    {
+        let scope = Scope::new(self);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// 返回队列和每个工作线程的统计信息。
+    pub fn stats(&self) -> Stats {
+        let workers = self.shared.workers.read().unwrap();
+        Stats {
+            queued: self.shared.queued.load(Ordering::SeqCst),
+            capacity: self.shared.capacity,
+            workers: workers.iter().map(|worker| worker.stats()).collect(),
+        }
+    }
+
+    /// 不再接受新任务，等队列里的任务都执行完后退出。
+    pub fn shutdown(self) {
+        // 交给 `Drop`。
+    }
 
-        // self.sender.send(job).unwrap();
-        self.sender.as_ref().unwrap().send(job).unwrap();
+    /// 和 [`ThreadPool::shutdown`] 一样，但最多等 `timeout`。
+    ///
+    /// 超时后队列里还没开始的任务会被丢弃（它们的句柄收到
+    /// [`JoinError::Cancelled`]），正在执行的任务不会被打断，它们的线程在
+    /// 任务结束后自行退出。
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Result<(), ShutdownTimeout> {
+        self.finish(Some(Instant::now() + timeout))
+    }
+
+    fn finish(&mut self, deadline: Option<Instant>) -> Result<(), ShutdownTimeout> {
+        self.finished = true;
+        // 先停定时器，这样就不会再有到点的任务进队列了。
+        if let Some(timer) = self.timer.get() {
+            timer.close();
+        }
+        self.shared.close();
+
+        if !self.shared.wait_for_workers(deadline) {
+            // 剩下的线程就不等了。
+            let cancelled = self.shared.drain();
+            return Err(ShutdownTimeout {
+                running: self.shared.alive.load(Ordering::SeqCst),
+                cancelled: cancelled.len(),
+            });
+        }
+
+        let workers = std::mem::take(&mut *self.shared.workers.write().unwrap());
+        for worker in workers {
+            println!("Shutting down worker {}", worker.id);
+
+            // 线程 panic 后会换上新的线程，所以要一直 join 到没有为止。
+            loop {
+                let thread = worker.thread.lock().unwrap().take();
+                match thread {
+                    Some(thread) => {
+                        let _ = thread.join();
+                    }
+                    None => break,
+                }
+            }
+        }
+        Ok(())
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
+        if !self.finished {
+            let _ = self.finish(None);
+        }
+    }
+}
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("threads", &self.shared.alive.load(Ordering::SeqCst))
+            .finish()
+    }
+}
 
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
-            // worker.thread.join().unwrap();
+/// [`ThreadPool::stats`] 的返回值。
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// 排队中还没开始的任务数。
+    pub queued: usize,
+    pub capacity: usize,
+    /// 当前的工作线程，线程数会在最小值和最大值之间变化。
+    pub workers: Vec<WorkerStats>,
+}
+
+/// 一个工作线程的统计信息。线程 panic 后换上的新线程沿用原来的编号和计数。
+#[derive(Debug, Clone)]
+pub struct WorkerStats {
+    pub id: usize,
+    /// 正常结束的任务数。
+    pub completed: u64,
+    /// panic 的任务数。
+    pub panicked: u64,
+    /// 因为令牌被取消而没有执行的任务数。
+    pub cancelled: u64,
+    /// 因为任务 panic 而重新创建线程的次数。
+    pub respawns: u64,
+    /// 现在是否正在执行任务。
+    pub busy: bool,
+    /// 执行任务花费的总时间。
+    pub busy_time: Duration,
+}
+
+/// 任务没能放进队列，任务本身会被退回来。
+pub enum ExecuteError<F> {
+    /// 队列满了，只有 [`ThreadPool::try_execute`] 会返回。
+    Full(F),
+    /// 线程池已经关闭。
+    Shutdown(F),
+}
+
+impl Rejection {
+    fn with<F>(self, f: F) -> ExecuteError<F> {
+        match self {
+            Rejection::Full => ExecuteError::Full(f),
+            Rejection::Shutdown => ExecuteError::Shutdown(f),
         }
     }
 }
 
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+impl<F> ExecuteError<F> {
+    /// 取回被退回的任务。
+    pub fn into_inner(self) -> F {
+        match self {
+            ExecuteError::Full(f) | ExecuteError::Shutdown(f) => f,
+        }
+    }
 }
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
+impl<F> fmt::Debug for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Full(_) => write!(f, "Full(..)"),
+            ExecuteError::Shutdown(_) => write!(f, "Shutdown(..)"),
+        }
+    }
+}
 
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
+impl<F> fmt::Display for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Full(_) => write!(f, "the thread pool's queue is full"),
+            ExecuteError::Shutdown(_) => write!(f, "the thread pool is shut down"),
+        }
+    }
+}
 
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
-                }
+impl<F> Error for ExecuteError<F> {}
+
+/// [`ThreadPool::shutdown_timeout`] 超时了。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownTimeout {
+    /// 超时的时候还在执行任务的线程数。
+    pub running: usize,
+    /// 被丢弃的排队任务数。
+    pub cancelled: usize,
+}
+
+impl fmt::Display for ShutdownTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timed out with {} jobs still running, {} queued jobs cancelled",
+            self.running, self.cancelled
+        )
+    }
+}
+
+impl Error for ShutdownTimeout {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{mpsc, Mutex},
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    #[test]
+    fn returns_results() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..10)
+            .map(|i| pool.execute(move || i * i).unwrap())
+            .collect();
+        let results: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, (0..10).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panics_are_isolated() {
+        let pool = ThreadPool::new(1);
+        let err = pool
+            .execute(|| -> () { panic!("boom") })
+            .unwrap()
+            .join()
+            .unwrap_err();
+        assert!(err.is_panic());
+        assert_eq!(err.to_string(), "job panicked: boom");
+
+        // 唯一的线程 panic 后被换掉，线程池照常工作。
+        assert_eq!(pool.execute(|| 1).unwrap().join().unwrap(), 1);
+        let stats = pool.stats();
+        assert_eq!(stats.workers[0].panicked, 1);
+        assert_eq!(stats.workers[0].respawns, 1);
+        assert_eq!(stats.workers[0].completed, 1);
+    }
+
+    #[test]
+    fn try_execute_rejects_when_full() {
+        let pool = ThreadPool::builder().threads(1).queue_capacity(1).build();
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::channel();
+        let running = pool
+            .execute(move || {
+                started_tx.send(()).unwrap();
+                blocked.recv().unwrap();
+            })
+            .unwrap();
+        started.recv().unwrap();
+
+        let queued = pool.try_execute(|| 2).unwrap();
+        let rejected = pool.try_execute(|| 3).unwrap_err();
+        assert!(matches!(rejected, ExecuteError::Full(_)));
+        assert_eq!((rejected.into_inner())(), 3);
+        assert_eq!(pool.stats().queued, 1);
+
+        release.send(()).unwrap();
+        running.join().unwrap();
+        assert_eq!(queued.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn shutdown_timeout_cancels_queued_jobs() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        started.recv().unwrap();
+        let queued = pool.execute(|| ()).unwrap();
+
+        let err = pool
+            .shutdown_timeout(Duration::from_millis(50))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ShutdownTimeout {
+                running: 1,
+                cancelled: 1
+            }
+        );
+        assert!(queued.join().unwrap_err().is_cancelled());
+        drop(release);
+    }
+
+    #[test]
+    fn handles_are_futures() {
+        struct Unpark(thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let pool = ThreadPool::new(2);
+        let mut handle = pool
+            .execute(|| {
+                thread::sleep(Duration::from_millis(20));
+                "done"
+            })
+            .unwrap();
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let result = loop {
+            match Pin::new(&mut handle).poll(&mut cx) {
+                Poll::Ready(result) => break result,
+                Poll::Pending => thread::park(),
+            }
+        };
+        assert_eq!(result.unwrap(), "done");
+    }
+
+    #[test]
+    fn work_stealing_runs_nested_jobs() {
+        let pool = ThreadPool::builder()
+            .threads(3)
+            .scheduler(Scheduler::WorkStealing)
+            .build();
+        let (tx, rx) = mpsc::channel();
+        pool.scope(|s| {
+            for i in 0..8 {
+                let (pool, tx) = (&pool, tx.clone());
+                s.execute(move || {
+                    for j in 0..100 {
+                        let tx = tx.clone();
+                        pool.execute(move || tx.send(i * 100 + j).unwrap()).unwrap();
+                    }
+                })
+                .unwrap();
             }
         });
+        drop(tx);
+
+        let mut seen: Vec<i32> = rx.iter().collect();
+        seen.sort();
+        assert_eq!(seen, (0..800).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scope_waits_for_borrowing_jobs() {
+        let pool = ThreadPool::new(2);
+        let numbers: Vec<u64> = (1..=100).collect();
+        let total = pool.scope(|s| {
+            let handles: Vec<_> = numbers
+                .chunks(10)
+                .map(|chunk| s.execute(move || chunk.iter().sum::<u64>()).unwrap())
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum::<u64>()
+        });
+        assert_eq!(total, 5050);
+    }
 
-        Worker {
-            id,
-            thread: Some(thread),
+    #[test]
+    fn resizes_with_load() {
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(50))
+            .build();
+        assert_eq!(pool.stats().workers.len(), 1);
+
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(Mutex::new(blocked));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let blocked = Arc::clone(&blocked);
+                pool.execute(move || {
+                    let _ = blocked.lock().unwrap().recv();
+                })
+                .unwrap()
+            })
+            .collect();
+        // 第一个任务占住唯一的线程后，后面排队的任务会让线程池加线程。
+        wait_for_workers(&pool, 3);
+
+        drop(release);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        wait_for_workers(&pool, 1);
+    }
+
+    /// 等线程数变成 `n`，机器忙的时候可能要多等一会儿。
+    fn wait_for_workers(pool: &ThreadPool, n: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().workers.len() != n {
+            assert!(Instant::now() < deadline, "expected {n} workers");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// 占住唯一的工作线程，返回放行用的发送端。
+    fn block(pool: &ThreadPool) -> (mpsc::Sender<()>, JoinHandle<()>) {
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::channel();
+        let handle = pool
+            .execute(move || {
+                started_tx.send(()).unwrap();
+                let _ = blocked.recv();
+            })
+            .unwrap();
+        started.recv().unwrap();
+        (release, handle)
+    }
+
+    #[test]
+    fn higher_priority_runs_first() {
+        let pool = ThreadPool::new(1);
+        let (release, running) = block(&pool);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let handles: Vec<_> = [Priority::Low, Priority::Normal, Priority::High]
+            .into_iter()
+            .map(|priority| {
+                let order = Arc::clone(&order);
+                let options = JobOptions::new().priority(priority);
+                pool.execute_with(options, move || order.lock().unwrap().push(priority))
+                    .unwrap()
+            })
+            .collect();
+
+        release.send(()).unwrap();
+        running.join().unwrap();
+        for handle in handles {
+            handle.join().unwrap();
         }
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::High, Priority::Normal, Priority::Low]
+        );
+    }
+
+    #[test]
+    fn delayed_jobs_wait() {
+        let pool = ThreadPool::new(1);
+        let start = Instant::now();
+        let options = JobOptions::new().delay(Duration::from_millis(50));
+        let handle = pool.execute_with(options, Instant::now).unwrap();
+        // 还没到点的任务不占队列。
+        assert_eq!(pool.stats().queued, 0);
+        assert!(handle.join().unwrap() - start >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn cancelled_jobs_are_skipped() {
+        let pool = ThreadPool::new(1);
+        let (release, running) = block(&pool);
+
+        let token = CancellationToken::new();
+        let handle = pool
+            .execute_with(JobOptions::new().cancel_token(token.clone()), || 1)
+            .unwrap();
+        token.cancel();
+        release.send(()).unwrap();
+        running.join().unwrap();
+
+        assert!(handle.join().unwrap_err().is_cancelled());
+        assert_eq!(pool.stats().workers[0].cancelled, 1);
+    }
+
+    #[test]
+    fn schedule_every_repeats_until_cancelled() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel();
+        let token = pool.schedule_every(Duration::from_millis(10), move || {
+            let _ = tx.send(());
+        });
+        for _ in 0..3 {
+            rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        }
+
+        token.cancel();
+        // 取消之前可能已经有一次进了队列，之后就不会再执行了。
+        thread::sleep(Duration::from_millis(30));
+        while rx.try_recv().is_ok() {}
+        thread::sleep(Duration::from_millis(50));
+        assert!(rx.try_recv().is_err());
     }
 }
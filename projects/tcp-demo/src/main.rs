@@ -1,95 +1,112 @@
 // https://kaisery.github.io/trpl-zh-cn/ch20-00-final-project-a-web-server.html
 
-use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
-    thread,
-    time::Duration,
-};
+use std::{env, path::PathBuf, process, thread, time::Duration};
 
-use tcp_demo::ThreadPool;
+use tcp_demo::{
+    http::{Response, Router, Server, StaticFiles},
+    ThreadPool,
+};
 
 // curl "http://127.0.0.1:7878"
 // curl "http://127.0.0.1:7878/sleep"
+// curl "http://127.0.0.1:7878/sleep?ms=500"
+// curl -d "hello" "http://127.0.0.1:7878/echo"
+//
+// 用法：tcp-demo [--addr ADDR] [--root DIR] [--threads N]
+// 按 Ctrl-C 停止，正在处理的请求会先处理完。
+
+/// 命令行参数。
+struct Args {
+    addr: String,
+    root: PathBuf,
+    threads: usize,
+}
 
 fn main() {
-    let addr = "127.0.0.1:7878";
-    let listener = TcpListener::bind(addr).unwrap();
-    println!("start server at {}", addr);
-    let pool = ThreadPool::new(4);
-
-    // take(2) 表示只循环2次
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap();
-
-        // handle_connection2(stream);
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!("usage: tcp-demo [--addr ADDR] [--root DIR] [--threads N]");
+            process::exit(2);
+        }
+    };
 
-        // 为每一个流新建一个线程
-        // thread::spawn(|| {
-        //     handle_connection2(stream);
-        // });
+    let server = match Server::bind(&args.addr, router(args.root.clone())) {
+        Ok(server) => server.pool(ThreadPool::new(args.threads)),
+        Err(e) => {
+            eprintln!("failed to bind {}: {e}", args.addr);
+            process::exit(1);
+        }
+    };
 
-        pool.execute(|| {
-            handle_connection2(stream);
-        });
+    let shutdown = server.shutdown_handle();
+    if let Err(e) = ctrlc::set_handler(move || shutdown.shutdown()) {
+        eprintln!("failed to install Ctrl-C handler: {e}");
+        process::exit(1);
     }
 
+    println!(
+        "start server at {} serving {}",
+        server.local_addr(),
+        args.root.display()
+    );
+    if let Err(e) = server.run() {
+        eprintln!("server error: {e}");
+        process::exit(1);
+    }
     println!("Shutting down.");
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let http_request: Vec<_> = buf_reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
-
-    println!("Request: {:#?}", http_request);
-
-    let status_line = "HTTP/1.1 200 OK";
-    let contents = fs::read_to_string("hello.html").unwrap();
-    let length = contents.len();
-
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-
-    stream.write_all(response.as_bytes()).unwrap();
+fn router(root: PathBuf) -> Router {
+    let files = StaticFiles::new(root)
+        .index("hello.html")
+        .not_found("404.html");
+    let page = files.clone();
+
+    Router::new()
+        // 模拟一个很慢的请求，默认 5 秒。
+        .get("/sleep", move |req| {
+            let ms = req
+                .query
+                .as_deref()
+                .and_then(|query| query.strip_prefix("ms="))
+                .and_then(|ms| ms.parse().ok())
+                .unwrap_or(5000);
+            thread::sleep(Duration::from_millis(ms));
+            page.file(200, "hello.html")
+        })
+        .post("/echo", |req| {
+            let content_type = req
+                .header("content-type")
+                .unwrap_or("application/octet-stream");
+            Response::new(200)
+                .header("Content-Type", content_type)
+                .body(req.body.clone())
+        })
+        .fallback(move |req| files.serve(req))
 }
 
-fn handle_connection1(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
-
-    let (status_line, filename) = if request_line == "GET / HTTP/1.1" {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        addr: "127.0.0.1:7878".to_string(),
+        root: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/public")),
+        threads: 4,
     };
-
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-
-    stream.write_all(response.as_bytes()).unwrap();
-}
-
-fn handle_connection2(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
-
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+        match arg.as_str() {
+            "--addr" => parsed.addr = value()?,
+            "--root" => parsed.root = value()?.into(),
+            "--threads" => {
+                parsed.threads = value()?
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or("--threads must be a positive number")?
+            }
+            _ => return Err(format!("unknown argument: {arg}")),
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
-    };
-
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-
-    stream.write_all(response.as_bytes()).unwrap();
+    }
+    Ok(parsed)
 }
@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+use crate::CancellationToken;
+
+/// 任务的优先级。工作线程总是先执行排队的高优先级任务。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// 提交任务时的选项，见 [`ThreadPool::execute_with`](crate::ThreadPool::execute_with)。
+#[derive(Debug, Clone, Default)]
+pub struct JobOptions {
+    pub(crate) priority: Priority,
+    pub(crate) not_before: Option<Instant>,
+    pub(crate) token: Option<CancellationToken>,
+}
+
+impl JobOptions {
+    pub fn new() -> JobOptions {
+        JobOptions::default()
+    }
+
+    pub fn priority(mut self, priority: Priority) -> JobOptions {
+        self.priority = priority;
+        self
+    }
+
+    /// 不早于 `at` 执行。
+    pub fn not_before(mut self, at: Instant) -> JobOptions {
+        self.not_before = Some(at);
+        self
+    }
+
+    /// 至少等 `delay` 以后再执行。
+    pub fn delay(self, delay: Duration) -> JobOptions {
+        self.not_before(Instant::now() + delay)
+    }
+
+    /// 令牌被取消后，还没开始的任务就不再执行。
+    pub fn cancel_token(mut self, token: CancellationToken) -> JobOptions {
+        self.token = Some(token);
+        self
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+
+use crate::{Job, Priority};
+
+/// 任务怎么分给工作线程。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scheduler {
+    /// 所有线程共用一个加锁的 FIFO 队列。
+    #[default]
+    Shared,
+    /// 每个线程有自己的双端队列。外面提交的任务进全局队列，任务里提交的任务
+    /// 进当前线程自己的队列，闲下来的线程会从全局队列和别的线程那里偷任务。
+    /// 适合任务又会提交很多小任务的场景，线程之间几乎不用抢锁。
+    WorkStealing,
+}
+
+/// 所有排队的任务。高优先级和低优先级的任务各有一个简单的 FIFO 队列，
+/// 普通优先级的任务按 [`Scheduler`] 排队。工作线程总是先取高优先级的任务，
+/// 最后才取低优先级的。
+pub(crate) struct Jobs {
+    high: Lane,
+    normal: Normal,
+    low: Lane,
+}
+
+#[derive(Default)]
+struct Lane {
+    jobs: Mutex<VecDeque<Job>>,
+    /// 队列长度，队列空着的时候不用去抢锁。
+    len: AtomicUsize,
+}
+
+enum Normal {
+    Shared(Mutex<VecDeque<Job>>),
+    Stealing(Box<Injector<Job>>),
+}
+
+/// 工作线程自己的队列，只在 work-stealing 模式下有。
+struct Local {
+    /// 所属线程池的编号，一个线程池的任务不能放进另一个线程池的队列。
+    pool: usize,
+    deque: Deque<Job>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Option<Local>> = const { RefCell::new(None) };
+}
+
+/// 把 `deque` 设为当前线程的本地队列。
+pub(crate) fn enter(pool: usize, deque: Deque<Job>) {
+    LOCAL.with(|local| *local.borrow_mut() = Some(Local { pool, deque }));
+}
+
+/// 取回当前线程的本地队列。
+pub(crate) fn leave() -> Option<Deque<Job>> {
+    LOCAL.with(|local| local.borrow_mut().take().map(|local| local.deque))
+}
+
+impl Jobs {
+    pub(crate) fn new(scheduler: Scheduler) -> Jobs {
+        let normal = match scheduler {
+            Scheduler::Shared => Normal::Shared(Mutex::new(VecDeque::new())),
+            Scheduler::WorkStealing => Normal::Stealing(Box::default()),
+        };
+        Jobs {
+            high: Lane::default(),
+            normal,
+            low: Lane::default(),
+        }
+    }
+
+    pub(crate) fn is_stealing(&self) -> bool {
+        matches!(self.normal, Normal::Stealing(_))
+    }
+
+    pub(crate) fn push(&self, pool: usize, job: Job, priority: Priority) {
+        match priority {
+            Priority::High => self.high.push(job),
+            Priority::Normal => self.normal.push(pool, job),
+            Priority::Low => self.low.push(job),
+        }
+    }
+
+    /// 把普通优先级的任务直接放进全局队列，不经过当前线程的本地队列。
+    pub(crate) fn push_global(&self, job: Job) {
+        self.normal.push_global(job);
+    }
+
+    /// 按优先级取一个任务，`others` 是可以偷任务的其他线程的队列。
+    pub(crate) fn pop(
+        &self,
+        pool: usize,
+        others: impl FnOnce() -> Vec<Stealer<Job>>,
+    ) -> Option<Job> {
+        self.high
+            .pop()
+            .or_else(|| self.normal.pop(pool, others))
+            .or_else(|| self.low.pop())
+    }
+
+    /// 取出所有还没开始的任务。
+    pub(crate) fn drain(&self, pool: usize, others: &[Stealer<Job>]) -> Vec<Job> {
+        let mut jobs = self.high.drain();
+        jobs.extend(self.normal.drain(pool, others));
+        jobs.extend(self.low.drain());
+        jobs
+    }
+}
+
+impl Lane {
+    fn push(&self, job: Job) {
+        self.jobs.lock().unwrap().push_back(job);
+        self.len.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn pop(&self) -> Option<Job> {
+        if self.len.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let job = self.jobs.lock().unwrap().pop_front()?;
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    fn drain(&self) -> Vec<Job> {
+        let jobs: Vec<Job> = self.jobs.lock().unwrap().drain(..).collect();
+        self.len.fetch_sub(jobs.len(), Ordering::SeqCst);
+        jobs
+    }
+}
+
+impl Normal {
+    fn push(&self, pool: usize, job: Job) {
+        match self {
+            Normal::Shared(queue) => queue.lock().unwrap().push_back(job),
+            Normal::Stealing(injector) => {
+                let job = LOCAL.with(|local| match &*local.borrow() {
+                    Some(local) if local.pool == pool => {
+                        local.deque.push(job);
+                        None
+                    }
+                    _ => Some(job),
+                });
+                if let Some(job) = job {
+                    injector.push(job);
+                }
+            }
+        }
+    }
+
+    fn push_global(&self, job: Job) {
+        match self {
+            Normal::Shared(queue) => queue.lock().unwrap().push_back(job),
+            Normal::Stealing(injector) => injector.push(job),
+        }
+    }
+
+    /// 取一个任务。work-stealing 模式下先看自己的队列，再看全局队列，最后
+    /// 才去 `others` 那里偷，`others` 只在需要的时候才会被调用。
+    fn pop(&self, pool: usize, others: impl FnOnce() -> Vec<Stealer<Job>>) -> Option<Job> {
+        let injector = match self {
+            Normal::Shared(queue) => return queue.lock().unwrap().pop_front(),
+            Normal::Stealing(injector) => injector,
+        };
+
+        let found = LOCAL.with(|local| {
+            let local = local.borrow();
+            let deque = match &*local {
+                Some(local) if local.pool == pool => &local.deque,
+                _ => return None,
+            };
+            deque
+                .pop()
+                .or_else(|| retry(|| injector.steal_batch_and_pop(deque)))
+        });
+        if found.is_some() {
+            return found;
+        }
+
+        // 不是工作线程，或者自己的队列和全局队列都空了。
+        let others = others();
+        retry(|| {
+            injector
+                .steal()
+                .or_else(|| others.iter().map(Stealer::steal).collect())
+        })
+    }
+
+    fn drain(&self, pool: usize, others: &[Stealer<Job>]) -> Vec<Job> {
+        match self {
+            Normal::Shared(queue) => queue.lock().unwrap().drain(..).collect(),
+            Normal::Stealing(_) => {
+                std::iter::from_fn(|| self.pop(pool, || others.to_vec())).collect()
+            }
+        }
+    }
+}
+
+/// 一直偷到成功或者确定没有任务为止。
+fn retry(mut steal: impl FnMut() -> Steal<Job>) -> Option<Job> {
+    loop {
+        match steal() {
+            Steal::Success(job) => return Some(job),
+            Steal::Empty => return None,
+            Steal::Retry => {}
+        }
+    }
+}
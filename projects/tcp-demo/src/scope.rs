@@ -0,0 +1,84 @@
+use std::{
+    marker::PhantomData,
+    mem,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::{job, ExecuteError, Job, JobFn, JoinHandle, Outcome, Priority, ThreadPool};
+
+/// [`ThreadPool::scope`] 里用来提交任务的作用域。
+///
+/// 通过它提交的任务可以借用调用者栈上的数据，`scope` 会等这些任务都结束了
+/// 才返回。和普通任务一样，任务 panic 只会体现在它的句柄上。
+pub struct Scope<'scope> {
+    pool: &'scope ThreadPool,
+    pending: Arc<Pending>,
+    /// 让 `'scope` 不变（invariant），不能被缩短或者延长。
+    _scope: PhantomData<&'scope mut &'scope ()>,
+}
+
+/// 还没结束的任务数。
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    done: Condvar,
+}
+
+/// 任务存在期间一直持有，任务结束或者被丢弃时释放。
+struct Guard(Arc<Pending>);
+
+impl<'scope> Scope<'scope> {
+    pub(crate) fn new(pool: &'scope ThreadPool) -> Scope<'scope> {
+        Scope {
+            pool,
+            pending: Arc::default(),
+            _scope: PhantomData,
+        }
+    }
+
+    /// 和 [`ThreadPool::execute`] 一样，但任务只需要活得比作用域长。
+    pub fn execute<F, T>(&self, f: F) -> Result<JoinHandle<T>, ExecuteError<F>>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        if let Err(rejection) = self.pool.shared.reserve(true) {
+            return Err(rejection.with(f));
+        }
+
+        *self.pending.count.lock().unwrap() += 1;
+        let guard = Guard(Arc::clone(&self.pending));
+        let (run, handle) = job(f, None);
+        let run: JobFn<'scope> = Box::new(move |done: &mut dyn FnMut(Outcome)| {
+            run(done);
+            // 任务的返回值已经交给了句柄（或者随句柄一起被丢弃了），到这里
+            // 任务才算结束。
+            drop(guard);
+        });
+
+        // SAFETY: `ThreadPool::scope` 返回之前会等到所有的 `Guard` 都被丢弃，
+        // 也就是每个任务要么执行完了，要么还没执行就被丢弃了，所以任务借用
+        // 的数据在任务存在期间一直有效。
+        let run = unsafe { mem::transmute::<JobFn<'scope>, Job>(run) };
+        self.pool.shared.push(run, Priority::Normal);
+        Ok(handle)
+    }
+
+    /// 等作用域里的任务都结束。
+    pub(crate) fn wait(&self) {
+        let mut count = self.pending.count.lock().unwrap();
+        while *count > 0 {
+            count = self.pending.done.wait(count).unwrap();
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
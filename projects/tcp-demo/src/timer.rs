@@ -0,0 +1,131 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    io, mem,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+use crate::{worker::Shared, Job, Priority};
+
+/// 延迟任务的定时器，第一次用到的时候才启动自己的线程。
+///
+/// 任务到点以后才放进线程池的队列，所以在那之前不占队列的容量。
+pub(crate) struct Timer {
+    state: Mutex<State>,
+    changed: Condvar,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+struct State {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    closed: bool,
+}
+
+struct Entry {
+    at: Instant,
+    /// 同一时刻的任务按提交顺序执行。
+    seq: u64,
+    priority: Priority,
+    job: Job,
+}
+
+impl Timer {
+    pub(crate) fn start(shared: &Arc<Shared>) -> io::Result<Arc<Timer>> {
+        let timer = Arc::new(Timer {
+            state: Mutex::new(State {
+                entries: BinaryHeap::new(),
+                next_seq: 0,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+            thread: Mutex::new(None),
+        });
+
+        let thread = {
+            let (timer, shared) = (Arc::clone(&timer), Arc::clone(shared));
+            thread::Builder::new()
+                .name(format!("{}-timer", shared.thread_name))
+                .spawn(move || timer.run(&shared))?
+        };
+        *timer.thread.lock().unwrap() = Some(thread);
+        Ok(timer)
+    }
+
+    /// 到 `at` 的时候把 `job` 放进线程池。定时器已经关闭的话把任务退回来。
+    pub(crate) fn schedule(&self, at: Instant, priority: Priority, job: Job) -> Result<(), Job> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(job);
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.push(Entry {
+            at,
+            seq,
+            priority,
+            job,
+        });
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// 停止定时器，还没到点的任务都会被丢弃。
+    pub(crate) fn close(&self) {
+        let entries = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            mem::take(&mut state.entries)
+        };
+        self.changed.notify_one();
+        // 在锁外面丢弃，任务的句柄会收到 `JoinError::Cancelled`。
+        drop(entries);
+
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn run(&self, shared: &Arc<Shared>) {
+        let mut state = self.state.lock().unwrap();
+        while !state.closed {
+            let now = Instant::now();
+            match state.entries.peek() {
+                Some(entry) if entry.at <= now => {
+                    let entry = state.entries.pop().unwrap();
+                    drop(state);
+                    shared.push_due(entry.job, entry.priority);
+                    state = self.state.lock().unwrap();
+                }
+                Some(entry) => {
+                    let left = entry.at - now;
+                    state = self.changed.wait_timeout(state, left).unwrap().0;
+                }
+                None => state = self.changed.wait(state).unwrap(),
+            }
+        }
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    // `BinaryHeap` 是大顶堆，反过来比较让最早到点的任务在堆顶。
+    fn cmp(&self, other: &Entry) -> Ordering {
+        other.at.cmp(&self.at).then(other.seq.cmp(&self.seq))
+    }
+}
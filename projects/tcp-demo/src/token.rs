@@ -0,0 +1,30 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// 用来取消任务的令牌，克隆出来的令牌共享同一个状态。
+///
+/// 取消以后，还没开始执行的任务不会再执行（它们的句柄收到
+/// [`JoinError::Cancelled`](crate::JoinError::Cancelled)），
+/// [`ThreadPool::schedule_every`](crate::ThreadPool::schedule_every) 的任务
+/// 不会再重复。已经在执行的任务不会被打断，但可以自己检查
+/// [`CancellationToken::is_cancelled`] 提前结束。
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,446 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::*},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_deque::{Stealer, Worker as Deque};
+
+use crate::{
+    queue::{self, Jobs, Scheduler},
+    Job, Outcome, Priority, WorkerStats,
+};
+
+/// 线程池和所有工作线程共享的状态。
+///
+/// 取任务和放任务只用原子变量和（work-stealing 模式下）无锁队列，`lock` 只在
+/// 线程需要睡眠或者唤醒别的线程时才用到。
+pub(crate) struct Shared {
+    pub(crate) jobs: Jobs,
+    /// 已经放进队列或者占好了位置、但还没开始执行的任务数。
+    pub(crate) queued: AtomicUsize,
+    pub(crate) capacity: usize,
+    closed: AtomicBool,
+    /// 还在运行的工作线程数。
+    pub(crate) alive: AtomicUsize,
+    min_threads: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    /// 在 `job_ready` 上睡眠的工作线程数。
+    sleeping: AtomicUsize,
+    /// 在 `space_ready` 上等待的提交者数。
+    blocked: AtomicUsize,
+    pub(crate) workers: RwLock<Vec<Arc<Worker>>>,
+    next_id: AtomicUsize,
+    pub(crate) thread_name: String,
+    lock: Mutex<()>,
+    /// 队列里有了新任务，或者线程池关闭了。
+    job_ready: Condvar,
+    /// 队列里空出了位置。
+    space_ready: Condvar,
+    /// 有工作线程退出了。
+    worker_exited: Condvar,
+}
+
+/// 任务没能放进队列的原因。
+pub(crate) enum Rejection {
+    Full,
+    Shutdown,
+}
+
+/// 工作线程找不到任务时等待的结果。
+enum Idle {
+    /// 可能有新任务了。
+    Woken,
+    /// 线程池关闭了，任务也都执行完了。
+    Closed,
+    /// 空闲太久，线程数又多于最小值，这个线程该退出了。
+    Retired,
+}
+
+pub(crate) struct Config {
+    pub(crate) scheduler: Scheduler,
+    pub(crate) min_threads: usize,
+    pub(crate) max_threads: usize,
+    pub(crate) keep_alive: Duration,
+    pub(crate) capacity: usize,
+    pub(crate) thread_name: String,
+}
+
+impl Shared {
+    pub(crate) fn new(config: Config) -> Shared {
+        Shared {
+            jobs: Jobs::new(config.scheduler),
+            queued: AtomicUsize::new(0),
+            capacity: config.capacity,
+            closed: AtomicBool::new(false),
+            alive: AtomicUsize::new(0),
+            min_threads: config.min_threads,
+            max_threads: config.max_threads,
+            keep_alive: config.keep_alive,
+            sleeping: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            workers: RwLock::new(Vec::new()),
+            next_id: AtomicUsize::new(0),
+            thread_name: config.thread_name,
+            lock: Mutex::new(()),
+            job_ready: Condvar::new(),
+            space_ready: Condvar::new(),
+            worker_exited: Condvar::new(),
+        }
+    }
+
+    /// 线程池的编号，用来区分线程局部的队列属于哪个线程池。
+    pub(crate) fn id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    /// 启动最少数量的工作线程。
+    pub(crate) fn start(self: &Arc<Shared>) -> io::Result<()> {
+        for _ in 0..self.min_threads {
+            self.alive.fetch_add(1, SeqCst);
+            if let Err(e) = self.spawn_worker() {
+                self.alive.fetch_sub(1, SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 在队列里占一个位置，`wait` 为真时队列满了会一直等。
+    pub(crate) fn reserve(&self, wait: bool) -> Result<(), Rejection> {
+        loop {
+            // 所有工作线程都没了（重启失败）的话，任务永远不会被执行。
+            if self.closed.load(SeqCst) || self.alive.load(SeqCst) == 0 {
+                return Err(Rejection::Shutdown);
+            }
+            let queued = self.queued.load(SeqCst);
+            if queued < self.capacity {
+                if self
+                    .queued
+                    .compare_exchange(queued, queued + 1, SeqCst, SeqCst)
+                    .is_ok()
+                {
+                    return Ok(());
+                }
+                continue;
+            }
+            if !wait {
+                return Err(Rejection::Full);
+            }
+
+            let guard = self.lock.lock().unwrap();
+            self.blocked.fetch_add(1, SeqCst);
+            let guard = if self.queued.load(SeqCst) >= self.capacity
+                && !self.closed.load(SeqCst)
+                && self.alive.load(SeqCst) > 0
+            {
+                self.space_ready.wait(guard).unwrap()
+            } else {
+                guard
+            };
+            self.blocked.fetch_sub(1, SeqCst);
+            drop(guard);
+        }
+    }
+
+    /// 放进一个已经用 [`Shared::reserve`] 占好位置的任务。
+    pub(crate) fn push(self: &Arc<Shared>, job: Job, priority: Priority) {
+        self.jobs.push(self.id(), job, priority);
+
+        if self.sleeping.load(SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.job_ready.notify_one();
+        } else {
+            self.grow();
+        }
+    }
+
+    /// 放进一个到点的延迟任务。它等得已经够久了，不管队列满没满都放进去。
+    pub(crate) fn push_due(self: &Arc<Shared>, job: Job, priority: Priority) {
+        self.queued.fetch_add(1, SeqCst);
+        self.push(job, priority);
+    }
+
+    /// 所有线程都在忙而且还有任务在排队时，如果还没到最大线程数就加一个线程。
+    fn grow(self: &Arc<Shared>) {
+        let mut alive = self.alive.load(SeqCst);
+        while alive < self.max_threads && self.queued.load(SeqCst) > 0 {
+            match self
+                .alive
+                .compare_exchange(alive, alive + 1, SeqCst, SeqCst)
+            {
+                Ok(_) => {
+                    if let Err(e) = self.spawn_worker() {
+                        println!("Could not add a worker: {e}");
+                        self.worker_gone();
+                    }
+                    return;
+                }
+                Err(actual) => alive = actual,
+            }
+        }
+    }
+
+    /// 取出一个任务，并把它从排队计数里减掉。
+    fn take_job(&self) -> Option<Job> {
+        let job = self.jobs.pop(self.id(), || self.stealers())?;
+        self.queued.fetch_sub(1, SeqCst);
+        if self.blocked.load(SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.space_ready.notify_one();
+        }
+        Some(job)
+    }
+
+    fn stealers(&self) -> Vec<Stealer<Job>> {
+        let workers = self.workers.read().unwrap();
+        workers
+            .iter()
+            .filter_map(|worker| worker.stealer.clone())
+            .collect()
+    }
+
+    /// 不再接受新任务，叫醒所有等待的线程。
+    pub(crate) fn close(&self) {
+        self.closed.store(true, SeqCst);
+        let _guard = self.lock.lock().unwrap();
+        self.job_ready.notify_all();
+        self.space_ready.notify_all();
+    }
+
+    /// 取出所有还没开始的任务。
+    pub(crate) fn drain(&self) -> Vec<Job> {
+        let jobs = self.jobs.drain(self.id(), &self.stealers());
+        self.queued.fetch_sub(jobs.len(), SeqCst);
+        jobs
+    }
+
+    /// 等所有工作线程退出，超过 `deadline` 就返回 `false`。
+    pub(crate) fn wait_for_workers(&self, deadline: Option<Instant>) -> bool {
+        let mut guard = self.lock.lock().unwrap();
+        while self.alive.load(SeqCst) > 0 {
+            guard = match deadline {
+                None => self.worker_exited.wait(guard).unwrap(),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return false;
+                    }
+                    self.worker_exited.wait_timeout(guard, left).unwrap().0
+                }
+            };
+        }
+        true
+    }
+
+    fn spawn_worker(self: &Arc<Shared>) -> io::Result<()> {
+        let (deque, stealer) = if self.jobs.is_stealing() {
+            let deque = Deque::new_fifo();
+            let stealer = deque.stealer();
+            (Some(deque), Some(stealer))
+        } else {
+            (None, None)
+        };
+        let worker = Arc::new(Worker {
+            id: self.next_id.fetch_add(1, SeqCst),
+            stats: Counters::default(),
+            thread: Mutex::new(None),
+            deque: Mutex::new(deque),
+            stealer,
+        });
+
+        // 先登记再启动，线程一启动就可能要把自己从列表里删掉。
+        self.workers.write().unwrap().push(Arc::clone(&worker));
+        match worker.spawn(self) {
+            Ok(thread) => {
+                *worker.thread.lock().unwrap() = Some(thread);
+                Ok(())
+            }
+            Err(e) => {
+                self.forget(&worker);
+                Err(e)
+            }
+        }
+    }
+
+    fn forget(&self, worker: &Arc<Worker>) {
+        self.workers
+            .write()
+            .unwrap()
+            .retain(|other| !Arc::ptr_eq(other, worker));
+    }
+
+    /// 一个工作线程退出了。
+    fn worker_gone(&self) {
+        self.alive.fetch_sub(1, SeqCst);
+        let _guard = self.lock.lock().unwrap();
+        self.worker_exited.notify_all();
+        // 最后一个线程也没了的话，等着放任务的线程要返回错误。
+        self.space_ready.notify_all();
+    }
+
+    /// 线程数多于最小值时，让出一个线程的名额。
+    fn try_retire(&self) -> bool {
+        let mut alive = self.alive.load(SeqCst);
+        while alive > self.min_threads {
+            match self
+                .alive
+                .compare_exchange(alive, alive - 1, SeqCst, SeqCst)
+            {
+                Ok(_) => return true,
+                Err(actual) => alive = actual,
+            }
+        }
+        false
+    }
+}
+
+pub(crate) struct Worker {
+    pub(crate) id: usize,
+    stats: Counters,
+    pub(crate) thread: Mutex<Option<thread::JoinHandle<()>>>,
+    /// work-stealing 模式下线程自己的队列，线程运行时放在线程局部变量里。
+    deque: Mutex<Option<Deque<Job>>>,
+    stealer: Option<Stealer<Job>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    cancelled: AtomicU64,
+    respawns: AtomicU64,
+    busy: AtomicBool,
+    busy_nanos: AtomicU64,
+}
+
+impl Worker {
+    fn spawn(self: &Arc<Worker>, shared: &Arc<Shared>) -> io::Result<thread::JoinHandle<()>> {
+        let (worker, shared) = (Arc::clone(self), Arc::clone(shared));
+        thread::Builder::new()
+            .name(format!("{}-{}", shared.thread_name, worker.id))
+            .spawn(move || worker.run(shared))
+    }
+
+    fn run(self: Arc<Worker>, shared: Arc<Shared>) {
+        if let Some(deque) = self.deque.lock().unwrap().take() {
+            queue::enter(shared.id(), deque);
+        }
+
+        let idle = loop {
+            let Some(job) = shared.take_job() else {
+                match self.wait_for_job(&shared) {
+                    Idle::Woken => continue,
+                    idle => break idle,
+                }
+            };
+
+            self.stats.busy.store(true, Relaxed);
+            let started = Instant::now();
+            let mut panicked = false;
+            job(&mut |outcome| {
+                self.stats
+                    .busy_nanos
+                    .fetch_add(started.elapsed().as_nanos() as u64, Relaxed);
+                self.stats.busy.store(false, Relaxed);
+                let counter = match outcome {
+                    Outcome::Completed => &self.stats.completed,
+                    Outcome::Panicked => &self.stats.panicked,
+                    Outcome::Cancelled => &self.stats.cancelled,
+                };
+                counter.fetch_add(1, Relaxed);
+                panicked = outcome == Outcome::Panicked;
+            });
+
+            if panicked {
+                // panic 可能让线程局部变量处于不一致的状态，换一个新线程。
+                *self.deque.lock().unwrap() = queue::leave();
+                self.respawn(&shared);
+                return;
+            }
+        };
+
+        // 本地队列里剩下的任务交给别的线程。
+        if let Some(deque) = queue::leave() {
+            while let Some(job) = deque.pop() {
+                shared.jobs.push_global(job);
+            }
+        }
+        if let Idle::Retired = idle {
+            shared.forget(&self);
+            // 已经退出的线程不用再 join。
+            drop(self.thread.lock().unwrap().take());
+            let _guard = shared.lock.lock().unwrap();
+            shared.worker_exited.notify_all();
+        } else {
+            shared.worker_gone();
+        }
+    }
+
+    fn wait_for_job(&self, shared: &Shared) -> Idle {
+        let guard = shared.lock.lock().unwrap();
+        shared.sleeping.fetch_add(1, SeqCst);
+
+        let idle = if shared.queued.load(SeqCst) > 0 {
+            Idle::Woken
+        } else if shared.closed.load(SeqCst) {
+            Idle::Closed
+        } else if shared.alive.load(SeqCst) > shared.min_threads {
+            let (_guard, timeout) = shared
+                .job_ready
+                .wait_timeout(guard, shared.keep_alive)
+                .unwrap();
+            if timeout.timed_out()
+                && shared.queued.load(SeqCst) == 0
+                && !shared.closed.load(SeqCst)
+                && shared.try_retire()
+            {
+                Idle::Retired
+            } else {
+                Idle::Woken
+            }
+        } else {
+            let _guard = shared.job_ready.wait(guard).unwrap();
+            Idle::Woken
+        };
+
+        shared.sleeping.fetch_sub(1, SeqCst);
+        idle
+    }
+
+    fn respawn(self: &Arc<Worker>, shared: &Arc<Shared>) {
+        // 先计数，新线程可能在 `spawn` 返回之前就执行完了下一个任务。
+        self.stats.respawns.fetch_add(1, Relaxed);
+        match self.spawn(shared) {
+            Ok(thread) => *self.thread.lock().unwrap() = Some(thread),
+            Err(e) => {
+                self.stats.respawns.fetch_sub(1, Relaxed);
+                println!("Worker {} could not be restarted: {e}", self.id);
+                if let Some(deque) = self.deque.lock().unwrap().take() {
+                    while let Some(job) = deque.pop() {
+                        shared.jobs.push_global(job);
+                    }
+                }
+                shared.forget(self);
+                shared.worker_gone();
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> WorkerStats {
+        WorkerStats {
+            id: self.id,
+            completed: self.stats.completed.load(Relaxed),
+            panicked: self.stats.panicked.load(Relaxed),
+            cancelled: self.stats.cancelled.load(Relaxed),
+            respawns: self.stats.respawns.load(Relaxed),
+            busy: self.stats.busy.load(Relaxed),
+            busy_time: Duration::from_nanos(self.stats.busy_nanos.load(Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! 启动 `tcp-demo` 可执行文件，通过本机的 socket 发请求。
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// 正在运行的服务器，测试结束时如果还没退出就杀掉。
+struct Server {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Server {
+    fn start() -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_tcp-demo"))
+            .args(["--addr", "127.0.0.1:0", "--threads", "2"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut server = Server {
+            child,
+            addr: ([0, 0, 0, 0], 0).into(),
+        };
+
+        // 等 "start server at 127.0.0.1:PORT serving DIR" 这一行。
+        let mut lines = BufReader::new(stdout).lines();
+        let line = lines
+            .by_ref()
+            .map(Result::unwrap)
+            .find(|line| line.starts_with("start server at "))
+            .expect("server exited before listening");
+        server.addr = line.split_whitespace().nth(3).unwrap().parse().unwrap();
+        // 剩下的输出也要读掉，不然管道满了服务器会卡住。
+        thread::spawn(move || lines.for_each(drop));
+
+        server
+    }
+
+    fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        stream
+    }
+
+    /// 发一个请求，读到连接关闭为止。
+    fn send(&self, request: &str) -> String {
+        let mut stream = self.connect();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn status(response: &str) -> &str {
+    response.split(' ').nth(1).unwrap()
+}
+
+fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").unwrap().1
+}
+
+/// 从 keep-alive 连接上读一个响应（只认 `Content-Length`）。
+fn read_response(reader: &mut impl BufRead) -> String {
+    let mut head = String::new();
+    loop {
+        let n = reader.read_line(&mut head).unwrap();
+        assert!(n > 0, "connection closed early: {head:?}");
+        if head.ends_with("\r\n\r\n") {
+            break;
+        }
+    }
+    let length: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    head + &String::from_utf8(body).unwrap()
+}
+
+#[test]
+fn serves_the_document_root() {
+    let server = Server::start();
+
+    let response = server.send("GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(status(&response), "200");
+    assert!(response.contains("Content-Type: text/html; charset=utf-8\r\n"));
+    assert!(body(&response).contains("Hi from Rust"));
+
+    let response = server.send("GET /missing.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(status(&response), "404");
+    assert!(body(&response).contains("Oops!"));
+
+    // 不能跑到文档根目录外面去。
+    let response = server.send("GET /../Cargo.toml HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(status(&response), "404");
+
+    let response = server.send("HEAD / HTTP/1.0\r\n\r\n");
+    assert_eq!(status(&response), "200");
+    assert!(!response.contains("Content-Length: 0\r\n"));
+    assert_eq!(body(&response), "");
+}
+
+#[test]
+fn routes_by_method_and_reads_bodies() {
+    let server = Server::start();
+
+    let response = server.send(
+        "POST /echo HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 11\r\n\
+         Connection: close\r\n\r\nhello world",
+    );
+    assert_eq!(status(&response), "200");
+    assert!(response.contains("Content-Type: text/plain\r\n"));
+    assert_eq!(body(&response), "hello world");
+
+    let response = server.send("GET /echo HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(status(&response), "405");
+    assert!(response.contains("Allow: POST\r\n"));
+
+    let response = server.send("GET / HTTP/9\r\n\r\n");
+    assert_eq!(status(&response), "501");
+
+    let response = server.send("nonsense\r\n\r\n");
+    assert_eq!(status(&response), "400");
+}
+
+#[test]
+fn keeps_connections_alive() {
+    let server = Server::start();
+    let mut stream = server.connect();
+
+    // 两个请求一起发过去，响应按顺序回来。
+    stream
+        .write_all(
+            b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\none\
+              POST /echo HTTP/1.1\r\nContent-Length: 3\r\n\r\ntwo",
+        )
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    assert_eq!(body(&read_response(&mut reader)), "one");
+    assert_eq!(body(&read_response(&mut reader)), "two");
+
+    stream
+        .write_all(b"GET /sleep?ms=0 HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut reader);
+    assert_eq!(status(&response), "200");
+    assert!(!response.contains("Connection: close"));
+}
+
+#[cfg(unix)]
+#[test]
+fn sigint_finishes_requests_in_flight() {
+    let mut server = Server::start();
+    let mut stream = server.connect();
+    stream
+        .write_all(b"GET /sleep?ms=500 HTTP/1.1\r\n\r\n")
+        .unwrap();
+    // 等请求被某个工作线程读走。
+    thread::sleep(Duration::from_millis(200));
+
+    let killed = Command::new("kill")
+        .args(["-INT", &server.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    // 请求照常处理完，服务器告诉客户端连接要关了。
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert_eq!(status(&response), "200");
+    assert!(response.contains("Connection: close\r\n"));
+
+    let exit = server.child.wait().unwrap();
+    assert!(exit.success());
+    assert!(TcpStream::connect(server.addr).is_err());
+}
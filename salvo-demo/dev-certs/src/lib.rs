@@ -0,0 +1,264 @@
+//! TLS certificates for local development.
+//!
+//! On first use a local CA is generated, and a certificate for `localhost`,
+//! `127.0.0.1` and `::1` signed by it. Both are cached in a directory, so the
+//! CA only has to be trusted once (`ca.pem` in [`dir`]); the leaf certificate
+//! is renewed from the cached CA before it expires.
+//!
+//! Servers call [`Certs::from_env_or_dev`], which prefers the PEM files named
+//! by `TLS_CERT` and `TLS_KEY` and falls back to the development ones.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose,
+};
+use time::OffsetDateTime;
+
+const SUBJECT_ALT_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+const CA_NAME: &str = "Rust demos development CA";
+const CA_VALIDITY: Duration = Duration::from_secs(10 * 365 * 86_400);
+/// Browsers refuse leaf certificates valid for more than 398 days.
+const LEAF_VALIDITY: Duration = Duration::from_secs(397 * 86_400);
+/// Leaf certificates older than this are replaced.
+const LEAF_RENEW_AFTER: Duration = Duration::from_secs(300 * 86_400);
+
+/// A PEM certificate chain and its private key.
+#[derive(Clone, Debug)]
+pub struct Certs {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+/// Where the development files live.
+#[derive(Clone, Debug)]
+pub struct Paths {
+    /// The CA certificate, to add to the trust store.
+    pub ca: PathBuf,
+    pub ca_key: PathBuf,
+    /// The leaf certificate followed by the CA certificate.
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Paths {
+    pub fn new(dir: &Path) -> Paths {
+        Paths {
+            ca: dir.join("ca.pem"),
+            ca_key: dir.join("ca-key.pem"),
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        }
+    }
+}
+
+/// The cache directory: `DEV_CERTS_DIR`, else `~/.cache/dev-certs`, else
+/// `dev-certs` in the temporary directory.
+pub fn dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("DEV_CERTS_DIR") {
+        return dir.into();
+    }
+    match std::env::var_os("HOME") {
+        Some(home) => Path::new(&home).join(".cache").join("dev-certs"),
+        None => std::env::temp_dir().join("dev-certs"),
+    }
+}
+
+/// Makes sure `dir` holds a CA and a current leaf certificate, generating
+/// whatever is missing or due for renewal.
+pub fn ensure(dir: &Path) -> io::Result<Paths> {
+    let paths = Paths::new(dir);
+    fs::create_dir_all(dir)?;
+
+    let ca_key = if paths.ca.exists() && paths.ca_key.exists() {
+        KeyPair::from_pem(&fs::read_to_string(&paths.ca_key)?).map_err(invalid)?
+    } else {
+        let key = KeyPair::generate().map_err(invalid)?;
+        let ca = ca_params(CA_VALIDITY).self_signed(&key).map_err(invalid)?;
+        write_private(&paths.ca_key, &key.serialize_pem())?;
+        fs::write(&paths.ca, ca.pem())?;
+        // A new CA invalidates the old leaf.
+        let _ = fs::remove_file(&paths.cert);
+        eprintln!(
+            "generated a development CA, trust {} to avoid certificate warnings",
+            paths.ca.display()
+        );
+        key
+    };
+
+    let renew = fs::metadata(&paths.cert)
+        .and_then(|meta| meta.modified())
+        .map(|modified| modified.elapsed().unwrap_or_default() > LEAF_RENEW_AFTER)
+        .unwrap_or(true);
+    if renew || !paths.key.exists() {
+        // Only the CA's name, key identifier and key take part in signing,
+        // so rebuilding its certificate from the cached key is equivalent to
+        // the one on disk.
+        let ca = ca_params(CA_VALIDITY)
+            .self_signed(&ca_key)
+            .map_err(invalid)?;
+        let key = KeyPair::generate().map_err(invalid)?;
+        let leaf = leaf_params()?
+            .signed_by(&key, &ca, &ca_key)
+            .map_err(invalid)?;
+        write_private(&paths.key, &key.serialize_pem())?;
+        let chain = leaf.pem() + &fs::read_to_string(&paths.ca)?;
+        fs::write(&paths.cert, chain)?;
+    }
+    Ok(paths)
+}
+
+fn ca_params(validity: Duration) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, CA_NAME);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::from_secs(86_400);
+    params.not_after = now + validity;
+    params
+}
+
+fn leaf_params() -> io::Result<CertificateParams> {
+    let names = SUBJECT_ALT_NAMES.map(String::from).to_vec();
+    let mut params = CertificateParams::new(names).map_err(invalid)?;
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "localhost");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::from_secs(86_400);
+    params.not_after = now + LEAF_VALIDITY;
+    Ok(params)
+}
+
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+fn invalid(e: rcgen::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Certs {
+    /// The files named by `TLS_CERT` and `TLS_KEY` when both are set,
+    /// otherwise the development certificate from [`dir`].
+    pub fn from_env_or_dev() -> io::Result<Certs> {
+        match (std::env::var_os("TLS_CERT"), std::env::var_os("TLS_KEY")) {
+            (Some(cert), Some(key)) => Certs::from_files(Path::new(&cert), Path::new(&key)),
+            _ => Certs::dev(&dir()),
+        }
+    }
+
+    pub fn from_files(cert: &Path, key: &Path) -> io::Result<Certs> {
+        Ok(Certs {
+            cert: fs::read(cert)?,
+            key: fs::read(key)?,
+        })
+    }
+
+    /// The development certificate cached in `dir`, see [`ensure`].
+    pub fn dev(dir: &Path) -> io::Result<Certs> {
+        let paths = ensure(dir)?;
+        Certs::from_files(&paths.cert, &paths.key)
+    }
+
+    #[cfg(feature = "rustls")]
+    pub fn keycert(&self) -> salvo::conn::rustls::Keycert {
+        salvo::conn::rustls::Keycert::new()
+            .cert(self.cert.clone())
+            .key(self.key.clone())
+    }
+
+    /// For `TcpListener::rustls` and `QuinnListener::new`.
+    #[cfg(feature = "rustls")]
+    pub fn rustls_config(&self) -> salvo::conn::rustls::RustlsConfig {
+        salvo::conn::rustls::RustlsConfig::new(self.keycert())
+    }
+
+    /// A Mozilla "intermediate" acceptor serving the chain, for
+    /// `HttpServer::bind_openssl` and the like.
+    #[cfg(feature = "openssl")]
+    pub fn ssl_acceptor(
+        &self,
+    ) -> Result<openssl::ssl::SslAcceptorBuilder, openssl::error::ErrorStack> {
+        use openssl::pkey::PKey;
+        use openssl::ssl::{SslAcceptor, SslMethod};
+        use openssl::x509::X509;
+
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        let key = PKey::private_key_from_pem(&self.key)?;
+        builder.set_private_key(&key)?;
+        let mut chain = X509::stack_from_pem(&self.cert)?.into_iter();
+        if let Some(leaf) = chain.next() {
+            builder.set_certificate(&leaf)?;
+        }
+        for cert in chain {
+            builder.add_extra_chain_cert(cert)?;
+        }
+        builder.check_private_key()?;
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_once_and_reuses() {
+        let dir = std::env::temp_dir().join(format!("dev-certs-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let first = Certs::dev(&dir).unwrap();
+        let ca = fs::read(dir.join("ca.pem")).unwrap();
+        let again = Certs::dev(&dir).unwrap();
+        assert_eq!(first.cert, again.cert);
+        assert!(first.cert.ends_with(&ca));
+
+        // A renewed leaf is still signed by the cached CA.
+        fs::remove_file(dir.join("cert.pem")).unwrap();
+        let renewed = Certs::dev(&dir).unwrap();
+        assert_ne!(renewed.cert, first.cert);
+        assert_eq!(fs::read(dir.join("ca.pem")).unwrap(), ca);
+
+        #[cfg(feature = "openssl")]
+        {
+            use openssl::stack::Stack;
+            use openssl::x509::store::X509StoreBuilder;
+            use openssl::x509::{X509StoreContext, X509};
+
+            let chain = X509::stack_from_pem(&renewed.cert).unwrap();
+            let mut store = X509StoreBuilder::new().unwrap();
+            store.add_cert(X509::from_pem(&ca).unwrap()).unwrap();
+            let store = store.build();
+            let mut context = X509StoreContext::new().unwrap();
+            let verified = context
+                .init(&store, &chain[0], &Stack::new().unwrap(), |c| {
+                    c.verify_cert()
+                })
+                .unwrap();
+            assert!(verified, "{}", context.error());
+            renewed.ssl_acceptor().unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
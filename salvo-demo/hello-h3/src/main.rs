@@ -1,4 +1,4 @@
-use salvo::conn::rustls::{Keycert, RustlsConfig};
+use dev_certs::Certs;
 use salvo::prelude::*;
 
 #[handler]
@@ -9,11 +9,11 @@ async fn hello() -> &'static str {
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt().init();
-    let cert = include_bytes!("../certs/cert.pem").to_vec();
-    let key = include_bytes!("../certs/key.pem").to_vec();
+    // TLS_CERT and TLS_KEY, or a generated development certificate.
+    let certs = Certs::from_env_or_dev().expect("failed to load the TLS certificate");
 
     let router = Router::new().get(hello);
-    let config = RustlsConfig::new(Keycert::new().cert(cert.as_slice()).key(key.as_slice()));
+    let config = certs.rustls_config();
     let listener = TcpListener::new(("127.0.0.1", 8080)).rustls(config.clone());
 
     let acceptor = QuinnListener::new(config, ("127.0.0.1", 8080))
@@ -24,4 +24,4 @@ async fn main() {
     Server::new(acceptor).serve(router).await;
 }
 
-// curl "http://127.0.0.1:8080/"
+// curl --cacert ~/.cache/dev-certs/ca.pem --http3 "https://127.0.0.1:8080/"
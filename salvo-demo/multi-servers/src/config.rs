@@ -0,0 +1,218 @@
+//! The listeners to run, read from a TOML file such as
+//!
+//! ```toml
+//! [[listeners]]
+//! address = "0.0.0.0:5443"
+//! protocols = ["http1", "http2", "http3"]
+//! tls = { cert = "certs/cert.pem", key = "certs/key.pem" }
+//! mounts = { "/" = "public" }
+//! ```
+//!
+//! Relative certificate paths are resolved against the config file's
+//! directory. `tls = {}` uses the development certificate from `dev-certs`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// How often to check the certificate files for changes, in seconds.
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: u64,
+    /// How long to wait for open requests on shutdown, in seconds.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    pub listeners: Vec<Listener>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Listener {
+    pub address: SocketAddr,
+    /// Only chosen between during the TLS handshake; plain listeners always
+    /// speak HTTP/1 and h2c, so they cannot set anything else.
+    #[serde(default = "default_protocols")]
+    pub protocols: Vec<Protocol>,
+    pub tls: Option<Tls>,
+    /// Path prefix to router name, see `main::router`.
+    pub mounts: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Http1,
+    Http2,
+    Http3,
+}
+
+impl Protocol {
+    /// The ALPN id announced in the TLS handshake.
+    pub fn alpn(self) -> &'static [u8] {
+        match self {
+            Protocol::Http1 => b"http/1.1",
+            Protocol::Http2 => b"h2",
+            Protocol::Http3 => b"h3",
+        }
+    }
+}
+
+fn default_reload_interval() -> u64 {
+    30
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_protocols() -> Vec<Protocol> {
+    vec![Protocol::Http1, Protocol::Http2]
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {path:?}: {e}"))?;
+        let mut config = Config::parse(&text).map_err(|e| format!("invalid {path:?}: {e}"))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        for tls in config.listeners.iter_mut().filter_map(|l| l.tls.as_mut()) {
+            for path in [&mut tls.cert, &mut tls.key].into_iter().flatten() {
+                *path = base.join(&*path);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        if config.listeners.is_empty() {
+            return Err("no listeners configured".into());
+        }
+        if config.reload_interval < 1 {
+            return Err("reload_interval must be at least 1 second".into());
+        }
+        for listener in &config.listeners {
+            let address = listener.address;
+            if listener.protocols.is_empty() {
+                return Err(format!("{address}: no protocols"));
+            }
+            if listener.tls.is_none() && listener.protocols.contains(&Protocol::Http3) {
+                return Err(format!("{address}: http3 needs tls"));
+            }
+            if listener.tls.is_none() && listener.protocols != default_protocols() {
+                return Err(format!(
+                    "{address}: choosing protocols needs tls, plain listeners speak http1 and http2"
+                ));
+            }
+            if let Some(tls) = &listener.tls {
+                if tls.cert.is_some() != tls.key.is_some() {
+                    return Err(format!(
+                        "{address}: set both tls.cert and tls.key, or neither"
+                    ));
+                }
+            }
+            if listener.mounts.is_empty() {
+                return Err(format!("{address}: nothing mounted"));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_secs(self.reload_interval)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listeners() {
+        let config = Config::parse(
+            r#"
+            reload_interval = 5
+
+            [[listeners]]
+            address = "0.0.0.0:5800"
+            mounts = { "/" = "public" }
+
+            [[listeners]]
+            address = "127.0.0.1:5443"
+            protocols = ["http2", "http3"]
+            tls = {}
+            mounts = { "/" = "public", "/admin" = "admin" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.reload_interval(), Duration::from_secs(5));
+        assert_eq!(config.shutdown_timeout(), Duration::from_secs(30));
+        let [plain, tls] = &config.listeners[..] else {
+            panic!("expected two listeners");
+        };
+        assert_eq!(plain.protocols, [Protocol::Http1, Protocol::Http2]);
+        assert!(plain.tls.is_none());
+        assert_eq!(tls.protocols, [Protocol::Http2, Protocol::Http3]);
+        assert!(tls.tls.as_ref().unwrap().cert.is_none());
+        assert_eq!(tls.mounts["/admin"], "admin");
+    }
+
+    #[test]
+    fn rejects_protocols_without_tls() {
+        let error = Config::parse(
+            r#"
+            [[listeners]]
+            address = "0.0.0.0:5800"
+            protocols = ["http3"]
+            mounts = { "/" = "public" }
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(error, "0.0.0.0:5800: http3 needs tls");
+
+        let error = Config::parse(
+            r#"
+            [[listeners]]
+            address = "0.0.0.0:5800"
+            protocols = ["http2"]
+            mounts = { "/" = "public" }
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "0.0.0.0:5800: choosing protocols needs tls, plain listeners speak http1 and http2"
+        );
+    }
+
+    #[test]
+    fn rejects_a_zero_reload_interval() {
+        let error = Config::parse(
+            r#"
+            reload_interval = 0
+
+            [[listeners]]
+            address = "0.0.0.0:5800"
+            mounts = { "/" = "public" }
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(error, "reload_interval must be at least 1 second");
+    }
+}
@@ -1,28 +1,170 @@
+//! Runs every listener named in a TOML config, `multi-servers.toml` next to
+//! the manifest unless another path is given as the first argument.
+//!
+//! Certificates are reloaded when their files change, and SIGTERM or Ctrl-C
+//! stops all listeners gracefully: they stop accepting, and open requests
+//! get `shutdown_timeout` seconds to finish.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use std::{env, io, process};
+
+use futures_util::future::try_join_all;
+use salvo::conn::Acceptor;
 use salvo::prelude::*;
+use salvo::server::ServerHandle;
+
+use self::config::{Config, Listener, Protocol};
+
+mod config;
+mod tls;
+
+type Serving = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
 
 #[handler]
-async fn hello1() -> &'static str {
-    "Server1: Hello World"
+async fn hello() -> &'static str {
+    "Public: Hello World"
 }
 
 #[handler]
-async fn hello2() -> &'static str {
-    "Server2: Hello World"
+async fn status() -> &'static str {
+    "Admin: OK"
+}
+
+/// The routers a listener can mount, by name.
+fn router(name: &str) -> Option<Router> {
+    match name {
+        "public" => Some(Router::new().get(hello)),
+        "admin" => Some(Router::new().get(status)),
+        _ => None,
+    }
+}
+
+fn service(listener: &Listener) -> Result<Service, String> {
+    let mut root = Router::new();
+    for (path, name) in &listener.mounts {
+        let router =
+            router(name).ok_or_else(|| format!("{}: unknown router {name:?}", listener.address))?;
+        let path = path.trim_matches('/');
+        root = root.push(if path.is_empty() {
+            router
+        } else {
+            Router::with_path(path).push(router)
+        });
+    }
+    Ok(Service::new(root))
+}
+
+fn serve<A>(server: Server<A>, service: Service) -> (ServerHandle, Serving)
+where
+    A: Acceptor + Send + 'static,
+{
+    let handle = server.handle();
+    (handle, Box::pin(server.try_serve(service)))
+}
+
+/// Binds `listener`: plain TCP, or TLS over TCP for HTTP/1 and HTTP/2 joined
+/// with QUIC for HTTP/3, as its protocols ask.
+async fn start(listener: &Listener, reload: Duration) -> Result<(ServerHandle, Serving), String> {
+    let service = service(listener)?;
+    let addr = listener.address;
+    let bind_error = |e: io::Error| format!("{addr}: cannot bind: {e}");
+
+    let Some(tls) = &listener.tls else {
+        let acceptor = TcpListener::new(addr)
+            .try_bind()
+            .await
+            .map_err(bind_error)?;
+        return Ok(serve(Server::new(acceptor), service));
+    };
+    let alpn: Vec<Vec<u8>> = listener
+        .protocols
+        .iter()
+        .filter(|protocol| **protocol != Protocol::Http3)
+        .map(|protocol| protocol.alpn().to_vec())
+        .collect();
+    let h3 = listener.protocols.contains(&Protocol::Http3);
+    let quic = || -> Result<_, String> {
+        let config = tls::reloading(tls, vec![Protocol::Http3.alpn().to_vec()], reload)?;
+        Ok(QuinnListener::new(config, addr))
+    };
+
+    if alpn.is_empty() {
+        let acceptor = quic()?.try_bind().await.map_err(bind_error)?;
+        return Ok(serve(Server::new(acceptor), service));
+    }
+    let tcp = TcpListener::new(addr).rustls(tls::reloading(tls, alpn, reload)?);
+    if h3 {
+        let acceptor = quic()?.join(tcp).try_bind().await.map_err(bind_error)?;
+        Ok(serve(Server::new(acceptor), service))
+    } else {
+        let acceptor = tcp.try_bind().await.map_err(bind_error)?;
+        Ok(serve(Server::new(acceptor), service))
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+async fn run(path: &Path) -> Result<(), String> {
+    let config = Config::load(path)?;
+    let mut handles = Vec::new();
+    let mut servers = Vec::new();
+    for listener in &config.listeners {
+        let (handle, serving) = start(listener, config.reload_interval()).await?;
+        tracing::info!(
+            address = %listener.address,
+            protocols = ?listener.protocols,
+            tls = listener.tls.is_some(),
+            "listening"
+        );
+        handles.push(handle);
+        servers.push(serving);
+    }
+
+    let timeout = config.shutdown_timeout();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("shutting down");
+        for handle in handles {
+            handle.stop_graceful(timeout);
+        }
+    });
+    try_join_all(servers).await.map_err(|e| e.to_string())?;
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt().init();
 
-    let router1 = Router::new().get(hello1);
-    let router2 = Router::new().get(hello2);
-
-    tokio::try_join!(
-        Server::new(TcpListener::new("0.0.0.0:5800").bind().await).try_serve(router1),
-        Server::new(TcpListener::new("0.0.0.0:5801").bind().await).try_serve(router2),
-    )
-    .unwrap();
+    let path = env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/multi-servers.toml").into());
+    if let Err(e) = run(&path).await {
+        tracing::error!("{e}");
+        process::exit(1);
+    }
 }
 
 // curl "http://127.0.0.1:5800/"
 // curl "http://127.0.0.1:5801/"
+// curl --cacert ~/.cache/dev-certs/ca.pem --http2 "https://127.0.0.1:5443/admin"
+// curl --cacert ~/.cache/dev-certs/ca.pem --http3 "https://127.0.0.1:5443/"
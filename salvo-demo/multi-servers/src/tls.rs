@@ -0,0 +1,67 @@
+//! TLS settings that follow the certificate files on disk.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use futures_util::stream::{self, Stream, StreamExt};
+use salvo::conn::rustls::{Keycert, RustlsConfig};
+
+use crate::config::Tls;
+
+/// Loads the certificate and key now, then polls them every `interval` and
+/// yields a fresh config whenever either file changed. Salvo uses each new
+/// config for new handshakes only, so open connections are not dropped.
+///
+/// Replace the key before the certificate (or move both into place at once):
+/// a reload that reads a mismatched pair is rejected and the previous
+/// certificate stays in use until the next change.
+pub fn reloading(
+    tls: &Tls,
+    alpn: Vec<Vec<u8>>,
+    interval: Duration,
+) -> Result<impl Stream<Item = RustlsConfig> + Send + 'static, String> {
+    let (cert, key) = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+        _ => {
+            let paths = dev_certs::ensure(&dev_certs::dir())
+                .map_err(|e| format!("cannot generate a development certificate: {e}"))?;
+            (paths.cert, paths.key)
+        }
+    };
+    let initial = load(&cert, &key, &alpn)?;
+    let stamp = modified(&cert, &key);
+    let updates = stream::unfold(stamp, move |mut stamp| {
+        let (cert, key, alpn) = (cert.clone(), key.clone(), alpn.clone());
+        async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let current = modified(&cert, &key);
+                if current == stamp {
+                    continue;
+                }
+                stamp = current;
+                match load(&cert, &key, &alpn) {
+                    Ok(config) => {
+                        tracing::info!(cert = ?cert, "reloaded certificate");
+                        return Some((config, stamp));
+                    }
+                    Err(e) => tracing::error!("keeping the previous certificate: {e}"),
+                }
+            }
+        }
+    });
+    Ok(stream::once(async { initial }).chain(updates))
+}
+
+fn load(cert: &Path, key: &Path, alpn: &[Vec<u8>]) -> Result<RustlsConfig, String> {
+    let read = |path: &Path| fs::read(path).map_err(|e| format!("cannot read {path:?}: {e}"));
+    let keycert = Keycert::new().cert(read(cert)?).key(read(key)?);
+    Ok(RustlsConfig::new(keycert).alpn_protocols(alpn.to_vec()))
+}
+
+/// The later modification time of the two files.
+fn modified(cert: &Path, key: &Path) -> Option<SystemTime> {
+    let time = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    time(cert).max(time(key))
+}
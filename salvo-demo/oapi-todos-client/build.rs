@@ -0,0 +1,352 @@
+//! Generates the client in `$OUT_DIR/client.rs` from `openapi.json`.
+//!
+//! Only the part of OpenAPI the todos service uses is understood: object and
+//! string enum schemas under `components/schemas`, path and query parameters,
+//! JSON request bodies and JSON responses. Anything else becomes a
+//! `serde_json::Value` instead of failing the build.
+
+use std::{env, fs, path::Path};
+
+use serde_json::Value;
+
+const SPEC: &str = "openapi.json";
+const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+fn main() {
+    println!("cargo:rerun-if-changed={SPEC}");
+    let spec = fs::read_to_string(SPEC).expect("failed to read openapi.json");
+    let spec: Value = serde_json::from_str(&spec).expect("openapi.json is not valid JSON");
+
+    let mut out = String::new();
+    types(&spec, &mut out);
+    operations(&spec, &mut out);
+
+    let path = Path::new(&env::var_os("OUT_DIR").unwrap()).join("client.rs");
+    fs::write(path, out).expect("failed to write the generated client");
+}
+
+/// One Rust type per entry of `components/schemas`.
+fn types(spec: &Value, out: &mut String) {
+    let Some(schemas) = spec
+        .pointer("/components/schemas")
+        .and_then(Value::as_object)
+    else {
+        return;
+    };
+    for (name, schema) in schemas {
+        let name = type_name(name);
+        doc(out, "", &[schema["description"].as_str()]);
+        if let Some(variants) = schema["enum"].as_array() {
+            *out += "#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]\n";
+            *out += &format!("pub enum {name} {{\n");
+            for variant in variants.iter().filter_map(Value::as_str) {
+                *out += &format!("    #[serde(rename = {variant:?})]\n");
+                *out += &format!("    {},\n", pascal_case(variant));
+            }
+            *out += "}\n\n";
+            continue;
+        }
+
+        let required: Vec<&str> = schema["required"]
+            .as_array()
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        *out += "#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]\n";
+        *out += &format!("pub struct {name} {{\n");
+        for (json_name, property) in schema["properties"].as_object().into_iter().flatten() {
+            let field = field_name(json_name);
+            let mut ty = rust_type(property);
+            doc(out, "    ", &[property["description"].as_str()]);
+            if field.trim_start_matches("r#") != json_name {
+                *out += &format!("    #[serde(rename = {json_name:?})]\n");
+            }
+            if !required.contains(&json_name.as_str()) {
+                if !ty.starts_with("Option<") {
+                    ty = format!("Option<{ty}>");
+                }
+                *out += "    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n";
+            }
+            *out += &format!("    pub {field}: {ty},\n");
+        }
+        *out += "}\n\n";
+    }
+}
+
+/// One `Client` method per operation, named after its `operationId`.
+fn operations(spec: &Value, out: &mut String) {
+    *out += "impl Client {\n";
+    for (path, item) in spec["paths"].as_object().into_iter().flatten() {
+        for method in METHODS {
+            if let Some(operation) = item.get(method) {
+                operation_method(path, method, item, operation, out);
+            }
+        }
+    }
+    out.truncate(out.trim_end().len());
+    *out += "\n}\n";
+}
+
+struct Param {
+    name: String,
+    ident: String,
+    location: String,
+    required: bool,
+    ty: String,
+}
+
+fn operation_method(path: &str, method: &str, item: &Value, operation: &Value, out: &mut String) {
+    let name = match operation["operationId"].as_str() {
+        Some(id) => field_name(id.rsplit(['.', ':']).next().unwrap_or(id)),
+        None => field_name(&format!("{method}{}", path.replace(['/', '{', '}'], "_"))),
+    };
+    let params: Vec<Param> = item["parameters"]
+        .as_array()
+        .into_iter()
+        .chain(operation["parameters"].as_array())
+        .flatten()
+        .map(|param| {
+            let name = param["name"].as_str().unwrap_or_default().to_owned();
+            Param {
+                ident: field_name(&name),
+                location: param["in"].as_str().unwrap_or_default().to_owned(),
+                required: param["required"] == true,
+                ty: rust_type(&param["schema"]),
+                name,
+            }
+        })
+        .filter(|param| param.location == "path" || param.location == "query")
+        .collect();
+    let body = json_schema(&operation["requestBody"]).map(rust_type);
+    let response = operation["responses"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(status, _)| status.starts_with('2'))
+        .find_map(|(_, response)| json_schema(response))
+        .map(rust_type);
+
+    // Signature.
+    let route = format!("`{} {path}`", method.to_uppercase());
+    let secured = operation["security"]
+        .as_array()
+        .is_some_and(|schemes| !schemes.is_empty());
+    doc(
+        out,
+        "    ",
+        &[
+            operation["summary"].as_str(),
+            operation["description"].as_str(),
+            secured.then_some("Needs [`Credentials`]."),
+            Some(&route),
+        ],
+    );
+    if operation["deprecated"] == true {
+        *out += "    #[deprecated]\n";
+    }
+    *out += &format!("    pub async fn {name}(\n        &self,\n");
+    for param in &params {
+        let ty = if param.ty == "String" {
+            "&str"
+        } else {
+            &param.ty
+        };
+        if param.required || param.location == "path" {
+            *out += &format!("        {}: {ty},\n", param.ident);
+        } else {
+            *out += &format!("        {}: Option<{ty}>,\n", param.ident);
+        }
+    }
+    if let Some(body) = &body {
+        *out += &format!("        body: &{body},\n");
+    }
+    let ret = response.as_deref().unwrap_or("()");
+    *out += &format!("    ) -> Result<{ret}, Error> {{\n");
+
+    // Body.
+    let mut url = path.to_owned();
+    let mut args = String::new();
+    for param in params.iter().filter(|p| p.location == "path") {
+        url = url.replace(&format!("{{{}}}", param.name), "{}");
+        if param.ty == "String" {
+            args += &format!(", encode_path({})", param.ident);
+        } else {
+            args += &format!(", encode_path(&{}.to_string())", param.ident);
+        }
+    }
+    let method = method.to_uppercase();
+    if args.is_empty() {
+        *out +=
+            &format!("        let request = self.request(reqwest::Method::{method}, {url:?});\n");
+    } else {
+        *out += &format!("        let path = format!({url:?}{args});\n");
+        *out += &format!("        let request = self.request(reqwest::Method::{method}, &path);\n");
+    }
+    let (required, optional): (Vec<&Param>, Vec<&Param>) = params
+        .iter()
+        .filter(|p| p.location == "query")
+        .partition(|p| p.required);
+    if !required.is_empty() || !optional.is_empty() {
+        let pairs: Vec<String> = required
+            .iter()
+            .map(|param| format!("({:?}, {}.to_string())", param.name, param.ident))
+            .collect();
+        let binding = if optional.is_empty() {
+            "query"
+        } else {
+            "mut query"
+        };
+        let init = if pairs.is_empty() {
+            "Vec::new()".to_owned()
+        } else {
+            format!("vec![{}]", pairs.join(", "))
+        };
+        *out += &format!("        let {binding}: Vec<(&str, String)> = {init};\n");
+        for param in optional {
+            *out += &format!(
+                "        if let Some(value) = {} {{\n            query.push(({:?}, value.to_string()));\n        }}\n",
+                param.ident, param.name
+            );
+        }
+        *out += "        let request = request.query(&query);\n";
+    }
+    if body.is_some() {
+        *out += "        let request = request.json(body);\n";
+    }
+    if response.is_some() {
+        *out += "        Ok(self.send(request).await?.json().await?)\n";
+    } else {
+        *out += "        self.send(request).await?;\n        Ok(())\n";
+    }
+    *out += "    }\n\n";
+}
+
+/// The schema of the `application/json` content of a request body or
+/// response, if it has one.
+fn json_schema(value: &Value) -> Option<&Value> {
+    value
+        .pointer("/content/application~1json/schema")
+        .filter(|schema| !schema.is_null())
+}
+
+fn rust_type(schema: &Value) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return type_name(reference.rsplit('/').next().unwrap_or(reference));
+    }
+    // `Option<T>` of a named type comes out as `oneOf: [null, T]`.
+    for key in ["oneOf", "anyOf"] {
+        if let Some(options) = schema[key].as_array() {
+            let others: Vec<&Value> = options.iter().filter(|o| o["type"] != "null").collect();
+            return match others[..] {
+                [inner] if others.len() < options.len() => format!("Option<{}>", rust_type(inner)),
+                [inner] => rust_type(inner),
+                _ => "serde_json::Value".into(),
+            };
+        }
+    }
+
+    let (ty, nullable) = match &schema["type"] {
+        Value::String(ty) => (ty.as_str(), schema["nullable"] == true),
+        Value::Array(types) => {
+            let others: Vec<&str> = types
+                .iter()
+                .filter_map(Value::as_str)
+                .filter(|ty| *ty != "null")
+                .collect();
+            match others[..] {
+                [ty] => (ty, others.len() < types.len()),
+                _ => return "serde_json::Value".into(),
+            }
+        }
+        _ => return "serde_json::Value".into(),
+    };
+    let format = schema["format"].as_str().unwrap_or_default();
+    let base = match ty {
+        "boolean" => "bool".to_owned(),
+        "integer" => match format {
+            "int32" => "i32",
+            "uint32" => "u32",
+            f if f.starts_with("uint") => "u64",
+            _ => "i64",
+        }
+        .to_owned(),
+        "number" if format == "float" => "f32".to_owned(),
+        "number" => "f64".to_owned(),
+        "string" => "String".to_owned(),
+        "array" => format!("Vec<{}>", rust_type(&schema["items"])),
+        _ => "serde_json::Value".to_owned(),
+    };
+    if nullable {
+        format!("Option<{base}>")
+    } else {
+        base
+    }
+}
+
+/// Doc comment lines for the non-empty `paragraphs`, separated by blank ones.
+fn doc(out: &mut String, indent: &str, paragraphs: &[Option<&str>]) {
+    let paragraphs = paragraphs
+        .iter()
+        .flatten()
+        .filter(|text| !text.trim().is_empty());
+    for (i, text) in paragraphs.enumerate() {
+        if i > 0 {
+            *out += &format!("{indent}///\n");
+        }
+        for line in text.trim().lines() {
+            *out += format!("{indent}/// {line}").trim_end();
+            *out += "\n";
+        }
+    }
+}
+
+/// `oapi_todos.Todo` becomes `Todo`.
+fn type_name(name: &str) -> String {
+    pascal_case(name.rsplit(['.', ':']).next().unwrap_or(name))
+}
+
+fn pascal_case(s: &str) -> String {
+    let name: String = s
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        format!("V{name}")
+    } else {
+        name
+    }
+}
+
+/// A snake_case identifier, escaped if it is a keyword.
+fn field_name(s: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in s.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 && !name.ends_with('_') {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_matches('_').to_owned();
+    match name.as_str() {
+        "" => "value".to_owned(),
+        "self" | "super" | "crate" => format!("{name}_"),
+        "as" | "async" | "await" | "box" | "break" | "const" | "continue" | "dyn" | "else"
+        | "enum" | "extern" | "false" | "fn" | "for" | "if" | "impl" | "in" | "let" | "loop"
+        | "match" | "mod" | "move" | "mut" | "pub" | "ref" | "return" | "static" | "struct"
+        | "trait" | "true" | "type" | "unsafe" | "use" | "where" | "while" | "yield" => {
+            format!("r#{name}")
+        }
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => format!("_{name}"),
+        _ => name,
+    }
+}
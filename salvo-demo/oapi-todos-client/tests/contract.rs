@@ -0,0 +1,70 @@
+//! Keeps the client honest: the committed `openapi.json` must be exactly what
+//! the server generates for the current API version, and the client built
+//! from it must be able to talk to the server.
+
+use std::{env, fs};
+
+use oapi_todos::{Auth, Version};
+use oapi_todos_client::{Client, Credentials, Error, Priority, Todo};
+use salvo::prelude::*;
+use serde_json::Value;
+
+const SPEC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+#[test]
+fn committed_spec_matches_the_server() {
+    let served = Version::CURRENT.openapi().to_pretty_json().unwrap();
+    if env::var_os("UPDATE_OPENAPI").is_some() {
+        fs::write(SPEC, served + "\n").unwrap();
+        return;
+    }
+
+    let served: Value = serde_json::from_str(&served).unwrap();
+    let committed: Value = serde_json::from_str(&fs::read_to_string(SPEC).unwrap()).unwrap();
+    assert!(
+        served == committed,
+        "openapi.json no longer matches the server; if the API change is intended, \
+         rerun with UPDATE_OPENAPI=1 to refresh it and the generated client"
+    );
+}
+
+#[tokio::test]
+async fn client_talks_to_the_server() {
+    let acceptor = TcpListener::new("127.0.0.1:5810").bind().await;
+    let auth = Auth::new().api_key("contract-test");
+    tokio::spawn(Server::new(acceptor).serve(oapi_todos::router(auth)));
+    let anonymous = Client::new("http://127.0.0.1:5810/");
+    let client = anonymous
+        .clone()
+        .credentials(Credentials::ApiKey("contract-test".into()));
+
+    let mut todo = Todo {
+        id: 42,
+        title: "call the client".into(),
+        completed: false,
+        priority: Some(Priority::High),
+    };
+    match anonymous.create_todo(&todo).await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 401),
+        other => panic!("expected a 401, got {other:?}"),
+    }
+    client.create_todo(&todo).await.unwrap();
+    match client.create_todo(&todo).await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 409),
+        other => panic!("expected a 409, got {other:?}"),
+    }
+    todo.completed = true;
+    client.update_todo(todo.id, &todo).await.unwrap();
+    // Reading needs no credentials.
+    assert_eq!(
+        anonymous.list_todos(None, None).await.unwrap(),
+        [todo.clone()]
+    );
+    assert!(client.list_todos(Some(1), None).await.unwrap().is_empty());
+
+    client.delete_todo(todo.id).await.unwrap();
+    match client.delete_todo(todo.id).await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 404),
+        other => panic!("expected a 404, got {other:?}"),
+    }
+}
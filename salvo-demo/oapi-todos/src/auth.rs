@@ -0,0 +1,286 @@
+//! Authentication for the write endpoints.
+//!
+//! A caller authenticates with either
+//!
+//! * an API key in the `x-api-key` header, or
+//! * a JWT in `Authorization: Bearer <token>`, signed with HS256 (shared
+//!   secret) or RS256 (verified with the issuer's public key). The token must
+//!   carry `sub` and an unexpired `exp`.
+//!
+//! Requests without valid credentials get a 401. With nothing configured
+//! every request is rejected, so a missing setting never opens up the API.
+
+use std::{env, fs};
+
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use salvo::http::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use salvo::oapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use salvo::prelude::*;
+use serde::Deserialize;
+
+/// Header carrying the API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Names of the schemes in the OpenAPI document, as used by `security(...)`
+/// on the endpoints.
+pub const API_KEY_SCHEME: &str = "api_key";
+pub const BEARER_SCHEME: &str = "bearer";
+
+/// The hoop guarding the write endpoints. On success it injects the
+/// [`Caller`] into the depot.
+#[derive(Clone, Default)]
+pub struct Auth {
+    api_keys: Vec<String>,
+    hs256: Option<DecodingKey>,
+    rs256: Option<DecodingKey>,
+}
+
+/// Who made the request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Caller {
+    /// The token's `sub`, or `api-key:<n>` for the n-th configured key.
+    pub subject: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+impl Auth {
+    pub fn new() -> Auth {
+        Auth::default()
+    }
+
+    /// Reads the settings from the environment:
+    ///
+    /// * `TODOS_API_KEYS`: comma-separated API keys.
+    /// * `TODOS_JWT_SECRET`: the HS256 secret.
+    /// * `TODOS_JWT_PUBLIC_KEY`: path to the PEM-encoded RS256 public key.
+    pub fn from_env() -> Result<Auth, String> {
+        let mut auth = Auth::new();
+        if let Ok(keys) = env::var("TODOS_API_KEYS") {
+            for key in keys.split(',').map(str::trim).filter(|key| !key.is_empty()) {
+                auth = auth.api_key(key);
+            }
+        }
+        if let Ok(secret) = env::var("TODOS_JWT_SECRET") {
+            auth = auth.hs256_secret(secret.as_bytes());
+        }
+        if let Some(path) = env::var_os("TODOS_JWT_PUBLIC_KEY") {
+            let pem = fs::read(&path)
+                .map_err(|e| format!("cannot read TODOS_JWT_PUBLIC_KEY {path:?}: {e}"))?;
+            auth = auth
+                .rs256_public_key(&pem)
+                .map_err(|e| format!("invalid TODOS_JWT_PUBLIC_KEY {path:?}: {e}"))?;
+        }
+        if auth.api_keys.is_empty() && auth.hs256.is_none() && auth.rs256.is_none() {
+            tracing::warn!("no API keys or JWT keys configured, all writes will be rejected");
+        }
+        Ok(auth)
+    }
+
+    pub fn api_key(mut self, key: impl Into<String>) -> Auth {
+        self.api_keys.push(key.into());
+        self
+    }
+
+    /// Accepts HS256 tokens signed with `secret`.
+    pub fn hs256_secret(mut self, secret: &[u8]) -> Auth {
+        self.hs256 = Some(DecodingKey::from_secret(secret));
+        self
+    }
+
+    /// Accepts RS256 tokens verified with the PEM-encoded public key `pem`.
+    pub fn rs256_public_key(mut self, pem: &[u8]) -> Result<Auth, jsonwebtoken::errors::Error> {
+        self.rs256 = Some(DecodingKey::from_rsa_pem(pem)?);
+        Ok(self)
+    }
+
+    pub fn authenticate(&self, req: &Request) -> Result<Caller, &'static str> {
+        if let Some(key) = req.headers().get(API_KEY_HEADER) {
+            return self
+                .api_keys
+                .iter()
+                .position(|known| constant_time_eq(known.as_bytes(), key.as_bytes()))
+                .map(|n| Caller {
+                    subject: format!("api-key:{n}"),
+                })
+                .ok_or("invalid API key");
+        }
+
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or("missing credentials")?;
+        let header = decode_header(token).map_err(|_| "malformed token")?;
+        // Each algorithm has its own key, so an RS256 public key can never be
+        // used as an HS256 secret.
+        let key = match header.alg {
+            Algorithm::HS256 => self.hs256.as_ref(),
+            Algorithm::RS256 => self.rs256.as_ref(),
+            _ => None,
+        }
+        .ok_or("unsupported token algorithm")?;
+        let data = decode::<Claims>(token, key, &Validation::new(header.alg))
+            .map_err(|_| "invalid or expired token")?;
+        Ok(Caller {
+            subject: data.claims.sub,
+        })
+    }
+}
+
+#[async_trait]
+impl Handler for Auth {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        match self.authenticate(req) {
+            Ok(caller) => {
+                depot.inject(caller);
+            }
+            Err(reason) => {
+                tracing::debug!(reason, "rejected request");
+                res.headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                res.render(StatusError::unauthorized().brief(reason));
+                ctrl.skip_rest();
+            }
+        }
+    }
+}
+
+/// The `securitySchemes` of the OpenAPI document.
+pub fn security_schemes(doc: OpenApi) -> OpenApi {
+    doc.add_security_scheme(
+        API_KEY_SCHEME,
+        SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+    )
+    .add_security_scheme(
+        BEARER_SCHEME,
+        SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer).bearer_format("JWT")),
+    )
+}
+
+/// Compares without returning early, so the time taken says nothing about
+/// how much of a guessed key was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{crypto, encode, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(name: &'static str, value: &str) -> Request {
+        let mut req = Request::new();
+        req.headers_mut()
+            .insert(name, HeaderValue::from_str(value).unwrap());
+        req
+    }
+
+    /// A key pair made for these tests only.
+    const RS256_PRIVATE: &[u8] = include_bytes!("../testdata/rs256_private.pem");
+    const RS256_PUBLIC: &[u8] = include_bytes!("../testdata/rs256_public.pem");
+
+    const LATER: i64 = 4_102_444_800; // 2100-01-01
+
+    fn token(secret: &[u8], exp: i64) -> String {
+        let claims = json!({ "sub": "alice", "exp": exp });
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    fn rs256_token(exp: i64) -> String {
+        let claims = json!({ "sub": "alice", "exp": exp });
+        encode(
+            &Header::new(Algorithm::RS256),
+            &claims,
+            &EncodingKey::from_rsa_pem(RS256_PRIVATE).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn bearer(token: String) -> Request {
+        request("authorization", &format!("Bearer {token}"))
+    }
+
+    #[test]
+    fn accepts_api_keys() {
+        let auth = Auth::new().api_key("one").api_key("two");
+        let caller = auth.authenticate(&request(API_KEY_HEADER, "two")).unwrap();
+        assert_eq!(caller.subject, "api-key:1");
+        assert!(auth
+            .authenticate(&request(API_KEY_HEADER, "three"))
+            .is_err());
+        assert!(auth.authenticate(&Request::new()).is_err());
+    }
+
+    #[test]
+    fn accepts_hs256_tokens() {
+        let auth = Auth::new().hs256_secret(b"secret");
+
+        let caller = auth.authenticate(&bearer(token(b"secret", LATER))).unwrap();
+        assert_eq!(caller.subject, "alice");
+        assert!(auth.authenticate(&bearer(token(b"guess", LATER))).is_err());
+        assert!(auth.authenticate(&bearer(token(b"secret", 1))).is_err());
+        // Nothing is configured.
+        assert!(Auth::new()
+            .authenticate(&bearer(token(b"secret", LATER)))
+            .is_err());
+    }
+
+    #[test]
+    fn accepts_rs256_tokens() {
+        let auth = Auth::new().rs256_public_key(RS256_PUBLIC).unwrap();
+
+        let caller = auth.authenticate(&bearer(rs256_token(LATER))).unwrap();
+        assert_eq!(caller.subject, "alice");
+        assert!(auth.authenticate(&bearer(rs256_token(1))).is_err());
+        // A token signed by someone else.
+        let mut forged = rs256_token(LATER);
+        forged.replace_range(forged.len() - 4.., "AAAA");
+        assert!(auth.authenticate(&bearer(forged)).is_err());
+        // RS256 is not accepted without a public key.
+        let auth = Auth::new().hs256_secret(b"secret");
+        assert!(auth.authenticate(&bearer(rs256_token(LATER))).is_err());
+    }
+
+    #[test]
+    fn rejects_algorithm_confusion() {
+        let auth = Auth::new()
+            .hs256_secret(b"secret")
+            .rs256_public_key(RS256_PUBLIC)
+            .unwrap();
+
+        // The classic attack: HS256 with the public key as the secret.
+        assert!(auth
+            .authenticate(&bearer(token(RS256_PUBLIC, LATER)))
+            .is_err());
+
+        // An RS256 header over a valid HS256 signature.
+        let rs256 = rs256_token(LATER);
+        let message = &rs256[..rs256.rfind('.').unwrap()];
+        let signature = crypto::sign(
+            message.as_bytes(),
+            &EncodingKey::from_secret(b"secret"),
+            Algorithm::HS256,
+        )
+        .unwrap();
+        let confused = format!("{message}.{signature}");
+        assert!(auth.authenticate(&bearer(confused)).is_err());
+    }
+}
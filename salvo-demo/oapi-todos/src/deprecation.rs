@@ -0,0 +1,142 @@
+//! Response headers announcing that an API version is on its way out.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use salvo::http::header::{HeaderName, HeaderValue, LINK};
+use salvo::prelude::*;
+
+/// A hoop adding to every response
+///
+/// * `Deprecation: @<unix time>` (RFC 9745): since when the routes are
+///   deprecated,
+/// * `Sunset: <HTTP date>` (RFC 8594): when they will stop working, and
+/// * `Link: <...>; rel="successor-version"`: where to go instead.
+pub struct Deprecation {
+    since: SystemTime,
+    sunset: Option<SystemTime>,
+    successor: Option<String>,
+}
+
+impl Deprecation {
+    pub fn since(since: SystemTime) -> Deprecation {
+        Deprecation {
+            since,
+            sunset: None,
+            successor: None,
+        }
+    }
+
+    pub fn sunset(mut self, sunset: SystemTime) -> Deprecation {
+        self.sunset = Some(sunset);
+        self
+    }
+
+    /// The URL of the version replacing this one.
+    pub fn successor(mut self, url: impl Into<String>) -> Deprecation {
+        self.successor = Some(url.into());
+        self
+    }
+
+    fn headers(&self) -> Vec<(HeaderName, String)> {
+        let since = self.since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut headers = vec![(
+            HeaderName::from_static("deprecation"),
+            format!("@{}", since.as_secs()),
+        )];
+        if let Some(sunset) = self.sunset {
+            headers.push((HeaderName::from_static("sunset"), http_date(sunset)));
+        }
+        if let Some(url) = &self.successor {
+            headers.push((LINK, format!("<{url}>; rel=\"successor-version\"")));
+        }
+        headers
+    }
+}
+
+#[async_trait]
+impl Handler for Deprecation {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        ctrl.call_next(req, depot, res).await;
+        for (name, value) in self.headers() {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                res.headers_mut().append(name, value);
+            }
+        }
+    }
+}
+
+/// Formats `time` like `Thu, 01 Apr 2027 00:00:00 GMT`.
+fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since the epoch, after Howard Hinnant's
+    // `civil_from_days`.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{}, {day:02} {} {year} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        MONTHS[(month - 1) as usize],
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_http_dates() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(http_date(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(at(951_782_400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(
+            http_date(at(1_806_537_600 + 3_661)),
+            "Thu, 01 Apr 2027 01:01:01 GMT"
+        );
+    }
+
+    #[test]
+    fn announces_deprecation() {
+        let headers = Deprecation::since(UNIX_EPOCH + Duration::from_secs(1_790_812_800))
+            .sunset(UNIX_EPOCH + Duration::from_secs(1_806_537_600))
+            .successor("/api/v2/todos")
+            .headers();
+        let headers: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                ("deprecation", "@1790812800"),
+                ("sunset", "Thu, 01 Apr 2027 00:00:00 GMT"),
+                ("link", "</api/v2/todos>; rel=\"successor-version\""),
+            ]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! The todos service: its routes, its OpenAPI documents and the
+//! documentation UIs, kept in a library so other crates (the generated
+//! client's contract test in particular) can build the exact same API.
+//!
+//! The API is versioned: each [`Version`] is mounted under `/api/<version>`
+//! with its own `Todo` schema and OpenAPI document, all backed by the same
+//! store. The unversioned `/api/todos` of old still answers as v1.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use salvo::oapi::extract::*;
+use salvo::prelude::*;
+use tokio::sync::Mutex;
+
+pub use self::auth::{Auth, Caller};
+use self::deprecation::Deprecation;
+
+pub mod auth;
+pub mod deprecation;
+pub mod v1;
+pub mod v2;
+
+static STORE: Lazy<Db> = Lazy::new(new_store);
+pub type Db = Mutex<Vec<v2::Todo>>;
+
+pub fn new_store() -> Db {
+    Mutex::new(Vec::new())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    /// Deprecated since 2026-10-01, removed after 2027-04-01.
+    V1,
+    V2,
+}
+
+impl Version {
+    pub const ALL: [Version; 2] = [Version::V1, Version::V2];
+    pub const CURRENT: Version = Version::V2;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Version::V1 => "v1",
+            Version::V2 => "v2",
+        }
+    }
+
+    /// Where this version's OpenAPI document is served.
+    pub fn openapi_path(self) -> &'static str {
+        match self {
+            Version::V1 => "/api-doc/v1/openapi.json",
+            Version::V2 => "/api-doc/v2/openapi.json",
+        }
+    }
+
+    /// The `todos` routes of this version, to be mounted under `/api/<name>`
+    /// (or `/api` for the unversioned v1 paths).
+    fn todos(self, auth: Auth) -> Router {
+        #[allow(deprecated)]
+        let (list, create, update) = match self {
+            Version::V1 => (
+                Router::new().get(v1::list_todos),
+                Router::new().post(v1::create_todo),
+                Router::new().patch(v1::update_todo),
+            ),
+            Version::V2 => (
+                Router::new().get(v2::list_todos),
+                Router::new().post(v2::create_todo),
+                Router::new().patch(v2::update_todo),
+            ),
+        };
+        // Reading is open to everyone, changing todos takes the credentials
+        // `auth` accepts.
+        let router = Router::with_path("todos").push(list).push(
+            Router::new()
+                .hoop(auth)
+                .push(create)
+                .push(Router::with_path("<id>").push(update).delete(delete_todo)),
+        );
+        match self {
+            Version::V1 => router.hoop(
+                Deprecation::since(UNIX_EPOCH + Duration::from_secs(1_790_812_800))
+                    .sunset(UNIX_EPOCH + Duration::from_secs(1_806_537_600))
+                    .successor("/api/v2/todos"),
+            ),
+            Version::V2 => router,
+        }
+    }
+
+    /// The API routes of this version alone, under `/api/<name>`.
+    pub fn router(self, auth: Auth) -> Router {
+        Router::with_path("api").push(Router::with_path(self.name()).push(self.todos(auth)))
+    }
+
+    /// The OpenAPI document of [`Version::router`].
+    pub fn openapi(self) -> OpenApi {
+        let version = match self {
+            Version::V1 => "1.0.0",
+            Version::V2 => "2.0.0",
+        };
+        let doc = OpenApi::new("todos api", version).merge_router(&self.router(Auth::new()));
+        auth::security_schemes(doc)
+    }
+}
+
+/// The API routes of every version, without the documentation.
+pub fn router(auth: Auth) -> Router {
+    let mut api = Router::with_path("api").push(Version::V1.todos(auth.clone()));
+    for version in Version::ALL {
+        api = api.push(Router::with_path(version.name()).push(version.todos(auth.clone())));
+    }
+    api
+}
+
+/// The whole service: the API plus the index page, one OpenAPI document per
+/// version and documentation UIs for the current one.
+pub fn app(auth: Auth) -> Router {
+    let current = Version::CURRENT.openapi_path();
+    let mut router = Router::new()
+        .get(index)
+        .push(router(auth))
+        .unshift(
+            SwaggerUi::new(current)
+                .title("Todos - SwaggerUI")
+                .into_router("/swagger-ui"),
+        )
+        .unshift(
+            Scalar::new(current)
+                .title("Todos - Scalar")
+                .into_router("/scalar"),
+        )
+        .unshift(
+            RapiDoc::new(current)
+                .title("Todos - RapiDoc")
+                .into_router("/rapidoc"),
+        )
+        .unshift(
+            ReDoc::new(current)
+                .title("Todos - ReDoc")
+                .into_router("/redoc"),
+        );
+    for version in Version::ALL {
+        router = router.unshift(version.openapi().into_router(version.openapi_path()));
+    }
+    router
+}
+
+#[handler]
+pub async fn index() -> Text<&'static str> {
+    Text::Html(INDEX_HTML)
+}
+
+/// Every todo, from `offset` on and at most `limit` of them.
+async fn list(offset: Option<usize>, limit: Option<usize>) -> Vec<v2::Todo> {
+    let todos = STORE.lock().await;
+    todos
+        .iter()
+        .skip(offset.unwrap_or(0))
+        .take(limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect()
+}
+
+async fn insert(new: v2::Todo) -> Result<(), StatusError> {
+    let mut vec = STORE.lock().await;
+
+    for todo in vec.iter() {
+        if todo.id == new.id {
+            tracing::debug!(id = ?new.id, "todo already exists");
+            return Err(StatusError::conflict().brief("todo already exists"));
+        }
+    }
+
+    vec.push(new);
+    Ok(())
+}
+
+/// Applies `change` to the todo with `id`.
+async fn modify(id: u64, change: impl FnOnce(&mut v2::Todo)) -> Result<(), StatusError> {
+    let mut vec = STORE.lock().await;
+
+    for todo in vec.iter_mut() {
+        if todo.id == id {
+            change(todo);
+            return Ok(());
+        }
+    }
+
+    tracing::debug!(id = ?id, "todo is not found");
+    Err(StatusError::not_found())
+}
+
+/// Delete todo. The same in every version.
+#[endpoint(
+    tags("todos"),
+    operation_id = "delete_todo",
+    status_codes(200, 401, 404),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn delete_todo(id: PathParam<u64>, depot: &mut Depot) -> Result<StatusCode, StatusError> {
+    tracing::debug!(id = ?id, caller = caller(depot), "delete todo");
+
+    let mut vec = STORE.lock().await;
+
+    let len = vec.len();
+    vec.retain(|todo| todo.id != *id);
+
+    let deleted = vec.len() != len;
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        tracing::debug!(id = ?id, "todo is not found");
+        Err(StatusError::not_found())
+    }
+}
+
+/// The subject the [`Auth`] hoop authenticated, for the logs.
+fn caller(depot: &Depot) -> &str {
+    depot
+        .obtain::<Caller>()
+        .map_or("", |caller| caller.subject.as_str())
+}
+
+static INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+    <head>
+        <title>Oapi todos</title>
+    </head>
+    <body>
+        <ul>
+        <li><a href="swagger-ui" target="_blank">swagger-ui</a></li>
+        <li><a href="scalar" target="_blank">scalar</a></li>
+        <li><a href="rapidoc" target="_blank">rapidoc</a></li>
+        <li><a href="redoc" target="_blank">redoc</a></li>
+        <li><a href="api-doc/v2/openapi.json" target="_blank">v2 openapi.json</a></li>
+        <li><a href="api-doc/v1/openapi.json" target="_blank">v1 openapi.json</a> (deprecated)</li>
+        </ul>
+    </body>
+</html>
+"#;
@@ -0,0 +1,103 @@
+//! `/api/v1`: the original API, deprecated in favour of [`v2`](crate::v2).
+//!
+//! Its todos have `text` where v2 has `title` and no priority. The handlers
+//! whose shape differs live here and are marked deprecated in the v1
+//! document; `delete_todo` is the same in both versions and shared. Every v1
+//! response carries the `Deprecation` and `Sunset` headers either way.
+
+#![allow(deprecated)]
+
+use salvo::oapi::extract::*;
+use salvo::oapi::ToSchema;
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{caller, v2};
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct Todo {
+    #[salvo(schema(example = 1))]
+    pub id: u64,
+    #[salvo(schema(example = "Buy coffee"))]
+    pub text: String,
+    pub completed: bool,
+}
+
+impl From<v2::Todo> for Todo {
+    fn from(todo: v2::Todo) -> Self {
+        Todo {
+            id: todo.id,
+            text: todo.title,
+            completed: todo.completed,
+        }
+    }
+}
+
+impl From<Todo> for v2::Todo {
+    fn from(todo: Todo) -> Self {
+        v2::Todo {
+            id: todo.id,
+            title: todo.text,
+            completed: todo.completed,
+            priority: v2::Priority::default(),
+        }
+    }
+}
+
+/// List todos.
+#[deprecated(note = "use v2::list_todos")]
+#[endpoint(
+tags("todos"),
+operation_id = "list_todos",
+parameters(
+("offset", description = "Offset is an optional query paramter."),
+)
+)]
+pub async fn list_todos(
+    offset: QueryParam<usize, false>,
+    limit: QueryParam<usize, false>,
+) -> Json<Vec<Todo>> {
+    let todos = crate::list(offset.into_inner(), limit.into_inner()).await;
+    Json(todos.into_iter().map(Todo::from).collect())
+}
+
+/// Create new todo.
+#[deprecated(note = "use v2::create_todo")]
+#[endpoint(
+    tags("todos"),
+    operation_id = "create_todo",
+    status_codes(201, 401, 409),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn create_todo(
+    req: JsonBody<Todo>,
+    depot: &mut Depot,
+) -> Result<StatusCode, StatusError> {
+    tracing::debug!(todo = ?req, caller = caller(depot), "create todo");
+    crate::insert(req.into_inner().into()).await?;
+    Ok(StatusCode::CREATED)
+}
+
+/// Update existing todo. Its priority, which v1 does not know about, is kept.
+#[deprecated(note = "use v2::update_todo")]
+#[endpoint(
+    tags("todos"),
+    operation_id = "update_todo",
+    status_codes(200, 401, 404),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn update_todo(
+    id: PathParam<u64>,
+    updated: JsonBody<Todo>,
+    depot: &mut Depot,
+) -> Result<StatusCode, StatusError> {
+    tracing::debug!(todo = ?updated, id = ?id, caller = caller(depot), "update todo");
+    let updated = updated.into_inner();
+    crate::modify(*id, |todo| {
+        todo.id = updated.id;
+        todo.title = updated.text;
+        todo.completed = updated.completed;
+    })
+    .await?;
+    Ok(StatusCode::OK)
+}
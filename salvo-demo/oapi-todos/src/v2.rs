@@ -0,0 +1,76 @@
+//! `/api/v2`: the current API. Its [`Todo`] is also what the store keeps.
+
+use salvo::oapi::extract::*;
+use salvo::oapi::ToSchema;
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::caller;
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct Todo {
+    #[salvo(schema(example = 1))]
+    pub id: u64,
+    #[salvo(schema(example = "Buy coffee"))]
+    pub title: String,
+    pub completed: bool,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// List todos.
+#[endpoint(
+tags("todos"),
+operation_id = "list_todos",
+parameters(
+("offset", description = "Offset is an optional query paramter."),
+)
+)]
+pub async fn list_todos(
+    offset: QueryParam<usize, false>,
+    limit: QueryParam<usize, false>,
+) -> Json<Vec<Todo>> {
+    Json(crate::list(offset.into_inner(), limit.into_inner()).await)
+}
+
+/// Create new todo.
+#[endpoint(
+    tags("todos"),
+    operation_id = "create_todo",
+    status_codes(201, 401, 409),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn create_todo(
+    req: JsonBody<Todo>,
+    depot: &mut Depot,
+) -> Result<StatusCode, StatusError> {
+    tracing::debug!(todo = ?req, caller = caller(depot), "create todo");
+    crate::insert(req.into_inner()).await?;
+    Ok(StatusCode::CREATED)
+}
+
+/// Update existing todo.
+#[endpoint(
+    tags("todos"),
+    operation_id = "update_todo",
+    status_codes(200, 401, 404),
+    security(("api_key" = []), ("bearer" = []))
+)]
+pub async fn update_todo(
+    id: PathParam<u64>,
+    updated: JsonBody<Todo>,
+    depot: &mut Depot,
+) -> Result<StatusCode, StatusError> {
+    tracing::debug!(todo = ?updated, id = ?id, caller = caller(depot), "update todo");
+    crate::modify(*id, |todo| *todo = updated.into_inner()).await?;
+    Ok(StatusCode::OK)
+}
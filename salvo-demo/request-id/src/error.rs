@@ -0,0 +1,71 @@
+//! Error bodies that name the request they belong to, so a user's report
+//! can be matched with the logs.
+
+use salvo::prelude::*;
+use serde::Serialize;
+
+use crate::request_id::HEADER;
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    request_id: Option<String>,
+}
+
+impl ErrorBody {
+    fn new(error: String, req: &Request) -> ErrorBody {
+        ErrorBody {
+            error,
+            request_id: req.header(HEADER),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    /// A call to another service failed.
+    Upstream(reqwest_middleware::Error),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Upstream(e) => write!(f, "upstream request failed: {e}"),
+        }
+    }
+}
+
+impl From<reqwest_middleware::Error> for AppError {
+    fn from(e: reqwest_middleware::Error) -> Self {
+        AppError::Upstream(e)
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        AppError::Upstream(e.into())
+    }
+}
+
+#[async_trait]
+impl Writer for AppError {
+    async fn write(self, req: &mut Request, _depot: &mut Depot, res: &mut Response) {
+        // Still inside the request span, so this line carries the id too.
+        tracing::error!(error = %self, "request failed");
+        res.status_code(StatusCode::BAD_GATEWAY);
+        res.render(Json(ErrorBody::new(self.to_string(), req)));
+    }
+}
+
+/// Catcher hoop giving the errors salvo raises itself (404, 405, ...) the
+/// same JSON body. Catchers run after the service hoops have finished, so this
+/// is outside the request span, but the id is already in the request header.
+#[handler]
+pub async fn catch(req: &mut Request, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let status = res.status_code.unwrap_or(StatusCode::NOT_FOUND);
+    if (status.is_client_error() || status.is_server_error()) && res.body.is_none() {
+        let error = status.canonical_reason().unwrap_or("error").to_owned();
+        res.render(Json(ErrorBody::new(error, req)));
+        ctrl.skip_rest();
+    }
+}
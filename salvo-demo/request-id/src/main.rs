@@ -1,8 +1,52 @@
+use std::sync::LazyLock;
+
+use reqwest_middleware::ClientWithMiddleware;
+use salvo::catcher::Catcher;
 use salvo::prelude::*;
 
+use self::error::AppError;
+use self::request_id::{check_client_id, Propagate, HEADER};
+
+mod error;
+mod outbound;
+mod request_id;
+
+static CLIENT: LazyLock<ClientWithMiddleware> = LazyLock::new(outbound::client);
+
 #[handler]
 async fn hello(req: &mut Request) -> String {
-    format!("Request id: {:?}", req.header::<String>("x-request-id"))
+    tracing::info!("saying hello");
+    format!("Request id: {:?}", req.header::<String>(HEADER))
+}
+
+/// Calls `hello` on this same server, which logs and answers with the same
+/// request id.
+#[handler]
+async fn relay() -> Result<String, AppError> {
+    let body = CLIENT
+        .get("http://127.0.0.1:5800/")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(format!("Upstream said: {body}"))
+}
+
+fn router() -> Router {
+    Router::new()
+        .get(hello)
+        .push(Router::with_path("relay").get(relay))
+}
+
+/// Serves `router` with every request tagged by an id.
+fn service(router: Router) -> Service {
+    // On the service, so unmatched routes are covered too, see `request_id`.
+    Service::new(router)
+        .hoop(check_client_id)
+        .hoop(RequestId::new())
+        .hoop(Propagate)
+        .catcher(Catcher::default().hoop(error::catch))
 }
 
 #[tokio::main]
@@ -10,9 +54,136 @@ async fn main() {
     tracing_subscriber::fmt().init();
 
     let acceptor = TcpListener::new("0.0.0.0:5800").bind().await;
-    let router = Router::new().hoop(RequestId::new()).get(hello);
-    Server::new(acceptor).serve(router).await;
+    Server::new(acceptor).serve(service(router())).await;
 }
 
 // curl "http://127.0.0.1:5800/"
 // curl "http://127.0.0.1:5800/" -H "x-request-id: 123456789"
+// curl "http://127.0.0.1:5800/relay" -H "x-request-id: 123456789"
+// curl "http://127.0.0.1:5800/missing" -H "x-request-id: 123456789"
+// curl "http://127.0.0.1:5800/" -H "x-request-id: not allowed"
+
+#[cfg(test)]
+mod tests {
+    use salvo::prelude::*;
+    use salvo::test::{ResponseExt, TestClient};
+    use serde::Deserialize;
+
+    use super::{router, service, AppError, CLIENT, HEADER};
+    use crate::request_id::MAX_LEN;
+
+    /// Where [`echo`] listens for the outbound tests.
+    const ECHO: &str = "127.0.0.1:5811";
+
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        request_id: Option<String>,
+    }
+
+    /// Answers with the request id it was sent, if any.
+    #[handler]
+    async fn echo(req: &mut Request) -> String {
+        req.header::<String>(HEADER).unwrap_or_default()
+    }
+
+    #[handler]
+    async fn call_echo() -> Result<String, AppError> {
+        Ok(CLIENT
+            .get(format!("http://{ECHO}/"))
+            .send()
+            .await?
+            .text()
+            .await?)
+    }
+
+    #[handler]
+    async fn call_nobody() -> Result<String, AppError> {
+        // Nothing listens on port 1, so the connection is refused.
+        Ok(CLIENT
+            .get("http://127.0.0.1:1/")
+            .send()
+            .await?
+            .text()
+            .await?)
+    }
+
+    async fn get(path: &str, id: Option<&str>, router: Router) -> Response {
+        let mut req = TestClient::get(format!("http://127.0.0.1:5800{path}"));
+        if let Some(id) = id {
+            req = req.add_header(HEADER, id, true);
+        }
+        req.send(service(router)).await
+    }
+
+    fn response_id(res: &Response) -> String {
+        let id = res
+            .headers()
+            .get(HEADER)
+            .expect("response has no request id");
+        id.to_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn echoes_the_request_id() {
+        let mut res = get("/", Some("abc-123"), router()).await;
+        assert_eq!(response_id(&res), "abc-123");
+        assert_eq!(
+            res.take_string().await.unwrap(),
+            r#"Request id: Some("abc-123")"#
+        );
+
+        // Without one from the client, the generated id is what the handler saw.
+        let mut res = get("/", None, router()).await;
+        let id = response_id(&res);
+        assert!(!id.is_empty());
+        assert_eq!(
+            res.take_string().await.unwrap(),
+            format!("Request id: Some({id:?})")
+        );
+    }
+
+    #[tokio::test]
+    async fn replaces_invalid_client_ids() {
+        let longest = "a".repeat(MAX_LEN);
+        assert_eq!(
+            response_id(&get("/", Some(&longest), router()).await),
+            longest
+        );
+
+        let too_long = "a".repeat(MAX_LEN + 1);
+        for bad in [too_long.as_str(), "not allowed", "tab\there"] {
+            let id = response_id(&get("/", Some(bad), router()).await);
+            assert!(!id.is_empty());
+            assert_ne!(id, bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn error_bodies_name_the_request() {
+        let mut res = get("/missing", Some("lost-1"), router()).await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::NOT_FOUND);
+        assert_eq!(response_id(&res), "lost-1");
+        let body = res.take_json::<ErrorBody>().await.unwrap();
+        assert_eq!(body.request_id.as_deref(), Some("lost-1"));
+
+        let router = Router::with_path("nobody").get(call_nobody);
+        let mut res = get("/nobody", Some("down-1"), router).await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::BAD_GATEWAY);
+        let body = res.take_json::<ErrorBody>().await.unwrap();
+        assert_eq!(body.request_id.as_deref(), Some("down-1"));
+    }
+
+    #[tokio::test]
+    async fn outbound_calls_carry_the_request_id() {
+        let acceptor = TcpListener::new(ECHO).bind().await;
+        tokio::spawn(Server::new(acceptor).serve(Router::new().get(echo)));
+
+        let router = Router::with_path("echo").get(call_echo);
+        let mut res = get("/echo", Some("out-1"), router).await;
+        assert_eq!(res.take_string().await.unwrap(), "out-1");
+
+        // Outside a request there is no id to pass on.
+        let body = CLIENT.get(format!("http://{ECHO}/")).send().await.unwrap();
+        assert_eq!(body.text().await.unwrap(), "");
+    }
+}
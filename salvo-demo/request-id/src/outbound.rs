@@ -0,0 +1,36 @@
+//! Outbound HTTP calls that carry the id of the request being handled.
+
+use reqwest::header::HeaderValue;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Middleware, Next};
+use salvo::async_trait;
+
+use crate::request_id::{self, HEADER};
+
+/// Copies [`request_id::current`] into the `x-request-id` header of every
+/// request that does not already set one.
+pub struct PropagateRequestId;
+
+#[async_trait]
+impl Middleware for PropagateRequestId {
+    async fn handle(
+        &self,
+        mut req: reqwest::Request,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        if let Some(id) = request_id::current() {
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                req.headers_mut().entry(HEADER).or_insert(value);
+            }
+        }
+        tracing::debug!(method = %req.method(), url = %req.url(), "outbound request");
+        next.run(req, extensions).await
+    }
+}
+
+/// A client for handlers to call other services with.
+pub fn client() -> ClientWithMiddleware {
+    ClientBuilder::new(reqwest::Client::new())
+        .with(PropagateRequestId)
+        .build()
+}
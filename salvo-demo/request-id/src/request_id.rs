@@ -0,0 +1,82 @@
+//! Carries the request id through everything done for a request.
+//!
+//! [`Propagate`] runs the rest of the chain inside a `request` span holding
+//! the id, so every log line written while handling the request has it, and
+//! keeps it in a task-local for [`current`]. It relies on salvo's `RequestId`
+//! hoop having run first to fill in the header when the client sent none.
+//!
+//! All of these go on the `Service` rather than the `Router`, so requests that
+//! match no route, which only reach the catcher, get an id too. A client's id
+//! is only kept if it passes [`check_client_id`]; anything else ends up in
+//! every log line, so it is replaced with a generated one.
+
+use salvo::http::header::HeaderValue;
+use salvo::prelude::*;
+use tracing::Instrument;
+
+pub const HEADER: &str = "x-request-id";
+
+/// Longest id accepted from a client. UUIDs and ULIDs fit easily.
+pub const MAX_LEN: usize = 64;
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+/// The id of the request the current task is handling, if any. Tasks spawned
+/// from a handler do not inherit it; pass it along explicitly.
+pub fn current() -> Option<String> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Drops an `x-request-id` that is too long or has characters other than
+/// ASCII letters, digits and `-_.:`, so `RequestId` generates a new one.
+/// Must run before `RequestId`.
+#[handler]
+pub async fn check_client_id(req: &mut Request) {
+    let Some(id) = req.headers().get(HEADER) else {
+        return;
+    };
+    let valid = id.len() <= MAX_LEN
+        && id
+            .as_bytes()
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+    if !valid {
+        tracing::debug!(len = id.len(), "replacing invalid client request id");
+        req.headers_mut().remove(HEADER);
+    }
+}
+
+pub struct Propagate;
+
+#[async_trait]
+impl Handler for Propagate {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let id = req.header::<String>(HEADER).unwrap_or_default();
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            res.headers_mut().insert(HEADER, value);
+        }
+        let span = tracing::info_span!(
+            "request",
+            id = %id,
+            method = %req.method(),
+            path = %req.uri().path(),
+        );
+        CURRENT
+            .scope(id, async {
+                tracing::info!("started");
+                ctrl.call_next(req, depot, res).await;
+                let status = res.status_code.unwrap_or(StatusCode::OK);
+                tracing::info!(status = status.as_u16(), "finished");
+            })
+            .instrument(span)
+            .await;
+    }
+}
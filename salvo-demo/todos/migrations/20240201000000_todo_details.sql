@@ -0,0 +1,14 @@
+-- Dates and timestamps are stored as ISO 8601 text so they sort correctly,
+-- tags as a JSON array and priority as 0 (low), 1 (normal) or 2 (high).
+ALTER TABLE todos ADD COLUMN due TEXT;
+ALTER TABLE todos ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE todos ADD COLUMN priority INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE todos ADD COLUMN created_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00.000Z';
+ALTER TABLE todos ADD COLUMN updated_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00.000Z';
+
+-- Existing todos are treated as created now.
+UPDATE todos SET
+    created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+    updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+
+CREATE INDEX IF NOT EXISTS todos_due ON todos (due);
@@ -0,0 +1,85 @@
+use std::fmt;
+
+use salvo::http::{ParseError, StatusCode};
+use salvo::prelude::*;
+use salvo::writing::Scribe;
+use serde::Serialize;
+
+use crate::store::StoreError;
+
+/// Everything a handler can fail with, rendered as a JSON body like
+/// `{"code": "not_found", "message": "todo 7 not found"}`.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request could not be parsed or failed validation.
+    BadRequest(String),
+    NotFound(u64),
+    Conflict(String),
+    /// Details are logged, never sent to the client.
+    Internal,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Internal => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(msg) => write!(f, "{msg}"),
+            ApiError::NotFound(id) => write!(f, "todo {id} not found"),
+            ApiError::Conflict(msg) => write!(f, "{msg}"),
+            ApiError::Internal => write!(f, "internal server error"),
+        }
+    }
+}
+
+impl Scribe for ApiError {
+    fn render(self, res: &mut Response) {
+        res.status_code(self.status());
+        res.render(Json(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        }));
+    }
+}
+
+impl From<ParseError> for ApiError {
+    fn from(e: ParseError) -> Self {
+        ApiError::BadRequest(e.to_string())
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Conflict(_) | StoreError::Modified(_) => ApiError::Conflict(e.to_string()),
+            e => {
+                tracing::error!(error = %e, "todo store failed");
+                ApiError::Internal
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! The todo change feed behind `GET /todos/events`.
+//!
+//! Every change is published to an [`EventLog`] with an increasing id. The
+//! last few are kept so a client that reconnects with the id of the last
+//! event it saw gets whatever it missed before the live events. When that is
+//! not possible, because the events were already dropped or the client fell
+//! too far behind, it gets a [`Change::Reset`] instead and should reload the
+//! list.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::{stream, Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::models::Todo;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub id: u64,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Change {
+    Created {
+        todo: Todo,
+    },
+    Updated {
+        todo: Todo,
+    },
+    Deleted {
+        todo_id: u64,
+    },
+    /// Events were missed: reload `GET /todos`, then carry on as if this
+    /// event's id was the last one seen.
+    Reset,
+}
+
+impl Event {
+    /// The `type` field, also used as the SSE event name.
+    pub fn kind(&self) -> &'static str {
+        match self.change {
+            Change::Created { .. } => "created",
+            Change::Updated { .. } => "updated",
+            Change::Deleted { .. } => "deleted",
+            Change::Reset => "reset",
+        }
+    }
+}
+
+/// Recent changes plus a channel of the live ones. Cloning is cheap and
+/// shares the log.
+#[derive(Clone)]
+pub struct EventLog {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    sender: broadcast::Sender<Event>,
+    capacity: usize,
+}
+
+struct State {
+    /// The id of the most recent event. Seeded from the clock so ids keep
+    /// growing across restarts and ids from a previous run read as stale.
+    last_id: u64,
+    recent: VecDeque<Event>,
+}
+
+/// What a client gets on connecting: any events it missed, then live ones.
+pub struct Subscription {
+    backlog: Vec<Event>,
+    receiver: broadcast::Receiver<Event>,
+    log: EventLog,
+}
+
+impl EventLog {
+    /// Keeps the last `capacity` events for resuming clients, which is also
+    /// how far a connected client may fall behind before it is reset.
+    pub fn new(capacity: usize) -> EventLog {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        EventLog {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    last_id: now.as_micros() as u64,
+                    recent: VecDeque::with_capacity(capacity),
+                }),
+                sender: broadcast::channel(capacity).0,
+                capacity,
+            }),
+        }
+    }
+
+    pub fn publish(&self, change: Change) -> Event {
+        let mut state = self.inner.state.lock().unwrap();
+        state.last_id += 1;
+        let event = Event {
+            id: state.last_id,
+            change,
+        };
+        if state.recent.len() == self.inner.capacity {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(event.clone());
+        // Sending only fails when nobody is listening.
+        let _ = self.inner.sender.send(event.clone());
+        event
+    }
+
+    /// Starts following the log. With `since`, the events after that id are
+    /// replayed first; without it only new events are delivered.
+    pub fn subscribe(&self, since: Option<u64>) -> Subscription {
+        // Holding the lock while subscribing means no event is both replayed
+        // and received live, and none falls in between.
+        let state = self.inner.state.lock().unwrap();
+        let receiver = self.inner.sender.subscribe();
+        let backlog = match since {
+            None => Vec::new(),
+            Some(since) if since >= state.last_id => Vec::new(),
+            Some(since) => {
+                let oldest = state.recent.front().map_or(state.last_id + 1, |e| e.id);
+                if since + 1 < oldest {
+                    vec![state.reset()]
+                } else {
+                    state
+                        .recent
+                        .iter()
+                        .filter(|event| event.id > since)
+                        .cloned()
+                        .collect()
+                }
+            }
+        };
+        Subscription {
+            backlog,
+            receiver,
+            log: self.clone(),
+        }
+    }
+
+    fn reset(&self) -> Event {
+        self.inner.state.lock().unwrap().reset()
+    }
+}
+
+impl State {
+    fn reset(&self) -> Event {
+        Event {
+            id: self.last_id,
+            change: Change::Reset,
+        }
+    }
+}
+
+impl Subscription {
+    /// The missed events followed by live ones. If the subscriber falls too
+    /// far behind, the stream ends with a reset event.
+    pub fn into_stream(self) -> impl Stream<Item = Event> + Send + 'static {
+        let Subscription {
+            backlog,
+            receiver,
+            log,
+        } = self;
+        let live = stream::unfold(Some((receiver, log)), |next| async move {
+            let (mut receiver, log) = next?;
+            match receiver.recv().await {
+                Ok(event) => Some((event, Some((receiver, log)))),
+                Err(RecvError::Lagged(_)) => Some((log.reset(), None)),
+                Err(RecvError::Closed) => None,
+            }
+        });
+        stream::iter(backlog).chain(live)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deleted(id: u64) -> Change {
+        Change::Deleted { todo_id: id }
+    }
+
+    async fn take(sub: Subscription, n: usize) -> Vec<Event> {
+        sub.into_stream().take(n).collect().await
+    }
+
+    #[tokio::test]
+    async fn resumes_after_the_last_seen_event() {
+        let log = EventLog::new(8);
+        let first = log.publish(deleted(1));
+        let second = log.publish(deleted(2));
+
+        let sub = log.subscribe(Some(first.id));
+        let third = log.publish(deleted(3));
+        assert_eq!(take(sub, 2).await, [second, third.clone()]);
+
+        let sub = log.subscribe(None);
+        let fourth = log.publish(deleted(4));
+        assert_eq!(take(sub, 1).await, [fourth]);
+    }
+
+    #[tokio::test]
+    async fn resets_when_events_were_dropped() {
+        let log = EventLog::new(2);
+        let stale = log.publish(deleted(1));
+        log.publish(deleted(2));
+        let last = log.publish(deleted(3));
+
+        let events = take(log.subscribe(Some(stale.id - 1)), 1).await;
+        assert_eq!(events[0].change, Change::Reset);
+        assert_eq!(events[0].id, last.id);
+
+        // A subscriber that falls behind is reset and then disconnected.
+        let sub = log.subscribe(None);
+        for id in 4..10 {
+            log.publish(deleted(id));
+        }
+        let events: Vec<Event> = sub.into_stream().collect().await;
+        assert_eq!(events.last().unwrap().change, Change::Reset);
+    }
+}
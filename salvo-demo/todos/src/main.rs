@@ -1,11 +1,75 @@
-use once_cell::sync::Lazy;
+use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use salvo::affix;
+use salvo::http::header::{IF_UNMODIFIED_SINCE, UPGRADE};
 use salvo::prelude::*;
 use salvo::size_limiter;
+use salvo::sse::{SseEvent, SseKeepAlive};
+use salvo::websocket::{Message, WebSocket, WebSocketUpgrade};
+use tokio::sync::Mutex;
 
+use self::error::ApiError;
+use self::events::{Change, Event, EventLog};
 use self::models::*;
+use self::store::{Config, StoreError, TodoStore};
 
-static STORE: Lazy<Db> = Lazy::new(new_store);
+mod error;
+mod events;
+mod models;
+mod store;
+
+/// What every handler works with: the store and the feed of the changes made
+/// to it, see [`store`](crate::store) and [`events`](crate::events).
+#[derive(Clone)]
+struct Todos {
+    store: Arc<dyn TodoStore>,
+    events: EventLog,
+    /// Held from a change until its event is published, so the feed lists
+    /// changes in the order the store made them.
+    writing: Arc<Mutex<()>>,
+}
+
+impl Todos {
+    fn new(store: Arc<dyn TodoStore>) -> Todos {
+        Todos {
+            store,
+            events: EventLog::new(1024),
+            writing: Arc::default(),
+        }
+    }
+
+    async fn create(&self, new: NewTodo) -> Result<Todo, StoreError> {
+        let _writing = self.writing.lock().await;
+        let todo = self.store.create(new).await?;
+        self.events.publish(Change::Created { todo: todo.clone() });
+        Ok(todo)
+    }
+
+    async fn update(
+        &self,
+        id: u64,
+        changes: TodoPatch,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> Result<Option<Todo>, StoreError> {
+        let _writing = self.writing.lock().await;
+        let todo = self.store.update(id, changes, unmodified_since).await?;
+        if let Some(todo) = &todo {
+            self.events.publish(Change::Updated { todo: todo.clone() });
+        }
+        Ok(todo)
+    }
+
+    async fn delete(&self, id: u64) -> Result<bool, StoreError> {
+        let _writing = self.writing.lock().await;
+        let deleted = self.store.delete(id).await?;
+        if deleted {
+            self.events.publish(Change::Deleted { todo_id: id });
+        }
+        Ok(deleted)
+    }
+}
 
 #[tokio::main]
 async fn main() {
@@ -14,148 +78,501 @@ async fn main() {
 }
 
 pub(crate) async fn start_server() {
+    // Open (and migrate) the store before accepting any requests.
+    let config = Config::from_env().unwrap_or_else(|e| panic!("{e}"));
+    tracing::info!(?config, "opening todo store");
+    let store = match config.open().await {
+        Ok(store) => store,
+        Err(e) => panic!("failed to open todo store: {e}"),
+    };
     let acceptor = TcpListener::new("0.0.0.0:5800").bind().await;
-    Server::new(acceptor).serve(route()).await;
+    Server::new(acceptor)
+        .serve(route(Todos::new(store.into())))
+        .await;
 }
 
-fn route() -> Router {
+fn route(todos: Todos) -> Router {
     Router::with_path("todos")
         .hoop(size_limiter::max_size(1024 * 16))
+        .hoop(affix::inject(todos))
         .get(list_todos)
         .post(create_todo)
+        .push(Router::with_path("events").get(todo_events))
         .push(
             Router::with_path("<id>")
                 .put(update_todo)
+                .patch(patch_todo)
                 .delete(delete_todo),
         )
 }
 
+fn todos(depot: &Depot) -> &Todos {
+    depot.obtain::<Todos>().expect("route() injects the todos")
+}
+
+/// `GET /todos?offset=10&limit=10&tag=work&due_before=2024-02-01&sort=-due`
+///
+/// See [`ListOptions`] for what each parameter does.
 #[handler]
-pub async fn list_todos(req: &mut Request, res: &mut Response) {
-    let opts = req.parse_body::<ListOptions>().await.unwrap_or_default();
-    let todos = STORE.lock().await;
-    let todos: Vec<Todo> = todos
-        .clone()
-        .into_iter()
-        .skip(opts.offset.unwrap_or(0))
-        .take(opts.limit.unwrap_or(usize::MAX))
-        .collect();
-    res.render(Json(todos));
+pub async fn list_todos(req: &mut Request, depot: &mut Depot) -> Result<Json<Vec<Todo>>, ApiError> {
+    let mut opts = req.parse_queries::<ListOptions>()?;
+    opts.validate()?;
+    Ok(Json(todos(depot).store.list(&opts).await?))
 }
 
 #[handler]
-pub async fn create_todo(req: &mut Request, res: &mut Response) {
-    let new_todo = req.parse_body::<Todo>().await.unwrap();
+pub async fn create_todo(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<Json<Todo>, ApiError> {
+    let mut new_todo = req.parse_json::<NewTodo>().await?;
+    new_todo.validate()?;
     tracing::debug!(todo = ?new_todo, "create todo");
 
-    let mut vec = STORE.lock().await;
-
-    for todo in vec.iter() {
-        if todo.id == new_todo.id {
-            tracing::debug!(id = ?new_todo.id, "todo already exists");
-            res.status_code(StatusCode::BAD_REQUEST);
-            return;
-        }
-    }
-
-    vec.push(new_todo);
+    let todo = todos(depot).create(new_todo).await?;
     res.status_code(StatusCode::CREATED);
+    Ok(Json(todo))
 }
 
+/// Replaces every field; fields missing from the body get their defaults.
+///
+/// Like `PATCH`, fails with 409 if the request has an `If-Unmodified-Since`
+/// header and the todo changed after that time.
 #[handler]
-pub async fn update_todo(req: &mut Request, res: &mut Response) {
-    let id = req.param::<u64>("id").unwrap();
-    let updated_todo = req.parse_body::<Todo>().await.unwrap();
+pub async fn update_todo(req: &mut Request, depot: &mut Depot) -> Result<Json<Todo>, ApiError> {
+    let id = todo_id(req)?;
+    let since = unmodified_since(req)?;
+    let mut updated_todo = req.parse_json::<NewTodo>().await?;
+    updated_todo.validate()?;
     tracing::debug!(todo = ?updated_todo, id = ?id, "update todo");
-    let mut vec = STORE.lock().await;
 
-    for todo in vec.iter_mut() {
-        if todo.id == id {
-            *todo = updated_todo;
-            res.status_code(StatusCode::OK);
-            return;
+    apply(todos(depot), id, updated_todo.into(), since).await
+}
+
+/// Changes only the fields present in the body.
+#[handler]
+pub async fn patch_todo(req: &mut Request, depot: &mut Depot) -> Result<Json<Todo>, ApiError> {
+    let id = todo_id(req)?;
+    let since = unmodified_since(req)?;
+    let mut changes = req.parse_json::<TodoPatch>().await?;
+    changes.validate()?;
+    tracing::debug!(changes = ?changes, id = ?id, "patch todo");
+
+    apply(todos(depot), id, changes, since).await
+}
+
+async fn apply(
+    todos: &Todos,
+    id: u64,
+    changes: TodoPatch,
+    unmodified_since: Option<DateTime<Utc>>,
+) -> Result<Json<Todo>, ApiError> {
+    match todos.update(id, changes, unmodified_since).await? {
+        Some(todo) => Ok(Json(todo)),
+        None => {
+            tracing::debug!(id = ?id, "todo is not found");
+            Err(ApiError::NotFound(id))
         }
     }
-
-    tracing::debug!(id = ?id, "todo is not found");
-    res.status_code(StatusCode::NOT_FOUND);
 }
 
 #[handler]
-pub async fn delete_todo(req: &mut Request, res: &mut Response) {
-    let id = req.param::<u64>("id").unwrap();
+pub async fn delete_todo(req: &mut Request, depot: &mut Depot) -> Result<StatusCode, ApiError> {
+    let id = todo_id(req)?;
     tracing::debug!(id = ?id, "delete todo");
 
-    let mut vec = STORE.lock().await;
-
-    let len = vec.len();
-    vec.retain(|todo| todo.id != id);
-
-    let deleted = vec.len() != len;
-    if deleted {
-        res.status_code(StatusCode::NO_CONTENT);
+    if todos(depot).delete(id).await? {
+        Ok(StatusCode::NO_CONTENT)
     } else {
         tracing::debug!(id = ?id, "todo is not found");
-        res.status_code(StatusCode::NOT_FOUND);
+        Err(ApiError::NotFound(id))
     }
 }
 
-mod models {
-    use serde::{Deserialize, Serialize};
-    use tokio::sync::Mutex;
+/// `GET /todos/events`: the change feed as server-sent events, or as a
+/// WebSocket of JSON messages when the request asks for an upgrade.
+///
+/// Clients resume with the id of the last event they saw, which `EventSource`
+/// sends by itself as `Last-Event-ID` when it reconnects; WebSocket clients
+/// pass it as `?since=`.
+#[handler]
+pub async fn todo_events(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), ApiError> {
+    let events = todos(depot)
+        .events
+        .subscribe(resume_after(req)?)
+        .into_stream();
+    if is_websocket(req) {
+        WebSocketUpgrade::new()
+            .upgrade(req, res, |ws| send_events(ws, events))
+            .await
+            .map_err(|e| ApiError::BadRequest(e.brief))
+    } else {
+        let events = events.map(|event| {
+            SseEvent::default()
+                .name(event.kind())
+                .id(event.id.to_string())
+                .json(&event)
+        });
+        SseKeepAlive::new(events).stream(res);
+        Ok(())
+    }
+}
 
-    pub type Db = Mutex<Vec<Todo>>;
+fn resume_after(req: &Request) -> Result<Option<u64>, ApiError> {
+    let id = match req.headers().get("last-event-id") {
+        Some(id) => Some(id.to_str().unwrap_or_default().to_owned()),
+        None => req.query::<String>("since"),
+    };
+    id.map(|id| {
+        id.trim()
+            .parse()
+            .map_err(|_| ApiError::BadRequest(format!("invalid event id {id:?}")))
+    })
+    .transpose()
+}
 
-    pub fn new_store() -> Db {
-        Mutex::new(Vec::new())
-    }
+fn is_websocket(req: &Request) -> bool {
+    req.headers()
+        .get(UPGRADE)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"websocket"))
+}
 
-    #[derive(Serialize, Deserialize, Clone, Debug)]
-    pub struct Todo {
-        pub id: u64,
-        pub text: String,
-        pub completed: bool,
+async fn send_events(mut ws: WebSocket, events: impl Stream<Item = Event> + Send) {
+    let mut events = std::pin::pin!(events);
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else { break };
+                let text = serde_json::to_string(&event).expect("events always serialize");
+                if ws.send(Message::text(text)).await.is_err() {
+                    return;
+                }
+            }
+            // Clients have nothing to say; this only notices them leaving.
+            msg = ws.recv() => match msg {
+                Some(Ok(msg)) if !msg.is_close() => {}
+                _ => return,
+            },
+        }
     }
+    let _ = ws.close().await;
+}
 
-    #[derive(Deserialize, Debug, Default)]
-    pub struct ListOptions {
-        pub offset: Option<usize>,
-        pub limit: Option<usize>,
-    }
+/// The `If-Unmodified-Since` header, an HTTP date such as
+/// `Tue, 06 Feb 2024 10:15:00 GMT`.
+fn unmodified_since(req: &Request) -> Result<Option<DateTime<Utc>>, ApiError> {
+    let Some(value) = req.headers().get(IF_UNMODIFIED_SINCE) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .map(|date| Some(date.with_timezone(&Utc)))
+        .ok_or_else(|| ApiError::BadRequest("invalid If-Unmodified-Since date".into()))
+}
+
+fn todo_id(req: &Request) -> Result<u64, ApiError> {
+    req.param::<u64>("id")
+        .ok_or_else(|| ApiError::BadRequest("todo id must be a positive integer".into()))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
     use salvo::http::StatusCode;
-    use salvo::test::TestClient;
+    use salvo::test::{ResponseExt, TestClient};
+    use serde_json::{json, Value};
 
-    use super::models::Todo;
+    use futures_util::future::join_all;
+    use futures_util::StreamExt;
 
-    #[tokio::test]
-    async fn test_todo_create() {
-        tokio::task::spawn(async {
-            super::start_server().await;
-        });
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        let res = TestClient::post("http://0.0.0.0:5800/todos")
+    use super::events::Change;
+    use super::models::{NewTodo, Todo};
+    use super::store::{JsonStore, SqliteStore};
+    use super::Todos;
+
+    /// Runs every test once per store, as `sqlite::<test>` and `json::<test>`.
+    macro_rules! store_tests {
+        ($($test:ident),* $(,)?) => {
+            mod sqlite {
+                $(
+                    #[tokio::test]
+                    async fn $test() {
+                        super::$test(super::sqlite_todos().await).await;
+                    }
+                )*
+            }
+
+            mod json {
+                $(
+                    #[tokio::test]
+                    async fn $test() {
+                        super::$test(super::json_todos().await).await;
+                    }
+                )*
+            }
+        };
+    }
+
+    store_tests!(
+        test_todo_create,
+        test_todo_errors,
+        test_todo_patch,
+        test_todo_conflict,
+        test_todo_filters,
+        test_todo_events,
+        test_todo_event_order,
+    );
+
+    /// A fresh in-memory database; sqlx gives every `:memory:` URL its own.
+    async fn sqlite_todos() -> Todos {
+        let options = "sqlite::memory:".parse().unwrap();
+        Todos::new(Arc::new(SqliteStore::connect(options).await.unwrap()))
+    }
+
+    /// A fresh file in the temp directory, so tests never touch real data.
+    async fn json_todos() -> Todos {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("todos-test-{}-{n}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Todos::new(Arc::new(JsonStore::open(&path).await.unwrap()))
+    }
+
+    async fn test_todo_create(todos: Todos) {
+        let mut res = TestClient::post("http://0.0.0.0:5800/todos")
             .json(&test_todo())
-            .send(super::route())
+            .send(super::route(todos.clone()))
             .await;
 
         assert_eq!(res.status_code.unwrap(), StatusCode::CREATED);
-        let res = TestClient::post("http://0.0.0.0:5800/todos")
+        let first = res.take_json::<Todo>().await.unwrap();
+        assert_eq!(first.text, "test todo");
+
+        // The server assigns ids, so creating the same todo twice gives two
+        // todos.
+        let mut res = TestClient::post("http://0.0.0.0:5800/todos")
             .json(&test_todo())
-            .send(super::route())
+            .send(super::route(todos.clone()))
             .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::CREATED);
+        let second = res.take_json::<Todo>().await.unwrap();
+        assert_ne!(first.id, second.id);
+    }
+
+    async fn test_todo_errors(todos: Todos) {
+        let url = "http://0.0.0.0:5800/todos";
+
+        for body in [
+            json!({ "text": "  " }),
+            json!({ "id": 1, "text": "with id" }),
+        ] {
+            let mut res = TestClient::post(url)
+                .json(&body)
+                .send(super::route(todos.clone()))
+                .await;
+            assert_eq!(res.status_code.unwrap(), StatusCode::BAD_REQUEST);
+            let body = res.take_json::<Value>().await.unwrap();
+            assert_eq!(body["code"], "bad_request");
+        }
+
+        let res = TestClient::get(format!("{url}?limit=lots"))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::BAD_REQUEST);
+
+        let res = TestClient::delete(format!("{url}/abc"))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::BAD_REQUEST);
+
+        let mut res = TestClient::patch(format!("{url}/999999"))
+            .json(&json!({ "completed": true }))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::NOT_FOUND);
+        let body = res.take_json::<Value>().await.unwrap();
+        assert_eq!(body["message"], "todo 999999 not found");
+    }
+
+    async fn test_todo_patch(todos: Todos) {
+        let url = "http://0.0.0.0:5800/todos";
+
+        let mut res = TestClient::post(url)
+            .json(&json!({ "text": "patch me" }))
+            .send(super::route(todos.clone()))
+            .await;
+        let todo = res.take_json::<Todo>().await.unwrap();
+
+        let mut res = TestClient::patch(format!("{url}/{}", todo.id))
+            .json(&json!({ "completed": true }))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::OK);
+        let patched = res.take_json::<Todo>().await.unwrap();
+        assert_eq!(patched.text, "patch me");
+        assert!(patched.completed);
+
+        let mut res = TestClient::get(format!("{url}?limit=1"))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.take_json::<Vec<Todo>>().await.unwrap().len(), 1);
+    }
+
+    async fn test_todo_conflict(todos: Todos) {
+        let url = "http://0.0.0.0:5800/todos";
+        let http_date =
+            |t: chrono::DateTime<chrono::Utc>| t.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+
+        let mut res = TestClient::post(url)
+            .json(&json!({ "text": "edit me" }))
+            .send(super::route(todos.clone()))
+            .await;
+        let todo = res.take_json::<Todo>().await.unwrap();
+
+        // Unchanged since the client read it.
+        let mut res = TestClient::patch(format!("{url}/{}", todo.id))
+            .add_header("if-unmodified-since", http_date(todo.updated_at), true)
+            .json(&json!({ "completed": true }))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::OK);
+        let patched = res.take_json::<Todo>().await.unwrap();
 
+        // The client's copy is older than the todo's last change.
+        let stale = todo.updated_at - chrono::Duration::seconds(10);
+        let mut res = TestClient::put(format!("{url}/{}", todo.id))
+            .add_header("if-unmodified-since", http_date(stale), true)
+            .json(&json!({ "text": "stale edit" }))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::CONFLICT);
+        let body = res.take_json::<Value>().await.unwrap();
+        assert_eq!(body["code"], "conflict");
+
+        let mut res = TestClient::get(url).send(super::route(todos.clone())).await;
+        assert_eq!(res.take_json::<Vec<Todo>>().await.unwrap(), [patched]);
+
+        let res = TestClient::patch(format!("{url}/{}", todo.id))
+            .add_header("if-unmodified-since", "yesterday", true)
+            .json(&json!({ "completed": false }))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn test_todo_filters(todos: Todos) {
+        let url = "http://0.0.0.0:5800/todos";
+
+        for body in [
+            json!({ "text": "file taxes", "tags": ["Filters"], "due": "2024-04-15", "priority": "high" }),
+            json!({ "text": "renew passport", "tags": ["filters", "travel"], "due": "2024-03-01" }),
+            json!({ "text": "water plants", "tags": ["filters"], "priority": "low" }),
+        ] {
+            let res = TestClient::post(url)
+                .json(&body)
+                .send(super::route(todos.clone()))
+                .await;
+            assert_eq!(res.status_code.unwrap(), StatusCode::CREATED);
+        }
+
+        let todos = &todos;
+        let texts = |query: &'static str| async move {
+            let mut res = TestClient::get(format!("{url}?tag=filters&{query}"))
+                .send(super::route(todos.clone()))
+                .await;
+            assert_eq!(res.status_code.unwrap(), StatusCode::OK);
+            let todos = res.take_json::<Vec<Todo>>().await.unwrap();
+            todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts("sort=-priority").await,
+            ["file taxes", "renew passport", "water plants"]
+        );
+        assert_eq!(
+            texts("sort=due").await,
+            ["renew passport", "file taxes", "water plants"]
+        );
+        assert_eq!(texts("due_before=2024-04-01").await, ["renew passport"]);
+        assert_eq!(texts("q=TRAVEL").await, ["renew passport"]);
+        assert_eq!(texts("q=plants+water").await, ["water plants"]);
+        assert!(texts("completed=true").await.is_empty());
+
+        let res = TestClient::get(format!("{url}?sort=colour"))
+            .send(super::route(todos.clone()))
+            .await;
         assert_eq!(res.status_code.unwrap(), StatusCode::BAD_REQUEST);
     }
 
-    fn test_todo() -> Todo {
-        Todo {
-            id: 1,
+    async fn test_todo_events(todos: Todos) {
+        let url = "http://0.0.0.0:5800/todos";
+        let events = todos.events.subscribe(None).into_stream();
+
+        let mut res = TestClient::post(url)
+            .json(&json!({ "text": "watch me" }))
+            .send(super::route(todos.clone()))
+            .await;
+        let todo = res.take_json::<Todo>().await.unwrap();
+        TestClient::patch(format!("{url}/{}", todo.id))
+            .json(&json!({ "completed": true }))
+            .send(super::route(todos.clone()))
+            .await;
+        TestClient::delete(format!("{url}/{}", todo.id))
+            .send(super::route(todos.clone()))
+            .await;
+
+        let kinds: Vec<&str> = events.map(|event| event.kind()).take(3).collect().await;
+        assert_eq!(kinds, ["created", "updated", "deleted"]);
+
+        let res = TestClient::get(format!("{url}/events?since=soon"))
+            .send(super::route(todos.clone()))
+            .await;
+        assert_eq!(res.status_code.unwrap(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn test_todo_event_order(todos: Todos) {
+        let url = "http://0.0.0.0:5800/todos";
+        let mut res = TestClient::post(url)
+            .json(&json!({ "text": "race me" }))
+            .send(super::route(todos.clone()))
+            .await;
+        let todo = res.take_json::<Todo>().await.unwrap();
+        let events = todos.events.subscribe(None).into_stream();
+
+        join_all((0..20).map(|i| {
+            TestClient::patch(format!("{url}/{}", todo.id))
+                .json(&json!({ "text": format!("take {i}") }))
+                .send(super::route(todos.clone()))
+        }))
+        .await;
+
+        // Whichever patch won, the last event shows what the store kept.
+        let last = events.take(20).collect::<Vec<_>>().await.pop().unwrap();
+        let mut res = TestClient::get(url).send(super::route(todos.clone())).await;
+        let stored = res.take_json::<Vec<Todo>>().await.unwrap();
+        assert_eq!(
+            last.change,
+            Change::Updated {
+                todo: stored[0].clone()
+            }
+        );
+    }
+
+    fn test_todo() -> NewTodo {
+        NewTodo {
             text: "test todo".into(),
             completed: false,
+            due: None,
+            tags: Vec::new(),
+            priority: Default::default(),
         }
     }
 }
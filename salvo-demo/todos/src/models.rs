@@ -0,0 +1,322 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::error::ApiError;
+
+/// Longest allowed todo text, in characters.
+pub const MAX_TEXT_LEN: usize = 1000;
+
+/// Most todos returned by one list request.
+pub const MAX_LIMIT: usize = 1000;
+
+/// Most tags on one todo, and the longest allowed tag.
+pub const MAX_TAGS: usize = 20;
+pub const MAX_TAG_LEN: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Todo {
+    pub id: u64,
+    pub text: String,
+    pub completed: bool,
+    pub due: Option<NaiveDate>,
+    pub tags: Vec<String>,
+    pub priority: Priority,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn as_i64(self) -> i64 {
+        self as i64
+    }
+
+    pub fn from_i64(n: i64) -> Priority {
+        match n {
+            i64::MIN..=0 => Priority::Low,
+            1 => Priority::Normal,
+            _ => Priority::High,
+        }
+    }
+}
+
+/// The body of a create or update request; ids and timestamps are assigned by
+/// the server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NewTodo {
+    pub text: String,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub due: Option<NaiveDate>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+/// The body of a PATCH request. Missing fields are left unchanged; `"due":
+/// null` clears the due date.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TodoPatch {
+    pub text: Option<String>,
+    pub completed: Option<bool>,
+    #[serde(default, deserialize_with = "present")]
+    pub due: Option<Option<NaiveDate>>,
+    pub tags: Option<Vec<String>>,
+    pub priority: Option<Priority>,
+}
+
+/// Tells a field that is `null` (`Some(None)`) apart from one that is missing
+/// (`None`, via `#[serde(default)]`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Query string of `GET /todos`. All filters must match.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ListOptions {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub completed: Option<bool>,
+    /// Todos carrying this tag.
+    pub tag: Option<String>,
+    /// Todos due strictly before this date; todos without a due date never match.
+    pub due_before: Option<NaiveDate>,
+    /// Whitespace-separated words that must all appear, ignoring ASCII case,
+    /// in the text or one of the tags.
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: Sort,
+}
+
+/// `sort=<field>` or `sort=-<field>` for descending order. Ties are broken by
+/// id, and todos without a due date sort last either way.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Id,
+    Text,
+    Due,
+    Priority,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl TryFrom<String> for Sort {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (descending, name) = match s.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, s.as_str()),
+        };
+        let key = match name {
+            "id" => SortKey::Id,
+            "text" => SortKey::Text,
+            "due" => SortKey::Due,
+            "priority" => SortKey::Priority,
+            "created_at" => SortKey::CreatedAt,
+            "updated_at" => SortKey::UpdatedAt,
+            _ => {
+                return Err(format!(
+                    "cannot sort by {name:?}, expected one of id, text, due, priority, \
+                     created_at, updated_at"
+                ))
+            }
+        };
+        Ok(Sort { key, descending })
+    }
+}
+
+fn validate_text(text: &str) -> Result<(), ApiError> {
+    if text.trim().is_empty() {
+        return Err(ApiError::BadRequest("text must not be empty".into()));
+    }
+    if text.chars().count() > MAX_TEXT_LEN {
+        return Err(ApiError::BadRequest(format!(
+            "text must be at most {MAX_TEXT_LEN} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// Trims, lowercases and deduplicates `tags`, keeping their order.
+fn normalize_tags(tags: &mut Vec<String>) -> Result<(), ApiError> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags.drain(..) {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_LEN {
+            return Err(ApiError::BadRequest(format!(
+                "tags must be between 1 and {MAX_TAG_LEN} characters"
+            )));
+        }
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(ApiError::BadRequest(format!(
+            "a todo can have at most {MAX_TAGS} tags"
+        )));
+    }
+    *tags = normalized;
+    Ok(())
+}
+
+impl NewTodo {
+    /// Checks the fields and normalises the tags.
+    pub fn validate(&mut self) -> Result<(), ApiError> {
+        validate_text(&self.text)?;
+        normalize_tags(&mut self.tags)
+    }
+
+    pub fn into_todo(self, id: u64, now: DateTime<Utc>) -> Todo {
+        Todo {
+            id,
+            text: self.text,
+            completed: self.completed,
+            due: self.due,
+            tags: self.tags,
+            priority: self.priority,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl TodoPatch {
+    /// Checks the fields and normalises the tags.
+    pub fn validate(&mut self) -> Result<(), ApiError> {
+        if let Some(text) = &self.text {
+            validate_text(text)?;
+        }
+        if let Some(tags) = &mut self.tags {
+            normalize_tags(tags)?;
+        }
+        Ok(())
+    }
+
+    pub fn apply(self, todo: &mut Todo, now: DateTime<Utc>) {
+        if let Some(text) = self.text {
+            todo.text = text;
+        }
+        if let Some(completed) = self.completed {
+            todo.completed = completed;
+        }
+        if let Some(due) = self.due {
+            todo.due = due;
+        }
+        if let Some(tags) = self.tags {
+            todo.tags = tags;
+        }
+        if let Some(priority) = self.priority {
+            todo.priority = priority;
+        }
+        todo.updated_at = now;
+    }
+}
+
+impl From<NewTodo> for TodoPatch {
+    fn from(todo: NewTodo) -> Self {
+        TodoPatch {
+            text: Some(todo.text),
+            completed: Some(todo.completed),
+            due: Some(todo.due),
+            tags: Some(todo.tags),
+            priority: Some(todo.priority),
+        }
+    }
+}
+
+impl ListOptions {
+    pub fn validate(&mut self) -> Result<(), ApiError> {
+        if self.limit.is_some_and(|limit| limit > MAX_LIMIT) {
+            return Err(ApiError::BadRequest(format!(
+                "limit must be at most {MAX_LIMIT}"
+            )));
+        }
+        if let Some(tag) = &mut self.tag {
+            *tag = tag.trim().to_lowercase();
+        }
+        Ok(())
+    }
+
+    /// The words of `q`, lowercased.
+    pub fn terms(&self) -> Vec<String> {
+        self.q
+            .as_deref()
+            .unwrap_or("")
+            .split_whitespace()
+            .map(str::to_ascii_lowercase)
+            .collect()
+    }
+
+    /// Whether `todo` passes every filter. Stores that cannot filter natively
+    /// use this.
+    pub fn matches(&self, todo: &Todo, terms: &[String]) -> bool {
+        self.completed.map_or(true, |c| todo.completed == c)
+            && self
+                .tag
+                .as_ref()
+                .map_or(true, |tag| todo.tags.contains(tag))
+            && self
+                .due_before
+                .map_or(true, |date| todo.due.is_some_and(|due| due < date))
+            && terms.iter().all(|term| {
+                todo.text.to_ascii_lowercase().contains(term.as_str())
+                    || todo.tags.iter().any(|tag| tag.contains(term.as_str()))
+            })
+    }
+}
+
+impl Sort {
+    /// Orders todos the way the SQLite store's `ORDER BY` does.
+    pub fn compare(&self, a: &Todo, b: &Todo) -> Ordering {
+        if self.key == SortKey::Due {
+            // Todos without a due date go last in both directions.
+            let order = a.due.is_none().cmp(&b.due.is_none());
+            if order != Ordering::Equal {
+                return order;
+            }
+        }
+        let order = match self.key {
+            SortKey::Id => a.id.cmp(&b.id),
+            SortKey::Text => a.text.cmp(&b.text),
+            SortKey::Due => a.due.cmp(&b.due),
+            SortKey::Priority => a.priority.cmp(&b.priority),
+            SortKey::CreatedAt => a.created_at.cmp(&b.created_at),
+            SortKey::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+        };
+        let order = if self.descending {
+            order.reverse()
+        } else {
+            order
+        };
+        order.then(a.id.cmp(&b.id))
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use salvo::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{fs, sync::Mutex};
+
+use super::{modified_since, now, StoreError, TodoStore};
+use crate::models::{ListOptions, NewTodo, Todo, TodoPatch};
+
+/// Current version of the file format, see [`migrate`].
+const VERSION: u32 = 2;
+
+/// Todos in a single JSON file. The whole file is loaded on open and
+/// rewritten after every change, which is plenty for a small team's list.
+pub struct JsonStore {
+    path: PathBuf,
+    data: Mutex<Data>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Data {
+    version: u32,
+    next_id: u64,
+    todos: Vec<Todo>,
+}
+
+impl JsonStore {
+    pub async fn open(path: &Path) -> Result<JsonStore, StoreError> {
+        let data = match fs::read(path).await {
+            Ok(bytes) => migrate(serde_json::from_slice(&bytes)?)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Data {
+                version: VERSION,
+                next_id: 1,
+                todos: Vec::new(),
+            },
+            Err(e) => return Err(e.into()),
+        };
+        let store = JsonStore {
+            path: path.to_owned(),
+            data: Mutex::new(data),
+        };
+        // Write straight away so a migrated file is upgraded on disk and a
+        // bad path is reported on startup rather than on the first change.
+        store.save(&*store.data.lock().await).await?;
+        Ok(store)
+    }
+
+    /// Writes to a temporary file first so a crash never leaves a
+    /// half-written todo list behind.
+    async fn save(&self, data: &Data) -> Result<(), StoreError> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(data)?).await?;
+        fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+
+    /// Saves `changed` and only then makes it the current data, so a failed
+    /// write leaves memory matching the file.
+    async fn replace(&self, data: &mut Data, changed: Data) -> Result<(), StoreError> {
+        self.save(&changed).await?;
+        *data = changed;
+        Ok(())
+    }
+}
+
+/// Brings a file written by an older version up to date.
+///
+/// * version 0: a bare array of todos with client-chosen ids.
+/// * version 1: todos without due dates, tags, priority or timestamps.
+fn migrate(value: Value) -> Result<Data, StoreError> {
+    let mut value = match value {
+        Value::Array(todos) => {
+            let max_id = todos.iter().filter_map(|t| t["id"].as_u64()).max();
+            json!({ "version": 1, "next_id": max_id.unwrap_or(0) + 1, "todos": todos })
+        }
+        value => value,
+    };
+    let Some(data) = value.as_object_mut() else {
+        return Ok(serde_json::from_value(value)?);
+    };
+
+    let version = data.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > u64::from(VERSION) {
+        return Err(StoreError::Config(format!(
+            "todo file version {version} is newer than this server supports ({VERSION})"
+        )));
+    }
+    if version < 2 {
+        let now = serde_json::to_value(now())?;
+        let todos = data.get_mut("todos").and_then(Value::as_array_mut);
+        for todo in todos.into_iter().flatten().filter_map(Value::as_object_mut) {
+            todo.entry("tags").or_insert_with(|| json!([]));
+            todo.entry("priority").or_insert_with(|| json!("normal"));
+            todo.entry("created_at").or_insert_with(|| now.clone());
+            todo.entry("updated_at").or_insert_with(|| now.clone());
+        }
+        data.insert("version".into(), json!(2));
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+#[async_trait]
+impl TodoStore for JsonStore {
+    async fn list(&self, opts: &ListOptions) -> Result<Vec<Todo>, StoreError> {
+        let data = self.data.lock().await;
+        let terms = opts.terms();
+        let mut todos: Vec<&Todo> = data
+            .todos
+            .iter()
+            .filter(|todo| opts.matches(todo, &terms))
+            .collect();
+        todos.sort_by(|a, b| opts.sort.compare(a, b));
+        Ok(todos
+            .into_iter()
+            .skip(opts.offset.unwrap_or(0))
+            .take(opts.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    async fn create(&self, new: NewTodo) -> Result<Todo, StoreError> {
+        let mut data = self.data.lock().await;
+        let todo = new.into_todo(data.next_id, now());
+        if data.todos.iter().any(|other| other.id == todo.id) {
+            return Err(StoreError::Conflict(todo.id));
+        }
+        let mut changed = data.clone();
+        changed.next_id += 1;
+        changed.todos.push(todo.clone());
+        self.replace(&mut data, changed).await?;
+        Ok(todo)
+    }
+
+    async fn update(
+        &self,
+        id: u64,
+        changes: TodoPatch,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> Result<Option<Todo>, StoreError> {
+        let mut data = self.data.lock().await;
+        let Some(index) = data.todos.iter().position(|todo| todo.id == id) else {
+            return Ok(None);
+        };
+        if unmodified_since.is_some_and(|since| modified_since(&data.todos[index], since)) {
+            return Err(StoreError::Modified(id));
+        }
+        let mut changed = data.clone();
+        let todo = &mut changed.todos[index];
+        changes.apply(todo, now());
+        let todo = todo.clone();
+        self.replace(&mut data, changed).await?;
+        Ok(Some(todo))
+    }
+
+    async fn delete(&self, id: u64) -> Result<bool, StoreError> {
+        let mut data = self.data.lock().await;
+        let mut changed = data.clone();
+        changed.todos.retain(|todo| todo.id != id);
+        if changed.todos.len() == data.todos.len() {
+            return Ok(false);
+        }
+        self.replace(&mut data, changed).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Priority;
+
+    #[test]
+    fn migrates_bare_arrays() {
+        let data = migrate(serde_json::json!([
+            { "id": 7, "text": "old", "completed": true },
+            { "id": 3, "text": "older", "completed": false },
+        ]))
+        .unwrap();
+        assert_eq!(data.version, VERSION);
+        assert_eq!(data.next_id, 8);
+        assert_eq!(data.todos.len(), 2);
+        assert_eq!(data.todos[0].priority, Priority::Normal);
+        assert!(data.todos[0].tags.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_writes_change_nothing() {
+        let dir = std::env::temp_dir().join(format!("todos-json-{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let store = JsonStore::open(&dir.join("todos.json")).await.unwrap();
+        let new = |text: &str| NewTodo {
+            text: text.into(),
+            completed: false,
+            due: None,
+            tags: Vec::new(),
+            priority: Priority::Normal,
+        };
+        let kept = store.create(new("kept")).await.unwrap();
+
+        // Nowhere to write the temporary file any more.
+        fs::remove_dir_all(&dir).await.unwrap();
+        assert!(store.create(new("lost")).await.is_err());
+        let patch = TodoPatch {
+            completed: Some(true),
+            ..TodoPatch::default()
+        };
+        assert!(store.update(kept.id, patch, None).await.is_err());
+        assert!(store.delete(kept.id).await.is_err());
+
+        let todos = store.list(&ListOptions::default()).await.unwrap();
+        assert_eq!(todos, [kept]);
+        assert_eq!(store.data.lock().await.next_id, 2);
+    }
+}
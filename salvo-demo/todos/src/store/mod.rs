@@ -0,0 +1,142 @@
+//! Todo persistence.
+//!
+//! The backend is picked from the environment when the server starts:
+//!
+//! * `TODOS_STORE=sqlite` (default) keeps todos in the SQLite database at
+//!   `TODOS_PATH` (default `todos.db`), created and migrated on startup.
+//! * `TODOS_STORE=json` keeps todos in the JSON file at `TODOS_PATH`
+//!   (default `todos.json`), rewritten atomically on every change.
+
+mod json;
+mod sqlite;
+
+use std::{env, fmt, io, path::PathBuf};
+
+use chrono::{DateTime, SubsecRound, Utc};
+use salvo::async_trait;
+
+use crate::models::{ListOptions, NewTodo, Todo, TodoPatch};
+
+pub use self::json::JsonStore;
+pub use self::sqlite::SqliteStore;
+
+/// Where todos live. Ids are assigned by the store, never by the client.
+#[async_trait]
+pub trait TodoStore: Send + Sync {
+    /// The todos passing every filter in `opts`, in its sort order.
+    async fn list(&self, opts: &ListOptions) -> Result<Vec<Todo>, StoreError>;
+
+    async fn create(&self, todo: NewTodo) -> Result<Todo, StoreError>;
+
+    /// Applies `changes` to the todo with `id` and bumps its `updated_at`,
+    /// returning `None` if there is no such todo. With `unmodified_since`, a
+    /// todo updated after that time, see [`modified_since`], is left alone
+    /// and the update fails with [`StoreError::Modified`].
+    async fn update(
+        &self,
+        id: u64,
+        changes: TodoPatch,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> Result<Option<Todo>, StoreError>;
+
+    /// Returns whether a todo with `id` existed.
+    async fn delete(&self, id: u64) -> Result<bool, StoreError>;
+}
+
+/// The current time, at the millisecond precision both backends store.
+pub fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(3)
+}
+
+/// Whether `todo` changed after `since`. HTTP dates are whole seconds, and so
+/// is the comparison: two changes within the same second look like one.
+pub fn modified_since(todo: &Todo, since: DateTime<Utc>) -> bool {
+    todo.updated_at.trunc_subsecs(0) > since
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Config {
+    Sqlite { path: PathBuf },
+    Json { path: PathBuf },
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config, StoreError> {
+        let path = env::var_os("TODOS_PATH").map(PathBuf::from);
+        match env::var("TODOS_STORE").as_deref() {
+            Ok("sqlite") | Err(env::VarError::NotPresent) => Ok(Config::Sqlite {
+                path: path.unwrap_or_else(|| "todos.db".into()),
+            }),
+            Ok("json") => Ok(Config::Json {
+                path: path.unwrap_or_else(|| "todos.json".into()),
+            }),
+            Ok(other) => Err(StoreError::Config(format!(
+                "unknown TODOS_STORE {other:?}, expected \"sqlite\" or \"json\""
+            ))),
+            Err(e) => Err(StoreError::Config(format!("invalid TODOS_STORE: {e}"))),
+        }
+    }
+
+    /// Opens the store, creating and migrating it if needed.
+    pub async fn open(&self) -> Result<Box<dyn TodoStore>, StoreError> {
+        Ok(match self {
+            Config::Sqlite { path } => Box::new(SqliteStore::open(path).await?),
+            Config::Json { path } => Box::new(JsonStore::open(path).await?),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// The id the store picked for a new todo is already taken, which only
+    /// happens when the data was edited behind the server's back.
+    Conflict(u64),
+    /// The todo with this id changed after the time the update required it
+    /// to be unchanged since.
+    Modified(u64),
+    Config(String),
+    Io(io::Error),
+    Json(serde_json::Error),
+    Sqlite(sqlx::Error),
+    Migrate(sqlx::migrate::MigrateError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Conflict(id) => write!(f, "todo {id} already exists"),
+            StoreError::Modified(id) => write!(f, "todo {id} was changed by someone else"),
+            StoreError::Config(msg) => write!(f, "{msg}"),
+            StoreError::Io(e) => write!(f, "i/o error: {e}"),
+            StoreError::Json(e) => write!(f, "invalid todo file: {e}"),
+            StoreError::Sqlite(e) => write!(f, "database error: {e}"),
+            StoreError::Migrate(e) => write!(f, "migration failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Json(e)
+    }
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(e: sqlx::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for StoreError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        StoreError::Migrate(e)
+    }
+}
@@ -0,0 +1,415 @@
+use std::path::Path;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use salvo::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{QueryBuilder, Sqlite};
+
+use super::{now, StoreError, TodoStore};
+use crate::models::{ListOptions, NewTodo, Priority, SortKey, Todo, TodoPatch};
+
+/// Todos in a SQLite database. The schema lives in `migrations/` and is
+/// applied on open.
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+const COLUMNS: &str = "id, text, completed, due, tags, priority, created_at, updated_at";
+
+type Row = (
+    i64,
+    String,
+    bool,
+    Option<String>,
+    String,
+    i64,
+    String,
+    String,
+);
+
+fn from_row(row: Row) -> Result<Todo, StoreError> {
+    let (id, text, completed, due, tags, priority, created_at, updated_at) = row;
+    let decode = |e: Box<dyn std::error::Error + Send + Sync>| sqlx::Error::Decode(e);
+    Ok(Todo {
+        id: id as u64,
+        text,
+        completed,
+        due: due
+            .map(|due| due.parse())
+            .transpose()
+            .map_err(|e| decode(Box::new(e)))?,
+        tags: serde_json::from_str(&tags)?,
+        priority: Priority::from_i64(priority),
+        created_at: parse_timestamp(&created_at).map_err(|e| decode(Box::new(e)))?,
+        updated_at: parse_timestamp(&updated_at).map_err(|e| decode(Box::new(e)))?,
+    })
+}
+
+fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    Ok(DateTime::parse_from_rfc3339(s)?.with_timezone(&Utc))
+}
+
+/// Matches the format the migrations use, so timestamps sort as text.
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Escapes `%`, `_` and the escape character itself for `LIKE ... ESCAPE '\'`.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl SqliteStore {
+    pub async fn open(path: &Path) -> Result<SqliteStore, StoreError> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        SqliteStore::connect(options).await
+    }
+
+    /// Like [`open`](Self::open), for any database sqlx can connect to, such
+    /// as `sqlite::memory:`.
+    pub async fn connect(options: SqliteConnectOptions) -> Result<SqliteStore, StoreError> {
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        sqlx::migrate!().run(&pool).await?;
+        Ok(SqliteStore { pool })
+    }
+
+    async fn exists(&self, id: u64) -> Result<bool, StoreError> {
+        let query = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM todos WHERE id = ?)");
+        Ok(query.bind(id as i64).fetch_one(&self.pool).await?)
+    }
+}
+
+#[async_trait]
+impl TodoStore for SqliteStore {
+    async fn list(&self, opts: &ListOptions) -> Result<Vec<Todo>, StoreError> {
+        let mut query =
+            QueryBuilder::<Sqlite>::new(format!("SELECT {COLUMNS} FROM todos WHERE TRUE"));
+        if let Some(completed) = opts.completed {
+            query.push(" AND completed = ").push_bind(completed);
+        }
+        if let Some(tag) = &opts.tag {
+            query
+                .push(" AND EXISTS (SELECT 1 FROM json_each(todos.tags) WHERE value = ")
+                .push_bind(tag.clone())
+                .push(")");
+        }
+        if let Some(date) = opts.due_before {
+            // NULL < date is NULL, so todos without a due date drop out.
+            query.push(" AND due < ").push_bind(date.to_string());
+        }
+        // LIKE ignores ASCII case, just like ListOptions::matches.
+        for term in opts.terms() {
+            let pattern = format!("%{}%", escape_like(&term));
+            query
+                .push(" AND (text LIKE ")
+                .push_bind(pattern.clone())
+                .push(
+                    " ESCAPE '\\' OR EXISTS (SELECT 1 FROM json_each(todos.tags) WHERE value LIKE ",
+                )
+                .push_bind(pattern)
+                .push(" ESCAPE '\\'))");
+        }
+
+        let column = match opts.sort.key {
+            SortKey::Id => "id",
+            SortKey::Text => "text",
+            SortKey::Due => "due",
+            SortKey::Priority => "priority",
+            SortKey::CreatedAt => "created_at",
+            SortKey::UpdatedAt => "updated_at",
+        };
+        let direction = if opts.sort.descending { "DESC" } else { "ASC" };
+        query.push(" ORDER BY ");
+        if opts.sort.key == SortKey::Due {
+            query.push("due IS NULL, ");
+        }
+        query.push(format_args!("{column} {direction}, id"));
+
+        // A negative LIMIT means no limit in SQLite.
+        let limit: i64 = opts
+            .limit
+            .map_or(-1, |limit| limit.try_into().unwrap_or(-1));
+        let offset: i64 = opts.offset.unwrap_or(0).try_into().unwrap_or(i64::MAX);
+        query.push(" LIMIT ").push_bind(limit);
+        query.push(" OFFSET ").push_bind(offset);
+
+        let rows: Vec<Row> = query.build_query_as().fetch_all(&self.pool).await?;
+        rows.into_iter().map(from_row).collect()
+    }
+
+    async fn create(&self, new: NewTodo) -> Result<Todo, StoreError> {
+        let now = timestamp(now());
+        let row: Row = sqlx::query_as(&format!(
+            "INSERT INTO todos (text, completed, due, tags, priority, created_at, updated_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING {COLUMNS}"
+        ))
+        .bind(new.text)
+        .bind(new.completed)
+        .bind(new.due.map(|due| due.to_string()))
+        .bind(serde_json::to_string(&new.tags)?)
+        .bind(new.priority.as_i64())
+        .bind(&now)
+        .bind(&now)
+        .fetch_one(&self.pool)
+        .await?;
+        from_row(row)
+    }
+
+    async fn update(
+        &self,
+        id: u64,
+        changes: TodoPatch,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> Result<Option<Todo>, StoreError> {
+        let tags = changes
+            .tags
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        // Not modified since a whole second means updated before the next
+        // one, see `modified_since`.
+        let unmodified_before =
+            unmodified_since.map(|since| timestamp(since + chrono::Duration::seconds(1)));
+        // NULL parameters leave the column as it is; `due` may be set to NULL,
+        // so it has a separate flag saying whether to touch it.
+        let row = sqlx::query_as(&format!(
+            "UPDATE todos SET text = COALESCE(?, text), completed = COALESCE(?, completed), \
+             due = CASE WHEN ? THEN ? ELSE due END, tags = COALESCE(?, tags), \
+             priority = COALESCE(?, priority), updated_at = ? \
+             WHERE id = ? AND (? IS NULL OR updated_at < ?) RETURNING {COLUMNS}"
+        ))
+        .bind(changes.text)
+        .bind(changes.completed)
+        .bind(changes.due.is_some())
+        .bind(changes.due.flatten().map(|due| due.to_string()))
+        .bind(tags)
+        .bind(changes.priority.map(Priority::as_i64))
+        .bind(timestamp(now()))
+        .bind(id as i64)
+        .bind(&unmodified_before)
+        .bind(&unmodified_before)
+        .fetch_optional(&self.pool)
+        .await?;
+        match row {
+            Some(row) => from_row(row).map(Some),
+            None if unmodified_before.is_some() && self.exists(id).await? => {
+                Err(StoreError::Modified(id))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, id: u64) -> Result<bool, StoreError> {
+        let result = sqlx::query("DELETE FROM todos WHERE id = ?")
+            .bind(id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::models::Sort;
+
+    async fn memory_store() -> SqliteStore {
+        SqliteStore::connect("sqlite::memory:".parse().unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrates_todos_without_details() {
+        let pool = SqlitePoolOptions::new()
+            .connect_with("sqlite::memory:".parse().unwrap())
+            .await
+            .unwrap();
+        // A database from before due dates, tags, priority and timestamps.
+        let mut migrator = sqlx::migrate!();
+        migrator.migrations = Cow::Owned(migrator.migrations[..1].to_vec());
+        migrator.run(&pool).await.unwrap();
+        sqlx::query("INSERT INTO todos (text, completed) VALUES ('old', TRUE)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let store = SqliteStore { pool };
+        let todos = store.list(&ListOptions::default()).await.unwrap();
+        assert_eq!(todos.len(), 1);
+        let todo = &todos[0];
+        assert_eq!(
+            (todo.id, todo.text.as_str(), todo.completed),
+            (1, "old", true)
+        );
+        assert_eq!(todo.due, None);
+        assert!(todo.tags.is_empty());
+        assert_eq!(todo.priority, Priority::Normal);
+        assert!(now() - todo.created_at < chrono::Duration::minutes(1));
+        assert_eq!(todo.updated_at, todo.created_at);
+
+        // New todos carry on after the old ids.
+        let todo = store
+            .create(new("new", None, &[], Priority::High))
+            .await
+            .unwrap();
+        assert_eq!(todo.id, 2);
+    }
+
+    fn new(text: &str, due: Option<&str>, tags: &[&str], priority: Priority) -> NewTodo {
+        NewTodo {
+            text: text.into(),
+            completed: false,
+            due: due.map(|due| due.parse().unwrap()),
+            tags: tags.iter().map(|&tag| tag.into()).collect(),
+            priority,
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_and_sorts_like_list_options() {
+        let store = memory_store().await;
+        let mut todos = Vec::new();
+        for todo in [
+            new("file taxes", Some("2024-04-15"), &["money"], Priority::High),
+            new(
+                "renew passport",
+                Some("2024-03-01"),
+                &["travel", "admin"],
+                Priority::Normal,
+            ),
+            new(
+                "book 100% refundable hotel",
+                None,
+                &["travel"],
+                Priority::Low,
+            ),
+            new("water plants", None, &[], Priority::Normal),
+            new(
+                "pay 1000 invoice",
+                Some("2024-03-01"),
+                &["money"],
+                Priority::High,
+            ),
+        ] {
+            todos.push(store.create(todo).await.unwrap());
+        }
+        let patch = TodoPatch {
+            completed: Some(true),
+            ..TodoPatch::default()
+        };
+        let done = store
+            .update(todos[2].id, patch, None)
+            .await
+            .unwrap()
+            .unwrap();
+        todos[2] = done;
+
+        let sort = |s: &str| Sort::try_from(s.to_owned()).unwrap();
+        let cases = [
+            ListOptions::default(),
+            ListOptions {
+                completed: Some(true),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                tag: Some("travel".into()),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                due_before: Some("2024-04-01".parse().unwrap()),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                q: Some("TRAVEL".into()),
+                ..ListOptions::default()
+            },
+            // LIKE wildcards in the search are taken literally.
+            ListOptions {
+                q: Some("100%".into()),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                q: Some("_".into()),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                sort: sort("due"),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                sort: sort("-due"),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                sort: sort("-priority"),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                sort: sort("text"),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                sort: sort("-updated_at"),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                tag: Some("money".into()),
+                sort: sort("-due"),
+                ..ListOptions::default()
+            },
+            ListOptions {
+                offset: Some(1),
+                limit: Some(2),
+                sort: sort("due"),
+                ..ListOptions::default()
+            },
+        ];
+
+        for opts in &cases {
+            let terms = opts.terms();
+            let mut expected: Vec<&Todo> = todos
+                .iter()
+                .filter(|todo| opts.matches(todo, &terms))
+                .collect();
+            expected.sort_by(|a, b| opts.sort.compare(a, b));
+            let expected: Vec<Todo> = expected
+                .into_iter()
+                .skip(opts.offset.unwrap_or(0))
+                .take(opts.limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect();
+            assert_eq!(store.list(opts).await.unwrap(), expected, "{opts:?}");
+        }
+
+        // Spot checks, in case ListOptions itself is wrong.
+        let texts = |todos: Vec<Todo>| todos.into_iter().map(|todo| todo.text).collect::<Vec<_>>();
+        assert_eq!(
+            texts(store.list(&cases[8]).await.unwrap()),
+            [
+                "file taxes",
+                "renew passport",
+                "pay 1000 invoice",
+                "book 100% refundable hotel",
+                "water plants"
+            ]
+        );
+        assert_eq!(
+            texts(store.list(&cases[5]).await.unwrap()),
+            ["book 100% refundable hotel"]
+        );
+        assert!(store.list(&cases[6]).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+//! Measures requests per second against an in-process server for a growing
+//! number of concurrent connections, once with a single shard (one lock for
+//! the whole keyspace, like the original tinydb) and once sharded.
+//!
+//!     cargo bench --bench throughput
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::SinkExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Framed, LinesCodec};
+
+use tinydb::db::{self, Database};
+use tinydb::pubsub::PubSub;
+use tinydb::replication::Replication;
+use tinydb::server;
+use tinydb::session::Context;
+
+/// Requests each connection sends per iteration, half `SET`s and half `GET`s.
+const REQUESTS_PER_CONNECTION: usize = 100;
+
+const CONNECTIONS: [usize; 4] = [1, 4, 16, 64];
+
+const SHARDS: [usize; 2] = [1, 16];
+
+type Connection = Framed<TcpStream, LinesCodec>;
+
+/// Starts a server with `shards` shards and opens `connections` to it.
+async fn setup(shards: usize, connections: usize) -> Vec<Connection> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let db = Arc::new(Database::new(HashMap::new(), None, shards));
+    tokio::spawn(db::feed_replicas(db.clone()));
+    let ctx = Context {
+        db,
+        pubsub: Arc::new(PubSub::default()),
+        replication: Arc::new(Replication::default()),
+    };
+    tokio::spawn(server::serve_lines(listener, ctx));
+
+    let mut conns = Vec::with_capacity(connections);
+    for _ in 0..connections {
+        let socket = TcpStream::connect(addr).await.unwrap();
+        socket.set_nodelay(true).unwrap();
+        conns.push(Framed::new(socket, LinesCodec::new()));
+    }
+    conns
+}
+
+/// Runs one round of requests on every connection concurrently.
+async fn round(conns: Vec<Connection>) -> Vec<Connection> {
+    let tasks: Vec<_> = conns
+        .into_iter()
+        .enumerate()
+        .map(|(id, mut conn)| {
+            tokio::spawn(async move {
+                for i in 0..REQUESTS_PER_CONNECTION / 2 {
+                    let key = format!("key:{}:{}", id, i);
+                    conn.send(format!("SET {} value", key)).await.unwrap();
+                    conn.next().await.unwrap().unwrap();
+                    conn.send(format!("GET {}", key)).await.unwrap();
+                    conn.next().await.unwrap().unwrap();
+                }
+                conn
+            })
+        })
+        .collect();
+
+    let mut conns = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        conns.push(task.await.unwrap());
+    }
+    conns
+}
+
+fn throughput(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("throughput");
+
+    for shards in SHARDS {
+        for connections in CONNECTIONS {
+            group.throughput(Throughput::Elements(
+                (connections * REQUESTS_PER_CONNECTION) as u64,
+            ));
+            let id = BenchmarkId::new(format!("{} shard(s)", shards), connections);
+
+            let mut conns = Some(rt.block_on(setup(shards, connections)));
+            group.bench_function(id, |b| {
+                b.iter_custom(|iters| {
+                    rt.block_on(async {
+                        let start = tokio::time::Instant::now();
+                        for _ in 0..iters {
+                            conns = Some(round(conns.take().unwrap()).await);
+                        }
+                        start.elapsed()
+                    })
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,323 @@
+//! Durability through an append-only file (AOF).
+//!
+//! Every mutation of the database is appended to the file as a [`Record`].
+//! Records always describe the complete resulting state of a key (its value
+//! and absolute expiry, or the fact that it was deleted) rather than the
+//! command that produced it, which makes replaying a record twice harmless.
+//! That property is what allows the file to be compacted while the server
+//! keeps accepting writes: the compacted file is a snapshot of the database
+//! followed by whatever records were still queued, some of which the snapshot
+//! may already contain.
+//!
+//! The file is flushed and synced to disk once per second, so a crash loses
+//! at most the last second of writes.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::db::{Database, Entry};
+
+/// A single line of the append-only file.
+///
+/// Records are also what primaries stream to their replicas, see
+/// `replication.rs`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    Put {
+        key: String,
+        value: String,
+        /// Expiry as milliseconds since the unix epoch.
+        expires_at: Option<u64>,
+    },
+    Del {
+        key: String,
+    },
+}
+
+impl Record {
+    pub fn put(key: &str, entry: &Entry) -> Record {
+        Record::Put {
+            key: key.to_string(),
+            value: entry.value.clone(),
+            expires_at: entry.expires_at.map(to_millis),
+        }
+    }
+
+    /// Splits the record into the key it is about and the entry that key now
+    /// holds, `None` meaning it was deleted.
+    pub fn into_change(self) -> (String, Option<Entry>) {
+        match self {
+            Record::Put {
+                key,
+                value,
+                expires_at,
+            } => {
+                let entry = Entry {
+                    value,
+                    expires_at: expires_at.map(from_millis),
+                };
+                (key, Some(entry))
+            }
+            Record::Del { key } => (key, None),
+        }
+    }
+
+    /// Turns the record into a command with its arguments, `SET key value
+    /// [PXAT ms]` or `DEL key`.
+    pub fn to_args(&self) -> Vec<String> {
+        match self {
+            Record::Put {
+                key,
+                value,
+                expires_at: None,
+            } => vec!["SET".into(), key.clone(), value.clone()],
+            Record::Put {
+                key,
+                value,
+                expires_at: Some(at),
+            } => vec![
+                "SET".into(),
+                key.clone(),
+                value.clone(),
+                "PXAT".into(),
+                at.to_string(),
+            ],
+            Record::Del { key } => vec!["DEL".into(), key.clone()],
+        }
+    }
+
+    /// The inverse of [`Record::to_args`].
+    pub fn from_args(args: &[String]) -> Result<Record, String> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            ["SET", key, value] => Ok(Record::Put {
+                key: key.to_string(),
+                value: value.to_string(),
+                expires_at: None,
+            }),
+            ["SET", key, value, "PXAT", at] => Ok(Record::Put {
+                key: key.to_string(),
+                value: value.to_string(),
+                expires_at: Some(at.parse().map_err(|_| format!("invalid expiry `{}`", at))?),
+            }),
+            ["DEL", key] => Ok(Record::Del {
+                key: key.to_string(),
+            }),
+            _ => Err(format!("malformed record `{}`", args.join(" "))),
+        }
+    }
+
+    /// Encodes the record as a single line, including the trailing newline.
+    fn encode(&self) -> String {
+        let fields: Vec<String> = self.to_args().iter().map(|arg| escape(arg)).collect();
+        fields.join(" ") + "\n"
+    }
+
+    fn decode(line: &str) -> Result<Record, String> {
+        let args = line
+            .split(' ')
+            .map(unescape)
+            .collect::<Result<Vec<_>, _>>()?;
+        Record::from_args(&args)
+    }
+}
+
+/// Loads the database contents from the append-only file at `path`.
+///
+/// Returns `None` if the file doesn't exist yet. A final line without a
+/// trailing newline is the result of a crash in the middle of a write and is
+/// ignored.
+pub fn load(path: &Path) -> io::Result<Option<HashMap<String, Entry>>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let complete = match contents.rfind('\n') {
+        Some(end) => &contents[..end],
+        None => "",
+    };
+    if complete.len() + 1 < contents.len() {
+        println!("ignoring truncated record at the end of {}", path.display());
+    }
+
+    let mut map = HashMap::new();
+    for (n, line) in complete.lines().enumerate() {
+        let record = Record::decode(line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), n + 1, e),
+            )
+        })?;
+        match record.into_change() {
+            (key, Some(entry)) => {
+                map.insert(key, entry);
+            }
+            (key, None) => {
+                map.remove(&key);
+            }
+        }
+    }
+
+    let now = SystemTime::now();
+    map.retain(|_, entry: &mut Entry| !entry.is_expired(now));
+    Ok(Some(map))
+}
+
+/// Appends records received on `records` to the file at `path` until every
+/// sender is dropped, rewriting the file from a snapshot of `db` every
+/// `compact_every`.
+pub async fn run(
+    path: PathBuf,
+    mut records: UnboundedReceiver<Record>,
+    db: Arc<Database>,
+    compact_every: Duration,
+) -> io::Result<()> {
+    let mut file = open(&path).await?;
+    let mut sync = tokio::time::interval(Duration::from_secs(1));
+    let mut compact = tokio::time::interval(compact_every);
+    // The first tick of an interval completes immediately, skip it.
+    compact.tick().await;
+
+    let mut dirty = false;
+    let mut written_since_compaction = false;
+    loop {
+        tokio::select! {
+            record = records.recv() => match record {
+                Some(record) => {
+                    file.write_all(record.encode().as_bytes()).await?;
+                    dirty = true;
+                    written_since_compaction = true;
+                }
+                None => break,
+            },
+            _ = sync.tick(), if dirty => {
+                file.flush().await?;
+                file.get_ref().sync_data().await?;
+                dirty = false;
+            }
+            _ = compact.tick(), if written_since_compaction => {
+                file.flush().await?;
+                file = compact_file(&path, &db).await?;
+                dirty = false;
+                written_since_compaction = false;
+            }
+        }
+    }
+
+    file.flush().await?;
+    file.get_ref().sync_data().await
+}
+
+/// Rewrites the file at `path` so it only contains the current contents of
+/// `db`, returning a handle to append further records to.
+async fn compact_file(path: &Path, db: &Database) -> io::Result<BufWriter<File>> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut tmp = BufWriter::new(File::create(&tmp_path).await?);
+    for (key, entry) in db.snapshot() {
+        tmp.write_all(Record::put(&key, &entry).encode().as_bytes())
+            .await?;
+    }
+    tmp.flush().await?;
+    tmp.get_ref().sync_all().await?;
+    drop(tmp);
+
+    tokio::fs::rename(&tmp_path, path).await?;
+    open(path).await
+}
+
+async fn open(path: &Path) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    Ok(BufWriter::new(file))
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_millis() as u64
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+/// Escapes a field so it contains neither spaces nor line breaks.
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ' ' => escaped.push_str("\\s"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            _ => return Err(format!("invalid escape in `{}`", field)),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Record;
+
+    #[test]
+    fn records_round_trip() {
+        let records = [
+            Record::Put {
+                key: "greeting".into(),
+                value: "hello world\n\\o/".into(),
+                expires_at: None,
+            },
+            Record::Put {
+                key: "session".into(),
+                value: "".into(),
+                expires_at: Some(1_700_000_000_000),
+            },
+            Record::Del {
+                key: "a key".into(),
+            },
+        ];
+
+        for record in records {
+            let line = record.encode();
+            assert!(line.ends_with('\n'));
+            assert_eq!(line.matches('\n').count(), 1);
+            assert_eq!(Record::decode(line.trim_end_matches('\n')), Ok(record));
+        }
+    }
+}
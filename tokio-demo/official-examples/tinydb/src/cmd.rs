@@ -0,0 +1,582 @@
+//! The commands understood by the server and the responses it sends back.
+
+use crate::resp::Frame;
+
+/// Possible requests our clients can send us
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: String,
+    },
+    Del {
+        keys: Vec<String>,
+    },
+    Exists {
+        keys: Vec<String>,
+    },
+    Incr {
+        key: String,
+    },
+    Expire {
+        key: String,
+        seconds: i64,
+    },
+    Ttl {
+        key: String,
+    },
+    Keys {
+        pattern: String,
+    },
+    MGet {
+        keys: Vec<String>,
+    },
+    MSet {
+        pairs: Vec<(String, String)>,
+    },
+    Ping {
+        msg: Option<String>,
+    },
+    Publish {
+        channel: String,
+        message: String,
+    },
+    Subscribe {
+        channels: Vec<String>,
+    },
+    Unsubscribe {
+        channels: Vec<String>,
+    },
+    PSubscribe {
+        patterns: Vec<String>,
+    },
+    PUnsubscribe {
+        patterns: Vec<String>,
+    },
+    Info {
+        section: Option<String>,
+    },
+    /// `REPLICAOF host port`, or `REPLICAOF NO ONE` (`primary: None`).
+    ReplicaOf {
+        primary: Option<String>,
+    },
+    /// Sent by a replica to start streaming from this server.
+    Sync,
+    /// Starts queueing commands until `EXEC` or `DISCARD`.
+    Multi,
+    Exec,
+    Discard,
+    /// Makes the next `EXEC` fail if any of `keys` is written to before it.
+    Watch {
+        keys: Vec<String>,
+    },
+    Unwatch,
+    /// Sets `key` to `new` if it holds `expected`.
+    Cas {
+        key: String,
+        expected: String,
+        new: String,
+    },
+}
+
+/// Responses to the `Request` commands above
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    Value {
+        key: String,
+        value: String,
+    },
+    Set {
+        key: String,
+        value: String,
+        previous: Option<String>,
+    },
+    /// A write that has nothing else to report.
+    Ok,
+    /// The values of several keys, `None` for missing ones.
+    Values {
+        values: Vec<Option<String>>,
+    },
+    /// The key asked for does not exist.
+    Missing {
+        key: String,
+    },
+    Integer {
+        value: i64,
+    },
+    Keys {
+        keys: Vec<String>,
+    },
+    Pong {
+        msg: Option<String>,
+    },
+    /// Confirms a change of subscriptions. `kind` is the command name in
+    /// lower case, `count` the number of subscriptions afterwards.
+    Subscription {
+        kind: &'static str,
+        channel: Option<String>,
+        count: usize,
+    },
+    /// A message pushed to a subscribed connection.
+    Message {
+        pattern: Option<String>,
+        channel: String,
+        payload: String,
+    },
+    /// Lines of `key:value` information, grouped in `# Section`s.
+    Info {
+        lines: Vec<String>,
+    },
+    /// A command was queued as part of a transaction.
+    Queued,
+    /// The results of the commands of a transaction, `None` if it was
+    /// aborted because a watched key changed.
+    Exec {
+        results: Option<Vec<Response>>,
+    },
+    Error {
+        msg: String,
+    },
+}
+
+impl Request {
+    /// Parses a request of the line protocol.
+    ///
+    /// Arguments are separated by whitespace, except for the value of `SET`
+    /// and the message of `PUBLISH` which are the rest of the line, spaces
+    /// included.
+    pub fn parse(input: &str) -> Result<Request, String> {
+        let keeps_spaces = input.split(' ').next().is_some_and(|cmd| {
+            cmd.eq_ignore_ascii_case("SET") || cmd.eq_ignore_ascii_case("PUBLISH")
+        });
+        let args = if keeps_spaces {
+            input.splitn(3, ' ').map(str::to_string).collect()
+        } else {
+            input.split_whitespace().map(str::to_string).collect()
+        };
+        Request::from_args(args)
+    }
+
+    /// Builds a request out of a command name and its arguments, as sent by
+    /// RESP clients. Command names are case insensitive.
+    pub fn from_args(args: Vec<String>) -> Result<Request, String> {
+        let mut args = args.into_iter();
+        let cmd = match args.next() {
+            Some(cmd) => cmd.to_ascii_uppercase(),
+            None => return Err("empty input".into()),
+        };
+        let rest: Vec<String> = args.collect();
+
+        match cmd.as_str() {
+            "GET" => {
+                let key = single_key("GET", rest)?;
+                Ok(Request::Get { key })
+            }
+            "SET" => {
+                let mut rest = rest.into_iter();
+                let key = match rest.next() {
+                    Some(key) if !key.is_empty() => key,
+                    _ => return Err("SET must be followed by a key".into()),
+                };
+                let value = match rest.next() {
+                    Some(value) => value,
+                    None => return Err("SET needs a value".into()),
+                };
+                if rest.next().is_some() {
+                    return Err("SET's value must not be followed by anything".into());
+                }
+                Ok(Request::Set { key, value })
+            }
+            "DEL" => {
+                let keys = some_keys("DEL", rest)?;
+                Ok(Request::Del { keys })
+            }
+            "EXISTS" => {
+                let keys = some_keys("EXISTS", rest)?;
+                Ok(Request::Exists { keys })
+            }
+            "INCR" => {
+                let key = single_key("INCR", rest)?;
+                Ok(Request::Incr { key })
+            }
+            "EXPIRE" => {
+                let (key, seconds) = match <[String; 2]>::try_from(rest) {
+                    Ok([key, seconds]) => (key, seconds),
+                    Err(_) => return Err("EXPIRE must be followed by a key and a timeout".into()),
+                };
+                let seconds = seconds
+                    .parse()
+                    .map_err(|_| format!("invalid timeout `{}`", seconds))?;
+                Ok(Request::Expire { key, seconds })
+            }
+            "TTL" => {
+                let key = single_key("TTL", rest)?;
+                Ok(Request::Ttl { key })
+            }
+            "KEYS" => {
+                let pattern = single_key("KEYS", rest)?;
+                Ok(Request::Keys { pattern })
+            }
+            "MGET" => {
+                let keys = some_keys("MGET", rest)?;
+                Ok(Request::MGet { keys })
+            }
+            "MSET" => {
+                if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                    return Err("MSET must be followed by key value pairs".into());
+                }
+                let mut rest = rest.into_iter();
+                let mut pairs = Vec::new();
+                while let (Some(key), Some(value)) = (rest.next(), rest.next()) {
+                    pairs.push((key, value));
+                }
+                Ok(Request::MSet { pairs })
+            }
+            "PING" => {
+                let mut rest = rest.into_iter();
+                let msg = rest.next();
+                if rest.next().is_some() {
+                    return Err("PING takes at most one argument".into());
+                }
+                Ok(Request::Ping { msg })
+            }
+            "PUBLISH" => {
+                let (channel, message) = match <[String; 2]>::try_from(rest) {
+                    Ok([channel, message]) => (channel, message),
+                    Err(_) => {
+                        return Err("PUBLISH must be followed by a channel and a message".into())
+                    }
+                };
+                Ok(Request::Publish { channel, message })
+            }
+            "SUBSCRIBE" => {
+                let channels = some_keys("SUBSCRIBE", rest)?;
+                Ok(Request::Subscribe { channels })
+            }
+            "UNSUBSCRIBE" => Ok(Request::Unsubscribe { channels: rest }),
+            "PSUBSCRIBE" => {
+                let patterns = some_keys("PSUBSCRIBE", rest)?;
+                Ok(Request::PSubscribe { patterns })
+            }
+            "PUNSUBSCRIBE" => Ok(Request::PUnsubscribe { patterns: rest }),
+            "INFO" => {
+                let mut rest = rest.into_iter();
+                let section = rest.next();
+                if rest.next().is_some() {
+                    return Err("INFO takes at most one section".into());
+                }
+                Ok(Request::Info { section })
+            }
+            "REPLICAOF" => match <[String; 2]>::try_from(rest) {
+                Ok([no, one])
+                    if no.eq_ignore_ascii_case("NO") && one.eq_ignore_ascii_case("ONE") =>
+                {
+                    Ok(Request::ReplicaOf { primary: None })
+                }
+                Ok([host, port]) => {
+                    port.parse::<u16>()
+                        .map_err(|_| format!("invalid port `{}`", port))?;
+                    Ok(Request::ReplicaOf {
+                        primary: Some(format!("{}:{}", host, port)),
+                    })
+                }
+                Err(_) => Err("REPLICAOF must be followed by a host and a port, or NO ONE".into()),
+            },
+            "SYNC" => match rest.is_empty() {
+                true => Ok(Request::Sync),
+                false => Err("SYNC takes no arguments".into()),
+            },
+            "MULTI" => no_args("MULTI", rest, Request::Multi),
+            "EXEC" => no_args("EXEC", rest, Request::Exec),
+            "DISCARD" => no_args("DISCARD", rest, Request::Discard),
+            "WATCH" => {
+                let keys = some_keys("WATCH", rest)?;
+                Ok(Request::Watch { keys })
+            }
+            "UNWATCH" => no_args("UNWATCH", rest, Request::Unwatch),
+            "CAS" => match <[String; 3]>::try_from(rest) {
+                Ok([key, expected, new]) => Ok(Request::Cas { key, expected, new }),
+                Err(_) => {
+                    Err("CAS must be followed by a key, the expected and the new value".into())
+                }
+            },
+            _ => Err(format!("unknown command: {}", cmd)),
+        }
+    }
+
+    /// Whether the request modifies the database, which replicas refuse.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Request::Set { .. }
+                | Request::Del { .. }
+                | Request::Incr { .. }
+                | Request::Expire { .. }
+                | Request::MSet { .. }
+                | Request::Cas { .. }
+        )
+    }
+
+    /// The keys the request reads or writes, whose shards have to be locked
+    /// to run it. Empty for requests that don't touch the keyspace, and for
+    /// `KEYS` which scans all of it.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Request::Get { key }
+            | Request::Set { key, .. }
+            | Request::Incr { key }
+            | Request::Expire { key, .. }
+            | Request::Ttl { key }
+            | Request::Cas { key, .. } => vec![key.as_str()],
+            Request::Del { keys } | Request::Exists { keys } | Request::MGet { keys } => {
+                keys.iter().map(String::as_str).collect()
+            }
+            Request::MSet { pairs } => pairs.iter().map(|(key, _)| key.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the request may be queued in a `MULTI` transaction.
+    pub fn allowed_in_transaction(&self) -> bool {
+        self.is_write()
+            || matches!(
+                self,
+                Request::Get { .. }
+                    | Request::Exists { .. }
+                    | Request::Ttl { .. }
+                    | Request::Keys { .. }
+                    | Request::MGet { .. }
+                    | Request::Ping { .. }
+                    | Request::Publish { .. }
+            )
+    }
+
+    /// Whether the request may be sent by a connection that is subscribed to
+    /// at least one channel or pattern.
+    pub fn allowed_while_subscribed(&self) -> bool {
+        matches!(
+            self,
+            Request::Subscribe { .. }
+                | Request::Unsubscribe { .. }
+                | Request::PSubscribe { .. }
+                | Request::PUnsubscribe { .. }
+                | Request::Ping { .. }
+        )
+    }
+}
+
+/// Parses the single key argument of `cmd`.
+fn single_key(cmd: &str, rest: Vec<String>) -> Result<String, String> {
+    let mut rest = rest.into_iter();
+    let key = rest
+        .next()
+        .ok_or_else(|| format!("{} must be followed by a key", cmd))?;
+    if rest.next().is_some() {
+        return Err(format!("{}'s key must not be followed by anything", cmd));
+    }
+    Ok(key)
+}
+
+/// Checks that `cmd` was given no arguments.
+fn no_args(cmd: &str, rest: Vec<String>, request: Request) -> Result<Request, String> {
+    match rest.is_empty() {
+        true => Ok(request),
+        false => Err(format!("{} takes no arguments", cmd)),
+    }
+}
+
+/// Parses the one or more key arguments of `cmd`.
+fn some_keys(cmd: &str, rest: Vec<String>) -> Result<Vec<String>, String> {
+    if rest.is_empty() {
+        return Err(format!("{} must be followed by at least one key", cmd));
+    }
+    Ok(rest)
+}
+
+impl Response {
+    pub fn serialize(&self) -> String {
+        match *self {
+            Response::Value { ref key, ref value } => format!("{} = {}", key, value),
+            Response::Set {
+                ref key,
+                ref value,
+                ref previous,
+            } => format!("set {} = `{}`, previous: {:?}", key, value, previous),
+            Response::Ok => "OK".to_string(),
+            Response::Values { ref values } => values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match value {
+                    Some(value) => format!("{}) {}", i + 1, value),
+                    None => format!("{}) (nil)", i + 1),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Response::Missing { ref key } => format!("error: no key {}", key),
+            Response::Integer { value } => format!("(integer) {}", value),
+            Response::Keys { ref keys } if keys.is_empty() => "(empty list)".to_string(),
+            Response::Keys { ref keys } => keys
+                .iter()
+                .enumerate()
+                .map(|(i, key)| format!("{}) {}", i + 1, key))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Response::Pong { msg: None } => "PONG".to_string(),
+            Response::Pong { msg: Some(ref msg) } => msg.clone(),
+            Response::Subscription {
+                kind,
+                ref channel,
+                count,
+            } => match channel {
+                Some(channel) => format!("{} {} ({} active)", kind, channel, count),
+                None => format!("{} ({} active)", kind, count),
+            },
+            Response::Message {
+                pattern: None,
+                ref channel,
+                ref payload,
+            } => format!("message {}: {}", channel, payload),
+            Response::Message {
+                pattern: Some(ref pattern),
+                ref channel,
+                ref payload,
+            } => format!("pmessage {} {}: {}", pattern, channel, payload),
+            Response::Info { ref lines } => lines.join("\n"),
+            Response::Queued => "QUEUED".to_string(),
+            Response::Exec { results: None } => "(nil)".to_string(),
+            Response::Exec {
+                results: Some(ref results),
+            } if results.is_empty() => "(empty list)".to_string(),
+            Response::Exec {
+                results: Some(ref results),
+            } => results
+                .iter()
+                .enumerate()
+                .map(|(i, result)| format!("{}) {}", i + 1, result.serialize()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Response::Error { ref msg } => format!("error: {}", msg),
+        }
+    }
+
+    /// Converts the response into the RESP frame a Redis server would reply
+    /// with.
+    pub fn into_frame(self) -> Frame {
+        match self {
+            Response::Value { value, .. } => Frame::bulk(value),
+            Response::Set { .. } => Frame::Simple("OK".into()),
+            Response::Ok => Frame::Simple("OK".into()),
+            Response::Values { values } => Frame::Array(Some(
+                values
+                    .into_iter()
+                    .map(|value| Frame::Bulk(value.map(Into::into)))
+                    .collect(),
+            )),
+            Response::Missing { .. } => Frame::Bulk(None),
+            Response::Integer { value } => Frame::Integer(value),
+            Response::Keys { keys } => {
+                Frame::Array(Some(keys.into_iter().map(Frame::bulk).collect()))
+            }
+            Response::Pong { msg: None } => Frame::Simple("PONG".into()),
+            Response::Pong { msg: Some(msg) } => Frame::bulk(msg),
+            Response::Subscription {
+                kind,
+                channel,
+                count,
+            } => Frame::Array(Some(vec![
+                Frame::bulk(kind),
+                Frame::Bulk(channel.map(Into::into)),
+                Frame::Integer(count as i64),
+            ])),
+            Response::Message {
+                pattern: None,
+                channel,
+                payload,
+            } => Frame::Array(Some(vec![
+                Frame::bulk("message"),
+                Frame::bulk(channel),
+                Frame::bulk(payload),
+            ])),
+            Response::Message {
+                pattern: Some(pattern),
+                channel,
+                payload,
+            } => Frame::Array(Some(vec![
+                Frame::bulk("pmessage"),
+                Frame::bulk(pattern),
+                Frame::bulk(channel),
+                Frame::bulk(payload),
+            ])),
+            Response::Info { lines } => Frame::bulk(lines.join("\r\n") + "\r\n"),
+            Response::Queued => Frame::Simple("QUEUED".into()),
+            Response::Exec { results } => Frame::Array(
+                results.map(|results| results.into_iter().map(Response::into_frame).collect()),
+            ),
+            Response::Error { msg } => Frame::Error(format!("ERR {}", msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Request;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            Request::parse("SET greeting hello world"),
+            Ok(Request::Set {
+                key: "greeting".into(),
+                value: "hello world".into(),
+            })
+        );
+        assert_eq!(
+            Request::parse("DEL a b"),
+            Ok(Request::Del {
+                keys: vec!["a".into(), "b".into()],
+            })
+        );
+        assert_eq!(
+            Request::parse("EXPIRE a 10"),
+            Ok(Request::Expire {
+                key: "a".into(),
+                seconds: 10,
+            })
+        );
+        assert_eq!(
+            Request::parse("MSET a 1 b 2"),
+            Ok(Request::MSet {
+                pairs: vec![("a".into(), "1".into()), ("b".into(), "2".into())],
+            })
+        );
+        assert!(Request::parse("MSET a 1 b").is_err());
+        assert!(Request::parse("GET").is_err());
+        assert!(Request::parse("GET a b").is_err());
+        assert!(Request::parse("EXPIRE a soon").is_err());
+        // Parses, the database is the one refusing it.
+        assert_eq!(
+            Request::parse("EXPIRE a 9223372036854775807"),
+            Ok(Request::Expire {
+                key: "a".into(),
+                seconds: i64::MAX,
+            })
+        );
+        assert_eq!(
+            Request::parse("get foo"),
+            Ok(Request::Get { key: "foo".into() })
+        );
+        assert_eq!(
+            Request::parse("CAS stock 3 2"),
+            Ok(Request::Cas {
+                key: "stock".into(),
+                expected: "3".into(),
+                new: "2".into(),
+            })
+        );
+        assert!(Request::parse("MULTI now").is_err());
+        assert!(Request::parse("").is_err());
+    }
+}
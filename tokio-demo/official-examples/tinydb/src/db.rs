@@ -0,0 +1,641 @@
+//! The key/value store shared by every connection.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::aof::Record;
+use crate::glob;
+
+/// A value stored in the database together with its optional expiry.
+///
+/// Expiry is kept as wall clock time so it can be written to the append-only
+/// file as an absolute timestamp and survive a restart.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub value: String,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Entry {
+    pub fn new(value: String) -> Entry {
+        Entry {
+            value,
+            expires_at: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        matches!(self.expires_at, Some(at) if at <= now)
+    }
+}
+
+/// One independently locked partition of the keyspace.
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<String, Entry>,
+    /// Flags of the connections `WATCH`ing a key, raised when it is written.
+    watchers: HashMap<String, Vec<Arc<AtomicBool>>>,
+}
+
+/// How many writes a replica may fall behind before it has to resync.
+const FEED_CAPACITY: usize = 16 * 1024;
+
+/// Numbers every write and fans it out to the connected replicas, see
+/// [`feed_replicas`].
+///
+/// The offset of a write is its position in the history of the database, it
+/// is what replicas report to tell how far they got.
+struct Feed {
+    offset: u64,
+    tx: broadcast::Sender<(u64, Record)>,
+}
+
+/// The in-memory database shared amongst all clients.
+///
+/// This database will be shared via `Arc`, so to mutate the internal maps
+/// we're going to use a `Mutex` for interior mutability. Rather than a single
+/// map behind a single lock, which would serialise every request of every
+/// connection, keys are spread by hash over a number of shards that are
+/// locked independently. Commands touching several keys lock all of the
+/// shards involved, always in ascending order so two such commands can't
+/// deadlock, and are therefore still atomic.
+///
+/// Every mutation is also sent to the append-only file writer (if persistence
+/// is enabled) while the lock of its shard is still held, so the log sees the
+/// writes to each key in exactly the order they were applied. The same goes
+/// for the feed of writes replicas receive. Both are unbounded channels,
+/// sending on them doesn't take a lock shared by all shards.
+pub struct Database {
+    shards: Box<[Mutex<Shard>]>,
+    hasher: RandomState,
+    aof: Option<UnboundedSender<Record>>,
+    /// Writes on their way to [`feed_replicas`].
+    feed_tx: UnboundedSender<Record>,
+    feed_rx: Mutex<Option<UnboundedReceiver<Record>>>,
+    feed: Mutex<Feed>,
+}
+
+impl Database {
+    /// Creates a database with `shards` partitions holding `entries`.
+    ///
+    /// [`feed_replicas`] has to run alongside it for writes to be numbered
+    /// and streamed to replicas.
+    pub fn new(
+        entries: HashMap<String, Entry>,
+        aof: Option<UnboundedSender<Record>>,
+        shards: usize,
+    ) -> Database {
+        assert!(shards > 0, "a database needs at least one shard");
+        let (feed_tx, feed_rx) = mpsc::unbounded_channel();
+        let mut db = Database {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+            aof,
+            feed_tx,
+            feed_rx: Mutex::new(Some(feed_rx)),
+            feed: Mutex::new(Feed {
+                offset: 0,
+                tx: broadcast::channel(FEED_CAPACITY).0,
+            }),
+        };
+        for (key, entry) in entries {
+            let index = db.shard_index(&key);
+            db.shards[index]
+                .get_mut()
+                .unwrap()
+                .entries
+                .insert(key, entry);
+        }
+        db
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    /// Locks the shards holding `keys`, in ascending order.
+    pub fn lock<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> Locked<'_> {
+        let mut indices: Vec<usize> = keys.into_iter().map(|key| self.shard_index(key)).collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        let guards = indices
+            .into_iter()
+            .map(|i| (i, self.shards[i].lock().unwrap()))
+            .collect();
+        Locked { db: self, guards }
+    }
+
+    /// Locks every shard, stopping the whole database.
+    pub fn lock_all(&self) -> Locked<'_> {
+        let guards = self
+            .shards
+            .iter()
+            .enumerate()
+            .map(|(i, shard)| (i, shard.lock().unwrap()))
+            .collect();
+        Locked { db: self, guards }
+    }
+
+    /// Returns the value of `key`, if it exists and has not expired.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lock([key]).get(key)
+    }
+
+    /// Sets `key` to `value`, clearing any expiry, and returns the previous
+    /// value.
+    pub fn set(&self, key: String, value: String) -> Option<String> {
+        let mut locked = self.lock([key.as_str()]);
+        locked.set(key, value)
+    }
+
+    /// Removes the given keys, returning how many of them existed.
+    pub fn del(&self, keys: &[String]) -> usize {
+        self.lock(keys.iter().map(String::as_str)).del(keys)
+    }
+
+    /// Returns how many of the given keys exist. A key mentioned twice is
+    /// counted twice, like Redis does.
+    pub fn exists(&self, keys: &[String]) -> usize {
+        self.lock(keys.iter().map(String::as_str)).exists(keys)
+    }
+
+    /// Increments the integer stored at `key` by one, treating a missing key
+    /// as `0`. The expiry of the key, if any, is preserved.
+    pub fn incr(&self, key: &str) -> Result<i64, String> {
+        self.lock([key]).incr(key)
+    }
+
+    /// Sets a time to live of `seconds` on `key`. Returns `false` if the key
+    /// does not exist. A non-positive timeout deletes the key straight away,
+    /// one too large to be represented is an error.
+    pub fn expire(&self, key: &str, seconds: i64) -> Result<bool, String> {
+        self.lock([key]).expire(key, seconds)
+    }
+
+    /// Returns the remaining time to live of `key` in seconds, `-1` if the key
+    /// has no expiry and `-2` if it does not exist.
+    pub fn ttl(&self, key: &str) -> i64 {
+        self.lock([key]).ttl(key)
+    }
+
+    /// Returns the values of all `keys`, read atomically.
+    pub fn mget(&self, keys: &[String]) -> Vec<Option<String>> {
+        self.lock(keys.iter().map(String::as_str)).mget(keys)
+    }
+
+    /// Sets all `pairs` atomically: no reader can observe some of them set
+    /// and others not.
+    pub fn mset(&self, pairs: Vec<(String, String)>) {
+        self.lock(pairs.iter().map(|(key, _)| key.as_str()))
+            .mset(pairs)
+    }
+
+    /// Returns all live keys matching the glob `pattern`, sorted.
+    ///
+    /// Shards are scanned one after the other, so unlike the commands above
+    /// this doesn't stop the whole database while it runs.
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let now = SystemTime::now();
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            keys.extend(
+                shard
+                    .entries
+                    .iter()
+                    .filter(|(key, entry)| !entry.is_expired(now) && glob::matches(pattern, key))
+                    .map(|(key, _)| key.clone()),
+            );
+        }
+        keys.sort();
+        keys
+    }
+
+    /// Drops every expired entry, returning how many were removed.
+    ///
+    /// Expired keys are already invisible to readers; this only reclaims the
+    /// memory of keys nobody touches anymore, and tells whoever watches them.
+    /// The append-only file doesn't need to hear about it since the expiry
+    /// timestamp is already recorded there.
+    pub fn purge_expired(&self) -> usize {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock().unwrap();
+            let expired: Vec<String> = shard
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.is_expired(now))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                shard.entries.remove(&key);
+                touch(&shard, &key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Returns a copy of every live entry, used to compact the append-only
+    /// file. Each shard is copied under its own lock.
+    pub fn snapshot(&self) -> Vec<(String, Entry)> {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            entries.extend(
+                shard
+                    .entries
+                    .iter()
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .map(|(key, entry)| (key.clone(), entry.clone())),
+            );
+        }
+        entries
+    }
+
+    /// Applies a record received from a primary.
+    pub fn apply(&self, record: Record) {
+        match record.into_change() {
+            (key, Some(entry)) => self.lock([key.as_str()]).put(&key, entry),
+            (key, None) => {
+                self.lock([key.as_str()]).remove(&key);
+            }
+        }
+    }
+
+    /// Replaces the whole contents of the database, used when a replica
+    /// receives a snapshot from its primary.
+    pub fn replace_all(&self, entries: HashMap<String, Entry>) {
+        let mut locked = self.lock_all();
+        let existing: Vec<String> = locked
+            .guards
+            .iter()
+            .flat_map(|(_, shard)| shard.entries.keys().cloned())
+            .collect();
+        for key in existing {
+            locked.remove(&key);
+        }
+        for (key, entry) in entries {
+            locked.put(&key, entry);
+        }
+    }
+
+    /// Raises `dirty` whenever `key` is written to or expires, until
+    /// [`Database::unwatch`] is called with the same flag.
+    pub fn watch(&self, key: &str, dirty: &Arc<AtomicBool>) {
+        let mut locked = self.lock([key]);
+        let watchers = locked
+            .shard(key)
+            .watchers
+            .entry(key.to_string())
+            .or_default();
+        if !watchers.iter().any(|w| Arc::ptr_eq(w, dirty)) {
+            watchers.push(dirty.clone());
+        }
+    }
+
+    pub fn unwatch(&self, key: &str, dirty: &Arc<AtomicBool>) {
+        let mut locked = self.lock([key]);
+        let shard = locked.shard(key);
+        if let Some(watchers) = shard.watchers.get_mut(key) {
+            watchers.retain(|w| !Arc::ptr_eq(w, dirty));
+            if watchers.is_empty() {
+                shard.watchers.remove(key);
+            }
+        }
+    }
+
+    /// Returns the offset of the latest write.
+    pub fn offset(&self) -> u64 {
+        self.feed.lock().unwrap().offset
+    }
+
+    /// Starts listening to writes, returning the offset of the latest write
+    /// that will *not* be received.
+    pub fn subscribe(&self) -> (u64, broadcast::Receiver<(u64, Record)>) {
+        let feed = self.feed.lock().unwrap();
+        (feed.offset, feed.tx.subscribe())
+    }
+
+    fn log(&self, record: Record) {
+        // The feed task lives as long as the server, and the server exits
+        // if the append-only file task fails, so neither send can fail while
+        // writes are still being acknowledged.
+        if let Some(aof) = &self.aof {
+            let _ = aof.send(record.clone());
+        }
+        let _ = self.feed_tx.send(record);
+    }
+}
+
+/// A set of locked shards, see [`Database::lock`].
+///
+/// Operations on a `Locked` may only touch keys whose shards were locked,
+/// anything else is a bug and panics.
+pub struct Locked<'a> {
+    db: &'a Database,
+    guards: Vec<(usize, MutexGuard<'a, Shard>)>,
+}
+
+impl Locked<'_> {
+    fn shard(&mut self, key: &str) -> &mut Shard {
+        let index = self.db.shard_index(key);
+        let (_, guard) = self
+            .guards
+            .iter_mut()
+            .find(|(i, _)| *i == index)
+            .expect("key's shard is not locked");
+        guard
+    }
+
+    /// Stores `entry` under `key` as is.
+    pub fn put(&mut self, key: &str, entry: Entry) {
+        self.db.log(Record::put(key, &entry));
+        let shard = self.shard(key);
+        shard.entries.insert(key.to_string(), entry);
+        touch(shard, key);
+    }
+
+    /// Removes `key`, returning whether it existed.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let shard = self.shard(key);
+        if shard.entries.remove(key).is_none() {
+            return false;
+        }
+        touch(shard, key);
+        self.db.log(Record::Del {
+            key: key.to_string(),
+        });
+        true
+    }
+
+    /// Returns the value of `key`. Like every lookup, this removes the key if
+    /// it has expired.
+    pub fn get(&mut self, key: &str) -> Option<String> {
+        live(self.shard(key), key).map(|entry| entry.value.clone())
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Option<String> {
+        let previous = self.get(&key);
+        self.put(&key, Entry::new(value));
+        previous
+    }
+
+    pub fn del(&mut self, keys: &[String]) -> usize {
+        let mut removed = 0;
+        for key in keys {
+            if live(self.shard(key), key).is_some() {
+                self.remove(key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    pub fn exists(&mut self, keys: &[String]) -> usize {
+        keys.iter()
+            .filter(|key| live(self.shard(key), key).is_some())
+            .count()
+    }
+
+    pub fn incr(&mut self, key: &str) -> Result<i64, String> {
+        let (current, expires_at) = match live(self.shard(key), key) {
+            Some(entry) => match entry.value.parse::<i64>() {
+                Ok(n) => (n, entry.expires_at),
+                Err(_) => return Err("value is not an integer or out of range".into()),
+            },
+            None => (0, None),
+        };
+        let next = current
+            .checked_add(1)
+            .ok_or("increment or decrement would overflow")?;
+
+        let entry = Entry {
+            value: next.to_string(),
+            expires_at,
+        };
+        self.put(key, entry);
+        Ok(next)
+    }
+
+    pub fn expire(&mut self, key: &str, seconds: i64) -> Result<bool, String> {
+        let mut entry = match live(self.shard(key), key) {
+            Some(entry) => entry.clone(),
+            None => return Ok(false),
+        };
+
+        if seconds <= 0 {
+            self.remove(key);
+        } else {
+            // Checked, as panicking here would poison the shard's lock.
+            let expires_at = SystemTime::now()
+                .checked_add(Duration::from_secs(seconds as u64))
+                .ok_or("invalid expire time")?;
+            entry.expires_at = Some(expires_at);
+            self.put(key, entry);
+        }
+        Ok(true)
+    }
+
+    pub fn ttl(&mut self, key: &str) -> i64 {
+        match live(self.shard(key), key).map(|entry| entry.expires_at) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(at)) => {
+                let left = at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO);
+                // Round up so a key that still exists never reports `0`.
+                left.as_millis().div_ceil(1000) as i64
+            }
+        }
+    }
+
+    pub fn mget(&mut self, keys: &[String]) -> Vec<Option<String>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    pub fn mset(&mut self, pairs: Vec<(String, String)>) {
+        for (key, value) in pairs {
+            self.set(key, value);
+        }
+    }
+
+    /// Sets `key` to `new` only if it currently holds `expected`, returning
+    /// whether it was set. The expiry of the key is cleared, like `SET` does.
+    pub fn cas(&mut self, key: &str, expected: &str, new: String) -> bool {
+        let current = live(self.shard(key), key).map(|entry| entry.value.as_str());
+        if current != Some(expected) {
+            return false;
+        }
+        self.put(key, Entry::new(new));
+        true
+    }
+
+    /// Returns the live keys of the locked shards matching `pattern`, sorted.
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let now = SystemTime::now();
+        let mut keys: Vec<String> = self
+            .guards
+            .iter()
+            .flat_map(|(_, shard)| shard.entries.iter())
+            .filter(|(key, entry)| !entry.is_expired(now) && glob::matches(pattern, key))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+}
+
+/// Tells the connections watching `key` that it was written to.
+fn touch(shard: &Shard, key: &str) {
+    for dirty in shard.watchers.get(key).into_iter().flatten() {
+        dirty.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Looks up `key`, lazily removing it if it has expired. Expiring counts as
+/// a write for the connections watching the key.
+fn live<'a>(shard: &'a mut Shard, key: &str) -> Option<&'a Entry> {
+    if shard.entries.get(key)?.is_expired(SystemTime::now()) {
+        shard.entries.remove(key);
+        touch(shard, key);
+        return None;
+    }
+    shard.entries.get(key)
+}
+
+/// Numbers the writes to `db` in the order they were applied and sends them
+/// to the replicas, for as long as the server runs.
+///
+/// A replica subscribing while some writes are still queued here receives
+/// them, even though its snapshot may already contain them; records describe
+/// the whole state of a key so applying them twice is harmless.
+pub async fn feed_replicas(db: Arc<Database>) {
+    let mut writes = db
+        .feed_rx
+        .lock()
+        .unwrap()
+        .take()
+        .expect("the feed of writes is already running");
+    while let Some(record) = writes.recv().await {
+        let mut feed = db.feed.lock().unwrap();
+        feed.offset += 1;
+        // Failing to send only means no replica is connected right now.
+        let _ = feed.tx.send((feed.offset, record));
+    }
+}
+
+/// Periodically removes expired keys in the background.
+pub async fn reap_expired(db: Arc<Database>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        db.purge_expired();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Database;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn database() -> Database {
+        Database::new(HashMap::new(), None, 4)
+    }
+
+    /// Two keys living in different shards of `db`.
+    fn keys_in_two_shards(db: &Database) -> (String, String) {
+        let first = "key0".to_string();
+        let second = (1..)
+            .map(|i| format!("key{}", i))
+            .find(|key| db.shard_index(key) != db.shard_index(&first))
+            .unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn expire_out_of_range() {
+        let db = database();
+        db.set("k".into(), "v".into());
+        assert_eq!(
+            db.expire("k", i64::MAX),
+            Err("invalid expire time".to_string())
+        );
+        // The shard is still usable and the key untouched.
+        assert_eq!(db.get("k"), Some("v".into()));
+        assert_eq!(db.ttl("k"), -1);
+        assert_eq!(db.expire("k", 10), Ok(true));
+        assert_eq!(db.expire("missing", i64::MAX), Ok(false));
+    }
+
+    #[test]
+    fn keys_and_lock_all_see_every_shard() {
+        let db = Database::new(HashMap::new(), None, 16);
+        let mut expected: Vec<String> = (0..100).map(|i| format!("key{:02}", i)).collect();
+        for key in &expected {
+            db.set(key.clone(), "v".into());
+        }
+        let used = db
+            .shards
+            .iter()
+            .filter(|shard| !shard.lock().unwrap().entries.is_empty())
+            .count();
+        assert!(used > 1, "all keys ended up in {} shard", used);
+
+        expected.sort();
+        assert_eq!(db.keys("*"), expected);
+        assert_eq!(db.lock_all().keys("*"), expected);
+        assert_eq!(db.keys("key1?"), expected[10..20]);
+        assert_eq!(db.snapshot().len(), 100);
+    }
+
+    #[test]
+    fn multi_key_commands_are_atomic_across_shards() {
+        let db = Arc::new(database());
+        let (a, b) = keys_in_two_shards(&db);
+        let keys = [a.clone(), b.clone()];
+
+        let writer = {
+            let (db, a, b) = (db.clone(), a.clone(), b.clone());
+            thread::spawn(move || {
+                for i in 0..10_000 {
+                    db.mset(vec![(a.clone(), i.to_string()), (b.clone(), i.to_string())]);
+                    if i % 3 == 0 {
+                        assert_eq!(db.del(&[b.clone(), a.clone()]), 2);
+                    }
+                }
+            })
+        };
+        // Locking in the opposite order must not deadlock with the writer.
+        let reverse = {
+            let (db, a, b) = (db.clone(), a.clone(), b.clone());
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    db.mset(vec![(b.clone(), "x".into()), (a.clone(), "x".into())]);
+                }
+            })
+        };
+
+        while !(writer.is_finished() && reverse.is_finished()) {
+            let values = db.mget(&keys);
+            assert_eq!(values[0], values[1], "torn read of {:?}", keys);
+            let exists = db.exists(&keys);
+            assert!(exists == 0 || exists == 2, "{} of {:?} exist", exists, keys);
+        }
+        writer.join().unwrap();
+        reverse.join().unwrap();
+    }
+}
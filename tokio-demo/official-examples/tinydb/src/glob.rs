@@ -0,0 +1,118 @@
+//! Redis style glob matching used by `KEYS pattern`.
+//!
+//! Supported syntax:
+//!
+//! * `*` matches any sequence of characters (including none)
+//! * `?` matches exactly one character
+//! * `[abc]`, `[a-z]` and `[^abc]` match one character out of a set
+//! * `\x` matches the character `x` literally
+
+/// Returns `true` if `text` matches the glob `pattern`.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position to resume from when the most recent `*` has to swallow one
+    // more character of the text.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match_class(&pattern[p..], text[t]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(2),
+            Some(&c) => (c == text[t]).then_some(1),
+            None => None,
+        };
+
+        match step {
+            Some(len) => {
+                p += len;
+                t += 1;
+            }
+            None => match backtrack {
+                Some((star, consumed)) => {
+                    p = star + 1;
+                    t = consumed + 1;
+                    backtrack = Some((star, consumed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    // Only trailing stars may be left over once the text is consumed.
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the character class at the start of `pattern`,
+/// returning the length of the class on success.
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let mut i = 1;
+    let negate = pattern.get(i) == Some(&'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    loop {
+        match pattern.get(i) {
+            // An unterminated class is treated as a literal `[`.
+            None => return (c == '[').then_some(1),
+            Some(']') => break,
+            Some('\\') if i + 1 < pattern.len() => {
+                matched |= pattern[i + 1] == c;
+                i += 2;
+            }
+            Some(&start) if pattern.get(i + 1) == Some(&'-') && i + 2 < pattern.len() => {
+                let end = pattern[i + 2];
+                let (lo, hi) = if start <= end { (start, end) } else { (end, start) };
+                matched |= lo <= c && c <= hi;
+                i += 3;
+            }
+            Some(&other) => {
+                matched |= other == c;
+                i += 1;
+            }
+        }
+    }
+
+    (matched != negate).then_some(i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "foo"));
+        assert!(matches("f*", "foo"));
+        assert!(matches("*o", "foo"));
+        assert!(matches("f*o*r", "foobar"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(!matches("f*x", "foo"));
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(matches("foo\\*", "foo*"));
+        assert!(!matches("foo\\*", "foobar"));
+    }
+}
@@ -0,0 +1,16 @@
+//! The building blocks of the tinydb server, see `main.rs` for the protocol.
+//!
+//! They are exposed as a library so the benchmarks can run a server in
+//! process.
+
+#![warn(rust_2018_idioms)]
+
+pub mod aof;
+pub mod cmd;
+pub mod db;
+pub mod glob;
+pub mod pubsub;
+pub mod replication;
+pub mod resp;
+pub mod server;
+pub mod session;
@@ -31,194 +31,238 @@
 //!     GET foo
 //!     foo = tokio
 //!
-//! Namely you can issue two forms of commands:
+//! Namely you can issue the following commands:
 //!
 //! * `GET $key` - this will fetch the value of `$key` from the database and
 //!   return it. The server's database is initially populated with the key `foo`
 //!   set to the value `bar`
 //! * `SET $key $value` - this will set the value of `$key` to `$value`,
-//!   returning the previous value, if any.
+//!   returning the previous value, if any. Any expiry on the key is cleared.
+//! * `DEL $key...` / `EXISTS $key...` - delete the given keys, or count how
+//!   many of them exist.
+//! * `INCR $key` - increment the integer stored at `$key`, starting from `0`.
+//! * `EXPIRE $key $seconds` / `TTL $key` - set or query the time to live of a
+//!   key. Expired keys are hidden straight away and removed by a background
+//!   task.
+//! * `KEYS $pattern` - list the keys matching a glob pattern such as `user:*`.
+//! * `MGET $key...` / `MSET $key $value...` - read or write several keys at
+//!   once, atomically.
+//! * `CAS $key $expected $new` - set `$key` to `$new` only if it currently
+//!   holds `$expected`, returning `1` if it did and `0` otherwise.
+//!
+//! Several commands can be run as one atomic transaction:
+//!
+//! * `MULTI` - start queueing commands instead of running them, each is
+//!   answered with `QUEUED`.
+//! * `EXEC` - run the queued commands and return all their results. If a
+//!   queued command was rejected the whole transaction is discarded.
+//! * `DISCARD` - drop the queued commands.
+//! * `WATCH $key...` / `UNWATCH` - make the next `EXEC` run nothing and
+//!   return `(nil)` if any of the keys is written to before it, by any
+//!   connection, or expires. This is how a client reads a value, computes a new one and
+//!   writes it back without losing a concurrent update: retry until `EXEC`
+//!   succeeds.
+//!
+//! Connections can also message each other through channels:
+//!
+//! * `SUBSCRIBE $channel...` / `PSUBSCRIBE $pattern...` - start receiving the
+//!   messages published to the given channels, or to any channel matching
+//!   the given glob patterns. While subscribed, a connection may only
+//!   (un)subscribe or `PING`.
+//! * `UNSUBSCRIBE [$channel...]` / `PUNSUBSCRIBE [$pattern...]` - stop
+//!   receiving messages, from everything if no argument is given.
+//! * `PUBLISH $channel $message` - send a message to every subscriber,
+//!   returning how many received it. Subscribers that fall too far behind
+//!   are disconnected rather than slowing down the publisher.
+//!
+//! The same database can also be served over RESP, the Redis protocol, on a
+//! second port, so `redis-cli` or any Redis client library can be pointed at
+//! it while the line protocol above keeps working:
+//!
+//!     cargo run --example tinydb 127.0.0.1:8080 --resp 127.0.0.1:6379
+//!     redis-cli -p 6379 SET foo bar
+//!
+//! Over RESP the commands behave like their Redis counterparts, e.g. `GET` of
+//! a missing key returns nil instead of an error, and `PING` is available for
+//! health checks.
+//!
+//! The keyspace is split into independently locked shards so connections
+//! working on different keys don't wait for each other, `--shards $n` picks
+//! how many (16 by default).
+//!
+//! A server can also follow another one as a read-only replica, receiving a
+//! snapshot of its data and then every write as it happens. Point
+//! `--replicaof` at either port of the primary:
+//!
+//!     cargo run --example tinydb 127.0.0.1:8081 --replicaof 127.0.0.1:8080
+//!
+//! Replicas refuse writes. `INFO` shows the role of a server along with
+//! replication offsets and lag, `REPLICAOF $host $port` makes a running server
+//! follow another primary and `REPLICAOF NO ONE` promotes a replica to a
+//! primary, e.g. to fail over.
+//!
+//! By default the database only lives in memory. Pass `--aof $path` to make
+//! it durable: every write is appended to that file, the file is replayed on
+//! start and compacted periodically.
+//!
+//!     cargo run --example tinydb 127.0.0.1:8080 --aof tinydb.aof
 
 #![warn(rust_2018_idioms)]
 
 use tokio::net::TcpListener;
-use tokio_stream::StreamExt;
-use tokio_util::codec::{Framed, LinesCodec};
+use tokio::sync::mpsc;
 
-use futures::SinkExt;
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
-use std::sync::{Arc, Mutex};
-
-/// The in-memory database shared amongst all clients.
-///
-/// This database will be shared via `Arc`, so to mutate the internal map we're
-/// going to use a `Mutex` for interior mutability.
-struct Database {
-    map: Mutex<HashMap<String, String>>,
-}
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tinydb::db::{self, Database, Entry};
+use tinydb::pubsub::PubSub;
+use tinydb::replication::Replication;
+use tinydb::session::Context;
+use tinydb::{aof, server};
+
+/// How often the background task looks for expired keys.
+const REAP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the append-only file is rewritten from a snapshot.
+const COMPACT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of independently locked partitions of the keyspace by default.
+const DEFAULT_SHARDS: usize = 16;
 
-/// Possible requests our clients can send us
-enum Request {
-    Get { key: String },
-    Set { key: String, value: String },
+/// Command line options of the server.
+struct Config {
+    addr: String,
+    resp_addr: Option<String>,
+    aof: Option<PathBuf>,
+    shards: usize,
+    replicaof: Option<String>,
 }
 
-/// Responses to the `Request` commands above
-enum Response {
-    Value {
-        key: String,
-        value: String,
-    },
-    Set {
-        key: String,
-        value: String,
-        previous: Option<String>,
-    },
-    Error {
-        msg: String,
-    },
+impl Config {
+    fn from_args() -> Result<Config, String> {
+        let mut addr = None;
+        let mut resp_addr = None;
+        let mut aof = None;
+        let mut shards = DEFAULT_SHARDS;
+        let mut replicaof = None;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--resp" => {
+                    let addr = args.next().ok_or("--resp must be followed by an address")?;
+                    resp_addr = Some(addr);
+                }
+                "--aof" => {
+                    let path = args.next().ok_or("--aof must be followed by a path")?;
+                    aof = Some(PathBuf::from(path));
+                }
+                "--replicaof" => {
+                    let primary = args
+                        .next()
+                        .ok_or("--replicaof must be followed by the primary's address")?;
+                    replicaof = Some(primary);
+                }
+                "--shards" => {
+                    shards = args
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or("--shards must be followed by a positive number")?;
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(format!("unknown option: {}", flag));
+                }
+                _ if addr.is_none() => addr = Some(arg),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+
+        Ok(Config {
+            addr: addr.unwrap_or_else(|| "127.0.0.1:8080".to_string()),
+            resp_addr,
+            aof,
+            shards,
+            replicaof,
+        })
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     // Parse the address we're going to run this server on
     // and set up our TCP listener to accept connections.
-    let addr = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let config = Config::from_args()?;
 
-    let listener = TcpListener::bind(&addr).await?;
-    println!("Listening on: {}", addr);
+    let listener = TcpListener::bind(&config.addr).await?;
+    println!("Listening on: {}", config.addr);
 
     // Create the shared state of this server that will be shared amongst all
-    // clients. We populate the initial database and then create the `Database`
-    // structure. Note the usage of `Arc` here which will be used to ensure that
-    // each independently spawned client will have a reference to the in-memory
-    // database.
-    let mut initial_db = HashMap::new();
-    initial_db.insert("foo".to_string(), "bar".to_string());
-    let db = Arc::new(Database {
-        map: Mutex::new(initial_db),
-    });
-
-    loop {
-        match listener.accept().await {
-            Ok((socket, _)) => {
-                // After getting a new connection first we see a clone of the database
-                // being created, which is creating a new reference for this connected
-                // client to use.
-                let db = db.clone();
-
-                // Like with other small servers, we'll `spawn` this client to ensure it
-                // runs concurrently with all other clients. The `move` keyword is used
-                // here to move ownership of our db handle into the async closure.
-                tokio::spawn(async move {
-                    // Since our protocol is line-based we use `tokio_codecs`'s `LineCodec`
-                    // to convert our stream of bytes, `socket`, into a `Stream` of lines
-                    // as well as convert our line based responses into a stream of bytes.
-                    let mut lines = Framed::new(socket, LinesCodec::new());
-
-                    // Here for every line we get back from the `Framed` decoder,
-                    // we parse the request, and if it's valid we generate a response
-                    // based on the values in the database.
-                    while let Some(result) = lines.next().await {
-                        match result {
-                            Ok(line) => {
-                                let response = handle_request(&line, &db);
-
-                                let response = response.serialize();
-
-                                if let Err(e) = lines.send(response.as_str()).await {
-                                    println!("error on sending response; error = {:?}", e);
-                                }
-                            }
-                            Err(e) => {
-                                println!("error on decoding from socket; error = {:?}", e);
-                            }
-                        }
-                    }
-
-                    // The connection will be closed at this point as `lines.next()` has returned `None`.
-                });
+    // clients. The initial contents come from the append-only file if there
+    // is one, otherwise we populate the database ourselves. Note the usage of
+    // `Arc` here which will be used to ensure that each independently spawned
+    // client will have a reference to the in-memory database.
+    let db = match config.aof {
+        Some(path) => {
+            let loaded = aof::load(&path)?;
+            let fresh = loaded.is_none();
+            let (tx, rx) = mpsc::unbounded_channel();
+            let db = Arc::new(Database::new(
+                loaded.unwrap_or_default(),
+                Some(tx),
+                config.shards,
+            ));
+            if fresh {
+                db.set("foo".to_string(), "bar".to_string());
             }
-            Err(e) => println!("error accepting socket; error = {:?}", e),
-        }
-    }
-}
 
-fn handle_request(line: &str, db: &Arc<Database>) -> Response {
-    let request = match Request::parse(line) {
-        Ok(req) => req,
-        Err(e) => return Response::Error { msg: e },
+            // A server that can no longer persist writes must not keep
+            // acknowledging them, so losing the file stops the process.
+            let writer_db = db.clone();
+            tokio::spawn(async move {
+                if let Err(e) = aof::run(path, rx, writer_db, COMPACT_INTERVAL).await {
+                    println!("error writing append-only file; error = {:?}", e);
+                    std::process::exit(1);
+                }
+            });
+            db
+        }
+        None => {
+            let mut initial_db = HashMap::new();
+            initial_db.insert("foo".to_string(), Entry::new("bar".to_string()));
+            Arc::new(Database::new(initial_db, None, config.shards))
+        }
     };
 
-    let mut db = db.map.lock().unwrap();
-    match request {
-        Request::Get { key } => match db.get(&key) {
-            Some(value) => Response::Value {
-                key,
-                value: value.clone(),
-            },
-            None => Response::Error {
-                msg: format!("no key {}", key),
-            },
-        },
-        Request::Set { key, value } => {
-            let previous = db.insert(key.clone(), value.clone());
-            Response::Set {
-                key,
-                value,
-                previous,
-            }
-        }
-    }
-}
+    tokio::spawn(db::reap_expired(db.clone(), REAP_INTERVAL));
+    tokio::spawn(db::feed_replicas(db.clone()));
 
-impl Request {
-    fn parse(input: &str) -> Result<Request, String> {
-        let mut parts = input.splitn(3, ' ');
-        match parts.next() {
-            Some("GET") => {
-                let key = parts.next().ok_or("GET must be followed by a key")?;
-                if parts.next().is_some() {
-                    return Err("GET's key must not be followed by anything".into());
-                }
-                Ok(Request::Get {
-                    key: key.to_string(),
-                })
-            }
-            Some("SET") => {
-                let key = match parts.next() {
-                    Some(key) => key,
-                    None => return Err("SET must be followed by a key".into()),
-                };
-                let value = match parts.next() {
-                    Some(value) => value,
-                    None => return Err("SET needs a value".into()),
-                };
-                Ok(Request::Set {
-                    key: key.to_string(),
-                    value: value.to_string(),
-                })
-            }
-            Some(cmd) => Err(format!("unknown command: {}", cmd)),
-            None => Err("empty input".into()),
-        }
+    let ctx = Context {
+        db,
+        pubsub: Arc::new(PubSub::default()),
+        replication: Arc::new(Replication::default()),
+    };
+    if let Some(primary) = config.replicaof {
+        println!("Replicating from: {}", primary);
+        ctx.replication.replicate_from(primary, ctx.db.clone());
     }
-}
 
-impl Response {
-    fn serialize(&self) -> String {
-        match *self {
-            Response::Value { ref key, ref value } => format!("{} = {}", key, value),
-            Response::Set {
-                ref key,
-                ref value,
-                ref previous,
-            } => format!("set {} = `{}`, previous: {:?}", key, value, previous),
-            Response::Error { ref msg } => format!("error: {}", msg),
+    match config.resp_addr {
+        Some(resp_addr) => {
+            let resp_listener = TcpListener::bind(&resp_addr).await?;
+            println!("Listening for RESP on: {}", resp_addr);
+            tokio::join!(
+                server::serve_lines(listener, ctx.clone()),
+                server::serve_resp(resp_listener, ctx),
+            );
         }
+        None => server::serve_lines(listener, ctx).await,
     }
+
+    Ok(())
 }
@@ -0,0 +1,274 @@
+//! Publish/subscribe messaging between connections.
+//!
+//! Every channel (and every pattern) that has at least one subscriber is
+//! backed by a `tokio::sync::broadcast` channel. Publishing never waits for
+//! subscribers: a subscriber that falls more than [`CHANNEL_CAPACITY`]
+//! messages behind is told how many messages it missed and is disconnected,
+//! much like Redis disconnects clients that exceed their pub/sub output
+//! buffer limit.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{StreamExt, StreamMap};
+
+use crate::glob;
+
+/// How many messages a subscriber may lag behind before it is dropped.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// A message published to a channel.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// The registry of channels, shared by all connections.
+#[derive(Default)]
+pub struct PubSub {
+    channels: Mutex<HashMap<String, broadcast::Sender<Message>>>,
+    patterns: Mutex<HashMap<String, broadcast::Sender<Message>>>,
+}
+
+impl PubSub {
+    /// Sends `payload` to everyone subscribed to `channel`, either directly or
+    /// through a pattern, returning the number of subscribers reached.
+    pub fn publish(&self, channel: &str, payload: &str) -> usize {
+        let message = Message {
+            channel: channel.to_string(),
+            payload: payload.to_string(),
+        };
+
+        let mut receivers = 0;
+        if let Some(tx) = self.channels.lock().unwrap().get(channel) {
+            receivers += tx.send(message.clone()).unwrap_or(0);
+        }
+        for (pattern, tx) in self.patterns.lock().unwrap().iter() {
+            if glob::matches(pattern, channel) {
+                receivers += tx.send(message.clone()).unwrap_or(0);
+            }
+        }
+        receivers
+    }
+
+    fn subscribe(
+        map: &Mutex<HashMap<String, broadcast::Sender<Message>>>,
+        name: &str,
+    ) -> broadcast::Receiver<Message> {
+        map.lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Forgets about `name` once its last subscriber is gone.
+    fn release(map: &Mutex<HashMap<String, broadcast::Sender<Message>>>, name: &str) {
+        let mut map = map.lock().unwrap();
+        if map.get(name).is_some_and(|tx| tx.receiver_count() == 0) {
+            map.remove(name);
+        }
+    }
+}
+
+/// Something that happened on one of a connection's subscriptions.
+pub enum Event {
+    Message {
+        /// The pattern that matched, `None` for a direct subscription.
+        pattern: Option<String>,
+        message: Message,
+    },
+    /// The connection didn't keep up and `skipped` messages were lost.
+    Lagged { skipped: u64 },
+}
+
+/// The subscriptions of a single connection.
+///
+/// This lives in the task serving the connection, so subscribing and
+/// unsubscribing never has to coordinate with other connections beyond the
+/// shared [`PubSub`] registry.
+pub struct Subscriptions {
+    pubsub: Arc<PubSub>,
+    channels: StreamMap<String, BroadcastStream<Message>>,
+    patterns: StreamMap<String, BroadcastStream<Message>>,
+}
+
+impl Subscriptions {
+    pub fn new(pubsub: Arc<PubSub>) -> Subscriptions {
+        Subscriptions {
+            pubsub,
+            channels: StreamMap::new(),
+            patterns: StreamMap::new(),
+        }
+    }
+
+    /// The number of channels and patterns subscribed to.
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    pub fn channels(&self) -> Vec<String> {
+        self.channels.keys().cloned().collect()
+    }
+
+    pub fn patterns(&self) -> Vec<String> {
+        self.patterns.keys().cloned().collect()
+    }
+
+    /// Subscribes to `channel`, returning the new subscription count.
+    pub fn subscribe(&mut self, channel: &str) -> usize {
+        if !self.channels.contains_key(channel) {
+            let rx = PubSub::subscribe(&self.pubsub.channels, channel);
+            self.channels
+                .insert(channel.to_string(), BroadcastStream::new(rx));
+        }
+        self.count()
+    }
+
+    /// Unsubscribes from `channel`, returning the new subscription count.
+    pub fn unsubscribe(&mut self, channel: &str) -> usize {
+        if self.channels.remove(channel).is_some() {
+            PubSub::release(&self.pubsub.channels, channel);
+        }
+        self.count()
+    }
+
+    /// Subscribes to channels matching `pattern`, returning the new
+    /// subscription count.
+    pub fn psubscribe(&mut self, pattern: &str) -> usize {
+        if !self.patterns.contains_key(pattern) {
+            let rx = PubSub::subscribe(&self.pubsub.patterns, pattern);
+            self.patterns
+                .insert(pattern.to_string(), BroadcastStream::new(rx));
+        }
+        self.count()
+    }
+
+    /// Unsubscribes from `pattern`, returning the new subscription count.
+    pub fn punsubscribe(&mut self, pattern: &str) -> usize {
+        if self.patterns.remove(pattern).is_some() {
+            PubSub::release(&self.pubsub.patterns, pattern);
+        }
+        self.count()
+    }
+
+    /// Waits for the next message on any subscription. Returns `None` right
+    /// away if there are no subscriptions.
+    pub async fn next(&mut self) -> Option<Event> {
+        // Prefer direct subscriptions so a message matching both a channel
+        // and a pattern is usually delivered as `message` before `pmessage`.
+        let (pattern, item) = tokio::select! {
+            biased;
+            Some((_, item)) = self.channels.next() => (None, item),
+            Some((pattern, item)) = self.patterns.next() => (Some(pattern), item),
+            else => return None,
+        };
+
+        Some(match item {
+            Ok(message) => Event::Message { pattern, message },
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => Event::Lagged { skipped },
+        })
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for channel in self.channels() {
+            self.unsubscribe(&channel);
+        }
+        for pattern in self.patterns() {
+            self.punsubscribe(&pattern);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, PubSub, Subscriptions, CHANNEL_CAPACITY};
+    use std::sync::Arc;
+
+    /// The next message, as `(pattern, channel, payload)`.
+    async fn next_message(subscriptions: &mut Subscriptions) -> (Option<String>, String, String) {
+        match subscriptions.next().await {
+            Some(Event::Message { pattern, message }) => {
+                (pattern, message.channel, message.payload)
+            }
+            Some(Event::Lagged { skipped }) => panic!("lagged by {}", skipped),
+            None => panic!("no subscriptions"),
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_to_channels_and_patterns() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut subscriptions = Subscriptions::new(pubsub.clone());
+        assert_eq!(subscriptions.subscribe("news"), 1);
+        assert_eq!(subscriptions.psubscribe("news.*"), 2);
+
+        assert_eq!(pubsub.publish("news", "hello"), 1);
+        assert_eq!(pubsub.publish("news.tech", "rust"), 1);
+        assert_eq!(pubsub.publish("weather", "rain"), 0);
+
+        assert_eq!(
+            next_message(&mut subscriptions).await,
+            (None, "news".into(), "hello".into())
+        );
+        assert_eq!(
+            next_message(&mut subscriptions).await,
+            (Some("news.*".into()), "news.tech".into(), "rust".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn unsubscribe() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut subscriptions = Subscriptions::new(pubsub.clone());
+        subscriptions.subscribe("news");
+        subscriptions.psubscribe("n*");
+
+        assert_eq!(subscriptions.unsubscribe("news"), 1);
+        assert_eq!(pubsub.publish("news", "hello"), 1);
+        assert_eq!(subscriptions.punsubscribe("n*"), 0);
+        assert_eq!(pubsub.publish("news", "hello"), 0);
+
+        // Channels nobody listens to anymore are forgotten.
+        assert!(pubsub.channels.lock().unwrap().is_empty());
+        assert!(pubsub.patterns.lock().unwrap().is_empty());
+        // The message published in between was queued before unsubscribing
+        // from the pattern and is gone with it.
+        assert!(subscriptions.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dropping_subscriptions_releases_channels() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut subscriptions = Subscriptions::new(pubsub.clone());
+        subscriptions.subscribe("news");
+        drop(subscriptions);
+        assert_eq!(pubsub.publish("news", "hello"), 0);
+        assert!(pubsub.channels.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn report_slow_subscribers() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut subscriptions = Subscriptions::new(pubsub.clone());
+        subscriptions.subscribe("news");
+
+        for i in 0..CHANNEL_CAPACITY + 3 {
+            assert_eq!(pubsub.publish("news", &i.to_string()), 1);
+        }
+        assert!(matches!(
+            subscriptions.next().await,
+            Some(Event::Lagged { skipped: 3 })
+        ));
+    }
+}
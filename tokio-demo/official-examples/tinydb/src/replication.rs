@@ -0,0 +1,456 @@
+//! Primary/replica replication.
+//!
+//! A replica connects to its primary, on either of the primary's ports, and
+//! sends `SYNC`. From then on the connection speaks RESP whatever port it was
+//! opened on, with the following commands, all arrays of bulk strings:
+//!
+//! * primary to replica: `FULLSYNC $offset $count`, followed by `$count`
+//!   records making up a snapshot of the database. `$offset` is the offset of
+//!   the primary's latest write not covered by the stream that follows.
+//! * primary to replica: `REPL $offset $record...` for every write applied
+//!   on the primary afterwards, and `PING $offset` every second so the
+//!   replica knows how far behind it is even when nothing is written.
+//! * replica to primary: `REPLCONF ACK $offset` every second, reporting the
+//!   offset of the latest write the replica applied.
+//!
+//! Records are the same as in the append-only file, they describe the whole
+//! state of a key so a write that made it into the snapshot and is streamed
+//! again afterwards is harmless. A replica that falls too far behind is
+//! disconnected; it reconnects and starts over with a fresh snapshot.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::SinkExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crate::aof::Record;
+use crate::db::Database;
+use crate::resp::{Frame, RespCodec};
+
+/// How often heartbeats and acknowledgements are sent.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a replica waits before reconnecting to its primary.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The replication role of the server and everything `INFO` reports about it.
+#[derive(Default)]
+pub struct Replication {
+    role: Mutex<Role>,
+    /// Replicas currently connected to this server, by connection id.
+    replicas: Mutex<HashMap<u64, ReplicaInfo>>,
+    next_replica_id: AtomicU64,
+}
+
+#[derive(Default)]
+enum Role {
+    #[default]
+    Primary,
+    Replica {
+        primary: String,
+        link: Arc<Link>,
+        task: JoinHandle<()>,
+    },
+}
+
+/// The state of a replica's connection to its primary.
+#[derive(Default)]
+struct Link {
+    up: AtomicBool,
+    /// Offset of the latest write applied.
+    applied: AtomicU64,
+    /// Offset of the latest write the primary told us about.
+    primary_offset: AtomicU64,
+    last_io: Mutex<Option<Instant>>,
+}
+
+impl Link {
+    fn heard_from_primary(&self, primary_offset: u64) {
+        self.primary_offset
+            .fetch_max(primary_offset, Ordering::SeqCst);
+        *self.last_io.lock().unwrap() = Some(Instant::now());
+    }
+}
+
+/// What a primary knows about one of its replicas.
+struct ReplicaInfo {
+    addr: SocketAddr,
+    acked: u64,
+    last_ack: Instant,
+}
+
+impl Replication {
+    pub fn is_replica(&self) -> bool {
+        matches!(*self.role.lock().unwrap(), Role::Replica { .. })
+    }
+
+    /// Turns the server into a replica of `primary`, dropping the link to
+    /// the previous primary if there was one.
+    pub fn replicate_from(&self, primary: String, db: Arc<Database>) {
+        let link = Arc::new(Link::default());
+        let task = tokio::spawn(follow(primary.clone(), db, link.clone()));
+
+        let previous = std::mem::replace(
+            &mut *self.role.lock().unwrap(),
+            Role::Replica {
+                primary,
+                link,
+                task,
+            },
+        );
+        if let Role::Replica { task, .. } = previous {
+            task.abort();
+        }
+    }
+
+    /// Turns the server into a primary, keeping its data.
+    pub fn promote(&self) {
+        let previous = std::mem::take(&mut *self.role.lock().unwrap());
+        if let Role::Replica { task, .. } = previous {
+            task.abort();
+        }
+    }
+
+    /// Returns the lines of the replication section of `INFO`.
+    pub fn info(&self, db: &Database) -> Vec<String> {
+        let mut lines = vec!["# Replication".to_string()];
+
+        match &*self.role.lock().unwrap() {
+            Role::Primary => lines.push("role:master".into()),
+            Role::Replica { primary, link, .. } => {
+                let (host, port) = primary.rsplit_once(':').unwrap_or((primary, ""));
+                let applied = link.applied.load(Ordering::SeqCst);
+                let primary_offset = link.primary_offset.load(Ordering::SeqCst);
+                let last_io = match *link.last_io.lock().unwrap() {
+                    Some(at) => at.elapsed().as_secs() as i64,
+                    None => -1,
+                };
+                let status = if link.up.load(Ordering::SeqCst) {
+                    "up"
+                } else {
+                    "down"
+                };
+
+                lines.push("role:slave".into());
+                lines.push(format!("master_host:{}", host));
+                lines.push(format!("master_port:{}", port));
+                lines.push(format!("master_link_status:{}", status));
+                lines.push(format!("master_last_io_seconds_ago:{}", last_io));
+                lines.push(format!("master_repl_offset:{}", primary_offset));
+                lines.push(format!("slave_repl_offset:{}", applied));
+                lines.push(format!(
+                    "slave_lag_offset:{}",
+                    primary_offset.saturating_sub(applied)
+                ));
+            }
+        }
+
+        let offset = db.offset();
+        let replicas = self.replicas.lock().unwrap();
+        let mut ids: Vec<&u64> = replicas.keys().collect();
+        ids.sort();
+        lines.push(format!("connected_slaves:{}", replicas.len()));
+        for (n, id) in ids.into_iter().enumerate() {
+            let replica = &replicas[id];
+            lines.push(format!(
+                "slave{}:ip={},port={},state=online,offset={},lag={},lag_offset={}",
+                n,
+                replica.addr.ip(),
+                replica.addr.port(),
+                replica.acked,
+                replica.last_ack.elapsed().as_secs(),
+                offset.saturating_sub(replica.acked),
+            ));
+        }
+        // This server's own offset, which is what its replicas follow.
+        lines.push(format!("repl_offset:{}", offset));
+
+        lines
+    }
+}
+
+/// Streams the contents of `db` to a replica that sent `SYNC`, until it
+/// disconnects or falls too far behind.
+pub async fn serve_replica(
+    mut frames: Framed<TcpStream, RespCodec>,
+    addr: SocketAddr,
+    db: &Database,
+    replication: &Replication,
+) -> io::Result<()> {
+    // Subscribe before taking the snapshot so no write can slip in between.
+    let (offset, mut writes) = db.subscribe();
+    let snapshot = db.snapshot();
+
+    frames
+        .feed(command(vec![
+            "FULLSYNC".into(),
+            offset.to_string(),
+            snapshot.len().to_string(),
+        ]))
+        .await?;
+    for (key, entry) in snapshot {
+        frames
+            .feed(command(Record::put(&key, &entry).to_args()))
+            .await?;
+    }
+    frames.flush().await?;
+
+    let id = replication.next_replica_id.fetch_add(1, Ordering::SeqCst);
+    replication.replicas.lock().unwrap().insert(
+        id,
+        ReplicaInfo {
+            addr,
+            acked: offset,
+            last_ack: Instant::now(),
+        },
+    );
+
+    let result = stream_writes(&mut frames, &mut writes, id, db, replication).await;
+    replication.replicas.lock().unwrap().remove(&id);
+    result
+}
+
+async fn stream_writes(
+    frames: &mut Framed<TcpStream, RespCodec>,
+    writes: &mut tokio::sync::broadcast::Receiver<(u64, Record)>,
+    id: u64,
+    db: &Database,
+    replication: &Replication,
+) -> io::Result<()> {
+    let mut heartbeat = time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            write = writes.recv() => match write {
+                Ok((offset, record)) => {
+                    let mut args = vec!["REPL".to_string(), offset.to_string()];
+                    args.extend(record.to_args());
+                    frames.send(command(args)).await?;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    return Err(protocol_error(&format!(
+                        "replica fell {} writes behind",
+                        skipped
+                    )));
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            frame = frames.next() => {
+                let args = match frame {
+                    Some(frame) => frame?.into_args().map_err(|e| protocol_error(&e))?,
+                    None => return Ok(()),
+                };
+                match args.as_slice() {
+                    [cmd, sub, acked] if cmd.eq_ignore_ascii_case("REPLCONF")
+                        && sub.eq_ignore_ascii_case("ACK") =>
+                    {
+                        let acked = parse_offset(acked)?;
+                        if let Some(replica) = replication.replicas.lock().unwrap().get_mut(&id) {
+                            replica.acked = acked;
+                            replica.last_ack = Instant::now();
+                        }
+                    }
+                    _ => return Err(protocol_error("unexpected command from replica")),
+                }
+            },
+            _ = heartbeat.tick() => {
+                frames.send(command(vec!["PING".into(), db.offset().to_string()])).await?;
+            }
+        }
+    }
+}
+
+/// Keeps `db` in sync with `primary`, reconnecting whenever the link drops.
+async fn follow(primary: String, db: Arc<Database>, link: Arc<Link>) {
+    loop {
+        if let Err(e) = sync_from(&primary, &db, &link).await {
+            println!("error replicating from {}; error = {}", primary, e);
+        }
+        link.up.store(false, Ordering::SeqCst);
+        time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+async fn sync_from(primary: &str, db: &Database, link: &Link) -> io::Result<()> {
+    let mut socket = TcpStream::connect(primary).await?;
+    // Sent inline rather than as a RESP array, so it is understood on the
+    // line protocol port as well.
+    socket.write_all(b"SYNC\r\n").await?;
+    let mut frames = Framed::new(socket, RespCodec::new());
+
+    let (offset, count) = match next_args(&mut frames).await?.as_slice() {
+        [cmd, offset, count] if cmd == "FULLSYNC" => (parse_offset(offset)?, parse_offset(count)?),
+        _ => return Err(protocol_error("expected FULLSYNC")),
+    };
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        let record =
+            Record::from_args(&next_args(&mut frames).await?).map_err(|e| protocol_error(&e))?;
+        if let (key, Some(entry)) = record.into_change() {
+            entries.insert(key, entry);
+        }
+    }
+    db.replace_all(entries);
+    link.applied.store(offset, Ordering::SeqCst);
+    link.heard_from_primary(offset);
+    link.up.store(true, Ordering::SeqCst);
+    println!("synchronised with primary {} at offset {}", primary, offset);
+
+    let mut ack = time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            args = next_args(&mut frames) => {
+                let args = args?;
+                match args.as_slice() {
+                    [cmd, offset, record @ ..] if cmd == "REPL" => {
+                        let offset = parse_offset(offset)?;
+                        let record = Record::from_args(record).map_err(|e| protocol_error(&e))?;
+                        db.apply(record);
+                        link.applied.store(offset, Ordering::SeqCst);
+                        link.heard_from_primary(offset);
+                    }
+                    [cmd, offset] if cmd == "PING" => link.heard_from_primary(parse_offset(offset)?),
+                    _ => return Err(protocol_error("unexpected command from primary")),
+                }
+            }
+            _ = ack.tick() => {
+                let applied = link.applied.load(Ordering::SeqCst);
+                frames
+                    .send(command(vec!["REPLCONF".into(), "ACK".into(), applied.to_string()]))
+                    .await?;
+            }
+        }
+    }
+}
+
+async fn next_args(frames: &mut Framed<TcpStream, RespCodec>) -> io::Result<Vec<String>> {
+    match frames.next().await {
+        Some(frame) => frame?.into_args().map_err(|e| protocol_error(&e)),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+fn command(args: Vec<String>) -> Frame {
+    Frame::Array(Some(args.into_iter().map(Frame::bulk).collect()))
+}
+
+fn parse_offset(offset: &str) -> io::Result<u64> {
+    offset
+        .parse()
+        .map_err(|_| protocol_error(&format!("invalid offset `{}`", offset)))
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Replication;
+    use crate::cmd::{Request, Response};
+    use crate::db::{self, Database, Entry};
+    use crate::pubsub::PubSub;
+    use crate::server;
+    use crate::session::{Context, Session};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::time;
+
+    fn context(entries: HashMap<String, Entry>) -> Context {
+        let db = Arc::new(Database::new(entries, None, 4));
+        tokio::spawn(db::feed_replicas(db.clone()));
+        Context {
+            db,
+            pubsub: Arc::new(PubSub::default()),
+            replication: Arc::new(Replication::default()),
+        }
+    }
+
+    /// Waits for `done` to hold, failing the test after a while.
+    async fn eventually(what: &str, done: impl Fn() -> bool) {
+        let waiting = async {
+            while !done() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        if time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .is_err()
+        {
+            panic!("timed out waiting for {}", what);
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_and_stream_writes() {
+        let mut entries = HashMap::new();
+        entries.insert("before".to_string(), Entry::new("1".to_string()));
+        let primary = context(entries);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server::serve_resp(listener, primary.clone()));
+
+        let replica = context(HashMap::new());
+        replica.db.set("stale".into(), "x".into());
+        replica
+            .replication
+            .replicate_from(addr.to_string(), replica.db.clone());
+
+        // The snapshot replaces whatever the replica held.
+        eventually("the initial sync", || {
+            replica.db.get("before").as_deref() == Some("1")
+        })
+        .await;
+        assert_eq!(replica.db.get("stale"), None);
+
+        let mut writer = Session::new(primary.clone());
+        writer.execute(Request::parse("SET after 2").unwrap());
+        writer.execute(Request::parse("EXPIRE after 100").unwrap());
+        writer.execute(Request::parse("DEL before").unwrap());
+        eventually("the streamed writes", || {
+            replica.db.get("after").as_deref() == Some("2") && replica.db.get("before").is_none()
+        })
+        .await;
+        assert!(replica.db.ttl("after") > 0);
+
+        assert_eq!(primary.db.offset(), 3);
+        eventually("the replica to report offset 3", || {
+            replica
+                .replication
+                .info(&replica.db)
+                .contains(&"slave_repl_offset:3".to_string())
+        })
+        .await;
+        let info = replica.replication.info(&replica.db);
+        assert!(info.contains(&"role:slave".to_string()), "{:?}", info);
+        assert!(
+            info.contains(&"master_link_status:up".to_string()),
+            "{:?}",
+            info
+        );
+
+        // Replicas refuse writes until promoted.
+        let mut session = Session::new(replica.clone());
+        assert!(matches!(
+            session.execute(Request::parse("SET after 3").unwrap()).as_slice(),
+            [Response::Error { msg }] if msg.starts_with("READONLY")
+        ));
+        replica.replication.promote();
+        assert!(!replica.replication.is_replica());
+        session.execute(Request::parse("SET after 3").unwrap());
+        assert_eq!(primary.db.get("after").as_deref(), Some("2"));
+    }
+}
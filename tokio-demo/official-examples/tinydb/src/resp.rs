@@ -0,0 +1,373 @@
+//! A `tokio_util::codec` implementation of RESP2, the Redis wire protocol.
+//!
+//! Clients send commands as arrays of bulk strings:
+//!
+//! ```text
+//! *2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n
+//! ```
+//!
+//! and the server answers with a single frame of any type. Like Redis, the
+//! decoder also accepts "inline" commands, a plain line of space separated
+//! words, which makes it possible to poke at the server with `telnet`.
+//!
+//! See <https://redis.io/docs/reference/protocol-spec/> for the details.
+
+use std::io;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Largest bulk string we are willing to buffer, same as Redis.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Largest number of elements in a single array.
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+
+/// Longest line (inline command or frame header) we are willing to buffer.
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Deepest nesting of arrays we accept. Commands are flat arrays of bulk
+/// strings, this only leaves room for replies to be decoded too.
+const MAX_DEPTH: usize = 8;
+
+/// A single RESP2 value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    /// A bulk string, `None` being the null bulk string.
+    Bulk(Option<Bytes>),
+    /// An array, `None` being the null array.
+    Array(Option<Vec<Frame>>),
+}
+
+impl Frame {
+    pub fn bulk(data: impl Into<Bytes>) -> Frame {
+        Frame::Bulk(Some(data.into()))
+    }
+
+    /// Turns a command frame into its arguments.
+    pub fn into_args(self) -> Result<Vec<String>, String> {
+        let items = match self {
+            Frame::Array(Some(items)) if !items.is_empty() => items,
+            _ => return Err("Protocol error: expected a non-empty array".into()),
+        };
+
+        items
+            .into_iter()
+            .map(|item| match item {
+                Frame::Bulk(Some(data)) => String::from_utf8(data.to_vec())
+                    .map_err(|_| "arguments must be valid UTF-8".to_string()),
+                _ => Err("Protocol error: expected bulk strings".into()),
+            })
+            .collect()
+    }
+}
+
+/// Encodes and decodes RESP2 frames.
+///
+/// Elements are consumed from the read buffer as soon as they are complete,
+/// so a large array arriving over many reads is only parsed once.
+#[derive(Debug, Default)]
+pub struct RespCodec {
+    /// The arrays being read, outermost first, along with how many elements
+    /// each of them is still missing.
+    partial: Vec<(usize, Vec<Frame>)>,
+}
+
+impl RespCodec {
+    pub fn new() -> RespCodec {
+        RespCodec::default()
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Frame>> {
+        if self.partial.is_empty() && src.first().is_some_and(|&b| b != b'*') {
+            return match parse_inline(src)? {
+                Some((frame, len)) => {
+                    src.advance(len);
+                    Ok(Some(frame))
+                }
+                None => Ok(None),
+            };
+        }
+
+        while let Some((item, len)) = parse_item(src)? {
+            src.advance(len);
+            let mut frame = match item {
+                Item::Frame(frame) => frame,
+                Item::Array(len) => {
+                    if self.partial.len() == MAX_DEPTH {
+                        return Err(protocol_error("arrays nested too deeply"));
+                    }
+                    self.partial.push((len, Vec::with_capacity(len.min(1024))));
+                    continue;
+                }
+            };
+
+            // Hand the element to the array it belongs to, which may complete
+            // that array and in turn the ones around it.
+            loop {
+                let Some((missing, items)) = self.partial.last_mut() else {
+                    return Ok(Some(frame));
+                };
+                items.push(frame);
+                *missing -= 1;
+                if *missing > 0 {
+                    break;
+                }
+                let (_, items) = self.partial.pop().unwrap();
+                frame = Frame::Array(Some(items));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<Frame> for RespCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> io::Result<()> {
+        write_frame(&frame, dst);
+        Ok(())
+    }
+}
+
+fn write_frame(frame: &Frame, dst: &mut BytesMut) {
+    match frame {
+        Frame::Simple(s) => {
+            dst.put_u8(b'+');
+            put_line(dst, s.as_bytes());
+        }
+        Frame::Error(msg) => {
+            dst.put_u8(b'-');
+            put_line(dst, msg.as_bytes());
+        }
+        Frame::Integer(n) => {
+            dst.put_u8(b':');
+            put_line(dst, n.to_string().as_bytes());
+        }
+        Frame::Bulk(None) => dst.put_slice(b"$-1\r\n"),
+        Frame::Bulk(Some(data)) => {
+            dst.put_u8(b'$');
+            put_line(dst, data.len().to_string().as_bytes());
+            put_line(dst, data);
+        }
+        Frame::Array(None) => dst.put_slice(b"*-1\r\n"),
+        Frame::Array(Some(items)) => {
+            dst.put_u8(b'*');
+            put_line(dst, items.len().to_string().as_bytes());
+            for item in items {
+                write_frame(item, dst);
+            }
+        }
+    }
+}
+
+fn put_line(dst: &mut BytesMut, line: &[u8]) {
+    dst.put_slice(line);
+    dst.put_slice(b"\r\n");
+}
+
+/// What starts at the beginning of the read buffer.
+enum Item {
+    /// A complete frame.
+    Frame(Frame),
+    /// The header of a non-empty array, whose elements follow.
+    Array(usize),
+}
+
+/// Parses one item from the start of `src`, returning it together with the
+/// number of bytes it occupies, or `None` if more data is needed.
+fn parse_item(src: &[u8]) -> io::Result<Option<(Item, usize)>> {
+    let (line, mut pos) = match read_line(src)? {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let (kind, rest) = match line.split_first() {
+        Some(split) => split,
+        None => return Err(protocol_error("empty frame header")),
+    };
+
+    let frame = match kind {
+        b'+' => Frame::Simple(to_string(rest)?),
+        b'-' => Frame::Error(to_string(rest)?),
+        b':' => Frame::Integer(parse_int(rest)?),
+        b'$' => match parse_len(rest, MAX_BULK_LEN)? {
+            None => Frame::Bulk(None),
+            Some(len) => {
+                if src.len() < pos + len + 2 {
+                    return Ok(None);
+                }
+                if &src[pos + len..pos + len + 2] != b"\r\n" {
+                    return Err(protocol_error("bulk string is not terminated by CRLF"));
+                }
+                let data = Bytes::copy_from_slice(&src[pos..pos + len]);
+                pos += len + 2;
+                Frame::bulk(data)
+            }
+        },
+        b'*' => match parse_len(rest, MAX_ARRAY_LEN)? {
+            None => Frame::Array(None),
+            Some(0) => Frame::Array(Some(Vec::new())),
+            Some(len) => return Ok(Some((Item::Array(len), pos))),
+        },
+        other => {
+            return Err(protocol_error(&format!(
+                "unexpected frame type `{}`",
+                *other as char
+            )))
+        }
+    };
+
+    Ok(Some((Item::Frame(frame), pos)))
+}
+
+/// Parses an inline command into an array of bulk strings.
+fn parse_inline(src: &[u8]) -> io::Result<Option<(Frame, usize)>> {
+    let (line, len) = match read_line(src)? {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let line = to_string(line)?;
+    let items = line
+        .split_whitespace()
+        .map(|word| Frame::bulk(word.to_string()))
+        .collect();
+    Ok(Some((Frame::Array(Some(items)), len)))
+}
+
+/// Finds the first CRLF terminated line in `src`, returning the line without
+/// the terminator and the length including it. A bare LF is accepted too so
+/// inline commands can be typed by hand.
+fn read_line(src: &[u8]) -> io::Result<Option<(&[u8], usize)>> {
+    match src.iter().position(|&b| b == b'\n') {
+        Some(end) => {
+            let line = &src[..end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            Ok(Some((line, end + 1)))
+        }
+        None if src.len() > MAX_LINE_LEN => Err(protocol_error("line too long")),
+        None => Ok(None),
+    }
+}
+
+fn parse_int(digits: &[u8]) -> io::Result<i64> {
+    to_string(digits)?
+        .parse()
+        .map_err(|_| protocol_error("invalid integer"))
+}
+
+/// Parses the length of a bulk string or array, `-1` meaning null.
+fn parse_len(digits: &[u8], max: usize) -> io::Result<Option<usize>> {
+    match parse_int(digits)? {
+        -1 => Ok(None),
+        n if n < 0 || n as usize > max => Err(protocol_error("invalid length")),
+        n => Ok(Some(n as usize)),
+    }
+}
+
+fn to_string(bytes: &[u8]) -> io::Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| protocol_error("invalid UTF-8"))
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Protocol error: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, RespCodec};
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn decode_command_in_pieces() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$3\r\nf"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(b"oo\r\n*1\r\n$4\r\nPING\r\n");
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.into_args(), Ok(vec!["GET".into(), "foo".into()]));
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.into_args(), Ok(vec!["PING".into()]));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_inline_command() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"SET  foo bar\r\n"[..]);
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            frame.into_args(),
+            Ok(vec!["SET".into(), "foo".into(), "bar".into()])
+        );
+    }
+
+    #[test]
+    fn consume_elements_as_they_arrive() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        // Only the unfinished element is left to be parsed again.
+        assert_eq!(&buf[..], b"$1");
+
+        buf.extend_from_slice(b"\r\nv\r\n");
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            frame.into_args(),
+            Ok(vec!["SET".into(), "k".into(), "v".into()])
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn reject_deep_nesting() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*1\r\n".repeat(200_000)[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*1\r\n*1\r\n*0\r\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Array(Some(vec![Frame::Array(Some(vec![
+                Frame::Array(Some(Vec::new()))
+            ]))])))
+        );
+    }
+
+    #[test]
+    fn reject_malformed_frames() {
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::from(&b"*1\r\n$3\r\nGETX\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut buf = BytesMut::from(&b"*1\r\n?3\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let frame = Frame::Array(Some(vec![
+            Frame::Simple("OK".into()),
+            Frame::Error("ERR no".into()),
+            Frame::Integer(-3),
+            Frame::bulk("hello\r\nworld"),
+            Frame::Bulk(None),
+            Frame::Array(None),
+        ]));
+
+        let mut codec = RespCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(frame.clone(), &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(frame));
+    }
+}
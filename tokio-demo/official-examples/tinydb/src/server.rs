@@ -0,0 +1,162 @@
+//! Accept loops for the two protocols the server speaks.
+
+use std::fmt::{Debug, Display};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::SinkExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedParts, LinesCodec};
+
+use crate::cmd::{Request, Response};
+use crate::replication;
+use crate::resp::{Frame, RespCodec};
+use crate::session::{Context, Session};
+
+/// How long to try telling a subscriber that it is being disconnected.
+const FAREWELL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Serves the human friendly line protocol on `listener`.
+pub async fn serve_lines(listener: TcpListener, ctx: Context) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                // After getting a new connection first we see a clone of the shared
+                // state being created, which is creating a new reference to the
+                // database for this connected client to use.
+                let ctx = ctx.clone();
+
+                // Like with other small servers, we'll `spawn` this client to ensure it
+                // runs concurrently with all other clients. The `move` keyword is used
+                // here to move ownership of our db handle into the async closure.
+                tokio::spawn(async move {
+                    // Since our protocol is line-based we use `tokio_codecs`'s `LineCodec`
+                    // to convert our stream of bytes, `socket`, into a `Stream` of lines
+                    // as well as convert our line based responses into a stream of bytes.
+                    let lines = LinesCodec::new();
+                    serve_connection(
+                        socket,
+                        lines,
+                        ctx,
+                        |line: String| Request::parse(&line),
+                        |response| response.serialize(),
+                    )
+                    .await;
+                });
+            }
+            Err(e) => println!("error accepting socket; error = {:?}", e),
+        }
+    }
+}
+
+/// Serves RESP2 on `listener` so `redis-cli` and Redis client libraries can
+/// talk to the database.
+pub async fn serve_resp(listener: TcpListener, ctx: Context) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                let ctx = ctx.clone();
+
+                tokio::spawn(async move {
+                    serve_connection(
+                        socket,
+                        RespCodec::new(),
+                        ctx,
+                        |frame: Frame| frame.into_args().and_then(Request::from_args),
+                        Response::into_frame,
+                    )
+                    .await;
+                });
+            }
+            Err(e) => println!("error accepting socket; error = {:?}", e),
+        }
+    }
+}
+
+/// Runs one connection until the client hangs up.
+///
+/// `parse` and `render` translate between the frames of `codec` and the
+/// protocol independent `Request`/`Response` types.
+async fn serve_connection<C, I, O>(
+    socket: TcpStream,
+    codec: C,
+    ctx: Context,
+    parse: impl Fn(I) -> Result<Request, String>,
+    render: impl Fn(Response) -> O,
+) where
+    C: Decoder<Item = I> + Encoder<O>,
+    <C as Decoder>::Error: Debug + Display,
+    <C as Encoder<O>>::Error: Debug,
+{
+    let mut frames = Framed::new(socket, codec);
+    let mut session = Session::new(ctx);
+
+    loop {
+        // While subscribed to something, messages pushed to the connection
+        // are interleaved with the responses to its requests.
+        let responses = tokio::select! {
+            result = frames.next() => match result {
+                // Here for every frame we get back from the `Framed` decoder,
+                // we parse the request, and if it's valid we generate a response
+                // based on the values in the database.
+                Some(Ok(frame)) => match parse(frame) {
+                    // A replica wants to follow this server, from now on the
+                    // connection is all about replication.
+                    Ok(Request::Sync) if !session.is_subscribed() => {
+                        let (frames, addr) = into_resp(frames);
+                        let ctx = session.context();
+                        if let Err(e) = replication::serve_replica(frames, addr, &ctx.db, &ctx.replication).await {
+                            println!("error streaming to replica {}; error = {}", addr, e);
+                        }
+                        return;
+                    }
+                    Ok(request) => session.execute(request),
+                    Err(msg) => session.reject(msg),
+                },
+                // There is no telling where the next request starts after a
+                // decoding error, so report it and hang up.
+                Some(Err(e)) => {
+                    println!("error on decoding from socket; error = {:?}", e);
+                    let msg = e.to_string();
+                    let _ = frames.send(render(Response::Error { msg })).await;
+                    break;
+                }
+                // The connection will be closed at this point as `frames.next()` has returned `None`.
+                None => break,
+            },
+            Some(message) = session.next_message(), if session.is_subscribed() => match message {
+                Ok(response) => vec![response],
+                // The client may well not be reading at all, so don't wait
+                // forever to tell it why it is being dropped.
+                Err(response) => {
+                    let _ = time::timeout(FAREWELL_TIMEOUT, frames.send(render(response))).await;
+                    break;
+                }
+            },
+        };
+
+        for response in responses {
+            if let Err(e) = frames.send(render(response)).await {
+                println!("error on sending response; error = {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Switches a connection over to RESP, keeping whatever was already read or
+/// is waiting to be written.
+fn into_resp<C>(frames: Framed<TcpStream, C>) -> (Framed<TcpStream, RespCodec>, SocketAddr) {
+    let old = frames.into_parts();
+    let addr = old
+        .io
+        .peer_addr()
+        .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+
+    let mut parts = FramedParts::new::<Frame>(old.io, RespCodec::new());
+    parts.read_buf = old.read_buf;
+    parts.write_buf = old.write_buf;
+    (Framed::from_parts(parts), addr)
+}
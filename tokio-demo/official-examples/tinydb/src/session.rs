@@ -0,0 +1,621 @@
+//! Executes requests on behalf of a single connection.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::cmd::{Request, Response};
+use crate::db::{Database, Locked};
+use crate::pubsub::{Event, PubSub, Subscriptions};
+use crate::replication::Replication;
+
+/// State shared by every connection of the server.
+#[derive(Clone)]
+pub struct Context {
+    pub db: Arc<Database>,
+    pub pubsub: Arc<PubSub>,
+    pub replication: Arc<Replication>,
+}
+
+/// The state of one connection.
+pub struct Session {
+    ctx: Context,
+    subscriptions: Subscriptions,
+    /// Commands queued since `MULTI`, `None` outside of a transaction.
+    multi: Option<Vec<Request>>,
+    /// Whether a command was refused since `MULTI`, which makes `EXEC` fail.
+    multi_failed: bool,
+    watched: Vec<String>,
+    /// Raised by the database when one of the `watched` keys is written to.
+    dirty: Arc<AtomicBool>,
+}
+
+impl Session {
+    pub fn new(ctx: Context) -> Session {
+        let subscriptions = Subscriptions::new(ctx.pubsub.clone());
+        Session {
+            ctx,
+            subscriptions,
+            multi: None,
+            multi_failed: false,
+            watched: Vec::new(),
+            dirty: Arc::default(),
+        }
+    }
+
+    /// Runs `request`, returning the responses to send back. Most requests
+    /// have exactly one response, but (un)subscribing confirms every channel
+    /// separately.
+    pub fn execute(&mut self, request: Request) -> Vec<Response> {
+        if !self.subscriptions.is_empty() && !request.allowed_while_subscribed() {
+            return vec![Response::Error {
+                msg: "only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed while subscribed"
+                    .into(),
+            }];
+        }
+
+        if request.is_write() && self.ctx.replication.is_replica() {
+            return vec![self
+                .fail_transaction("READONLY You can't write against a read only replica.".into())];
+        }
+
+        if let Some(queued) = &mut self.multi {
+            let response = match request {
+                Request::Exec => self.exec(),
+                Request::Discard => {
+                    self.multi = None;
+                    self.unwatch();
+                    Response::Ok
+                }
+                Request::Multi => self.fail_transaction("MULTI calls can not be nested".into()),
+                Request::Watch { .. } => Response::Error {
+                    msg: "WATCH inside MULTI is not allowed".into(),
+                },
+                request if request.allowed_in_transaction() => {
+                    queued.push(request);
+                    Response::Queued
+                }
+                _ => self.fail_transaction("command not allowed inside a transaction".into()),
+            };
+            return vec![response];
+        }
+
+        let db = &self.ctx.db;
+        let response = match request {
+            Request::Keys { pattern } => Response::Keys {
+                keys: db.keys(&pattern),
+            },
+            request if request.allowed_in_transaction() => {
+                let mut locked = db.lock(request.keys());
+                run(&mut locked, &self.ctx.pubsub, request)
+            }
+            Request::Multi => {
+                self.multi = Some(Vec::new());
+                Response::Ok
+            }
+            Request::Exec => Response::Error {
+                msg: "EXEC without MULTI".into(),
+            },
+            Request::Discard => Response::Error {
+                msg: "DISCARD without MULTI".into(),
+            },
+            Request::Watch { keys } => {
+                for key in keys {
+                    if !self.watched.contains(&key) {
+                        db.watch(&key, &self.dirty);
+                        self.watched.push(key);
+                    }
+                }
+                Response::Ok
+            }
+            Request::Unwatch => {
+                self.unwatch();
+                Response::Ok
+            }
+            Request::Info { section } => {
+                let lines = match section {
+                    Some(section) if !section.eq_ignore_ascii_case("replication") => Vec::new(),
+                    _ => self.ctx.replication.info(db),
+                };
+                Response::Info { lines }
+            }
+            Request::ReplicaOf { primary: None } => {
+                self.ctx.replication.promote();
+                Response::Ok
+            }
+            Request::ReplicaOf {
+                primary: Some(primary),
+            } => {
+                self.ctx.replication.replicate_from(primary, db.clone());
+                Response::Ok
+            }
+            // The connection is handed over to `replication::serve_replica`
+            // before it gets here.
+            Request::Sync => Response::Error {
+                msg: "SYNC is not allowed here".into(),
+            },
+            Request::Subscribe { channels } => {
+                return self.change_subscriptions("subscribe", channels, Subscriptions::subscribe)
+            }
+            Request::Unsubscribe { channels } => {
+                let channels = if channels.is_empty() {
+                    self.subscriptions.channels()
+                } else {
+                    channels
+                };
+                return self.change_subscriptions(
+                    "unsubscribe",
+                    channels,
+                    Subscriptions::unsubscribe,
+                );
+            }
+            Request::PSubscribe { patterns } => {
+                return self.change_subscriptions("psubscribe", patterns, Subscriptions::psubscribe)
+            }
+            Request::PUnsubscribe { patterns } => {
+                let patterns = if patterns.is_empty() {
+                    self.subscriptions.patterns()
+                } else {
+                    patterns
+                };
+                return self.change_subscriptions(
+                    "punsubscribe",
+                    patterns,
+                    Subscriptions::punsubscribe,
+                );
+            }
+            // Everything else can be queued in a transaction and was handled
+            // by `run` above.
+            _ => unreachable!(),
+        };
+        vec![response]
+    }
+
+    /// Answers a request that could not be parsed. Inside `MULTI` this also
+    /// dooms the transaction, so a typo can't make `EXEC` run half of it.
+    pub fn reject(&mut self, msg: String) -> Vec<Response> {
+        vec![self.fail_transaction(msg)]
+    }
+
+    fn fail_transaction(&mut self, msg: String) -> Response {
+        if self.multi.is_some() {
+            self.multi_failed = true;
+        }
+        Response::Error { msg }
+    }
+
+    /// Runs the queued commands of a transaction, all under a lock of the
+    /// whole database so no other connection sees it half done. Nothing is
+    /// run if one of the watched keys was written, or expired, since it was
+    /// watched.
+    fn exec(&mut self) -> Response {
+        let queued = self.multi.take().unwrap_or_default();
+        if std::mem::take(&mut self.multi_failed) {
+            self.unwatch();
+            return Response::Error {
+                msg: "EXECABORT Transaction discarded because of previous errors.".into(),
+            };
+        }
+
+        let mut locked = self.ctx.db.lock_all();
+        // A watched key may have run out without anyone looking it up yet,
+        // looking it up now expires it and raises `dirty`.
+        for key in &self.watched {
+            locked.get(key);
+        }
+        let results = if self.dirty.load(Ordering::SeqCst) {
+            None
+        } else {
+            let results = queued
+                .into_iter()
+                .map(|request| run(&mut locked, &self.ctx.pubsub, request))
+                .collect();
+            Some(results)
+        };
+        drop(locked);
+
+        self.unwatch();
+        Response::Exec { results }
+    }
+
+    fn unwatch(&mut self) {
+        for key in self.watched.drain(..) {
+            self.ctx.db.unwatch(&key, &self.dirty);
+        }
+        self.dirty.store(false, Ordering::SeqCst);
+    }
+
+    fn change_subscriptions(
+        &mut self,
+        kind: &'static str,
+        names: Vec<String>,
+        change: fn(&mut Subscriptions, &str) -> usize,
+    ) -> Vec<Response> {
+        // Unsubscribing from everything while not subscribed to anything
+        // still gets a confirmation, like in Redis.
+        if names.is_empty() {
+            return vec![Response::Subscription {
+                kind,
+                channel: None,
+                count: self.subscriptions.count(),
+            }];
+        }
+
+        names
+            .into_iter()
+            .map(|name| {
+                let count = change(&mut self.subscriptions, &name);
+                Response::Subscription {
+                    kind,
+                    channel: Some(name),
+                    count,
+                }
+            })
+            .collect()
+    }
+
+    /// Waits for a message on one of the connection's subscriptions.
+    ///
+    /// Returns `Err` with a final error response if the connection fell too
+    /// far behind and has to be dropped.
+    pub async fn next_message(&mut self) -> Option<Result<Response, Response>> {
+        match self.subscriptions.next().await? {
+            Event::Message { pattern, message } => Some(Ok(Response::Message {
+                pattern,
+                channel: message.channel,
+                payload: message.payload,
+            })),
+            Event::Lagged { skipped } => Some(Err(Response::Error {
+                msg: format!("subscriber too slow, {} messages dropped", skipped),
+            })),
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        !self.subscriptions.is_empty()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.unwatch();
+    }
+}
+
+/// Runs a request that only needs the keys it mentions, whose shards are
+/// locked in `locked`, or no key at all.
+fn run(locked: &mut Locked<'_>, pubsub: &PubSub, request: Request) -> Response {
+    match request {
+        Request::Get { key } => match locked.get(&key) {
+            Some(value) => Response::Value { key, value },
+            None => Response::Missing { key },
+        },
+        Request::Set { key, value } => {
+            let previous = locked.set(key.clone(), value.clone());
+            Response::Set {
+                key,
+                value,
+                previous,
+            }
+        }
+        Request::Del { keys } => Response::Integer {
+            value: locked.del(&keys) as i64,
+        },
+        Request::Exists { keys } => Response::Integer {
+            value: locked.exists(&keys) as i64,
+        },
+        Request::Incr { key } => match locked.incr(&key) {
+            Ok(value) => Response::Integer { value },
+            Err(msg) => Response::Error { msg },
+        },
+        Request::Expire { key, seconds } => match locked.expire(&key, seconds) {
+            Ok(existed) => Response::Integer {
+                value: existed as i64,
+            },
+            Err(msg) => Response::Error { msg },
+        },
+        Request::Ttl { key } => Response::Integer {
+            value: locked.ttl(&key),
+        },
+        Request::Keys { pattern } => Response::Keys {
+            keys: locked.keys(&pattern),
+        },
+        Request::MGet { keys } => Response::Values {
+            values: locked.mget(&keys),
+        },
+        Request::MSet { pairs } => {
+            locked.mset(pairs);
+            Response::Ok
+        }
+        Request::Cas { key, expected, new } => Response::Integer {
+            value: locked.cas(&key, &expected, new) as i64,
+        },
+        Request::Ping { msg } => Response::Pong { msg },
+        Request::Publish { channel, message } => Response::Integer {
+            value: pubsub.publish(&channel, &message) as i64,
+        },
+        request => unreachable!("{:?} can't be run under a lock", request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Session};
+    use crate::cmd::{Request, Response};
+    use crate::db::{Database, Entry};
+    use crate::pubsub::{PubSub, CHANNEL_CAPACITY};
+    use crate::replication::Replication;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    fn context() -> Context {
+        Context {
+            db: Arc::new(Database::new(HashMap::new(), None, 4)),
+            pubsub: Arc::new(PubSub::default()),
+            replication: Arc::new(Replication::default()),
+        }
+    }
+
+    /// Runs a line protocol command, which must have a single response.
+    fn run(session: &mut Session, line: &str) -> Response {
+        let mut responses = session.execute(Request::parse(line).unwrap());
+        assert_eq!(responses.len(), 1, "{:?}", responses);
+        responses.pop().unwrap()
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_receive() {
+        let ctx = context();
+        let mut subscriber = Session::new(ctx.clone());
+        let mut publisher = Session::new(ctx);
+
+        assert_eq!(
+            subscriber.execute(Request::parse("SUBSCRIBE a b").unwrap()),
+            vec![
+                Response::Subscription {
+                    kind: "subscribe",
+                    channel: Some("a".into()),
+                    count: 1,
+                },
+                Response::Subscription {
+                    kind: "subscribe",
+                    channel: Some("b".into()),
+                    count: 2,
+                },
+            ]
+        );
+        run(&mut subscriber, "PSUBSCRIBE c*");
+        assert!(matches!(
+            run(&mut subscriber, "GET a"),
+            Response::Error { .. }
+        ));
+
+        assert_eq!(
+            run(&mut publisher, "PUBLISH a hello world"),
+            Response::Integer { value: 1 }
+        );
+        assert_eq!(
+            run(&mut publisher, "PUBLISH cats meow"),
+            Response::Integer { value: 1 }
+        );
+        assert_eq!(
+            subscriber.next_message().await,
+            Some(Ok(Response::Message {
+                pattern: None,
+                channel: "a".into(),
+                payload: "hello world".into(),
+            }))
+        );
+        assert_eq!(
+            subscriber.next_message().await,
+            Some(Ok(Response::Message {
+                pattern: Some("c*".into()),
+                channel: "cats".into(),
+                payload: "meow".into(),
+            }))
+        );
+
+        // Unsubscribing from everything leaves the connection free to run
+        // other commands again.
+        assert_eq!(
+            subscriber
+                .execute(Request::parse("UNSUBSCRIBE").unwrap())
+                .len(),
+            2
+        );
+        run(&mut subscriber, "PUNSUBSCRIBE");
+        assert!(!subscriber.is_subscribed());
+        assert_eq!(
+            run(&mut publisher, "PUBLISH a again"),
+            Response::Integer { value: 0 }
+        );
+        assert!(matches!(
+            run(&mut subscriber, "GET a"),
+            Response::Missing { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn drop_slow_subscribers() {
+        let ctx = context();
+        let mut subscriber = Session::new(ctx.clone());
+        run(&mut subscriber, "SUBSCRIBE a");
+
+        for _ in 0..CHANNEL_CAPACITY + 1 {
+            ctx.pubsub.publish("a", "spam");
+        }
+        assert_eq!(
+            subscriber.next_message().await,
+            Some(Err(Response::Error {
+                msg: "subscriber too slow, 1 messages dropped".into(),
+            }))
+        );
+    }
+
+    #[test]
+    fn exec_and_discard() {
+        let mut session = Session::new(context());
+        assert_eq!(run(&mut session, "MULTI"), Response::Ok);
+        assert_eq!(run(&mut session, "SET a 1"), Response::Queued);
+        assert_eq!(run(&mut session, "INCR a"), Response::Queued);
+        assert_eq!(
+            run(&mut session, "EXEC"),
+            Response::Exec {
+                results: Some(vec![
+                    Response::Set {
+                        key: "a".into(),
+                        value: "1".into(),
+                        previous: None,
+                    },
+                    Response::Integer { value: 2 },
+                ]),
+            }
+        );
+
+        run(&mut session, "MULTI");
+        run(&mut session, "SET a 10");
+        assert_eq!(run(&mut session, "DISCARD"), Response::Ok);
+        assert_eq!(
+            run(&mut session, "GET a"),
+            Response::Value {
+                key: "a".into(),
+                value: "2".into(),
+            }
+        );
+        assert!(matches!(run(&mut session, "EXEC"), Response::Error { .. }));
+        assert!(matches!(
+            run(&mut session, "DISCARD"),
+            Response::Error { .. }
+        ));
+
+        // A command refused while queueing dooms the whole transaction.
+        run(&mut session, "MULTI");
+        run(&mut session, "SET a 10");
+        session.reject("unknown command: NOPE".into());
+        assert_eq!(
+            run(&mut session, "EXEC"),
+            Response::Error {
+                msg: "EXECABORT Transaction discarded because of previous errors.".into(),
+            }
+        );
+        assert_eq!(session.ctx.db.get("a").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn exec_aborts_when_a_watched_key_changes() {
+        let ctx = context();
+        let mut session = Session::new(ctx.clone());
+        let mut other = Session::new(ctx.clone());
+
+        run(&mut session, "WATCH a");
+        run(&mut session, "MULTI");
+        run(&mut session, "SET a mine");
+        run(&mut other, "SET a theirs");
+        assert_eq!(run(&mut session, "EXEC"), Response::Exec { results: None });
+        assert_eq!(ctx.db.get("a").as_deref(), Some("theirs"));
+
+        // EXEC forgets the watched keys, whatever its outcome.
+        run(&mut session, "MULTI");
+        run(&mut session, "SET a mine");
+        assert!(matches!(
+            run(&mut session, "EXEC"),
+            Response::Exec { results: Some(_) }
+        ));
+
+        // So does DISCARD.
+        run(&mut session, "WATCH a");
+        run(&mut session, "MULTI");
+        run(&mut session, "DISCARD");
+        run(&mut other, "SET a theirs");
+        run(&mut session, "MULTI");
+        run(&mut session, "SET a mine");
+        assert!(matches!(
+            run(&mut session, "EXEC"),
+            Response::Exec { results: Some(_) }
+        ));
+
+        // Writing a watched key yourself also counts.
+        run(&mut session, "WATCH a");
+        run(&mut session, "SET a again");
+        run(&mut session, "MULTI");
+        assert_eq!(run(&mut session, "EXEC"), Response::Exec { results: None });
+    }
+
+    #[test]
+    fn exec_aborts_when_a_watched_key_expires() {
+        let ctx = context();
+        let expiring = |key: &str| {
+            let entry = Entry {
+                value: "v".into(),
+                expires_at: Some(SystemTime::now() + Duration::from_millis(20)),
+            };
+            ctx.db.lock([key]).put(key, entry);
+        };
+        let mut session = Session::new(ctx.clone());
+
+        // Nobody looks the key up before EXEC.
+        expiring("a");
+        run(&mut session, "WATCH a");
+        thread::sleep(Duration::from_millis(50));
+        run(&mut session, "MULTI");
+        assert_eq!(run(&mut session, "EXEC"), Response::Exec { results: None });
+
+        // Another connection finds it expired.
+        expiring("b");
+        run(&mut session, "WATCH b");
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(ctx.db.get("b"), None);
+        run(&mut session, "MULTI");
+        assert_eq!(run(&mut session, "EXEC"), Response::Exec { results: None });
+
+        // The background reaper removes it.
+        expiring("c");
+        run(&mut session, "WATCH c");
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(ctx.db.purge_expired(), 1);
+        run(&mut session, "MULTI");
+        assert_eq!(run(&mut session, "EXEC"), Response::Exec { results: None });
+    }
+
+    #[test]
+    fn compare_and_set() {
+        let ctx = context();
+        let mut session = Session::new(ctx.clone());
+        let mut other = Session::new(ctx.clone());
+        run(&mut session, "SET stock 3");
+
+        assert_eq!(
+            run(&mut session, "CAS stock 3 2"),
+            Response::Integer { value: 1 }
+        );
+        assert_eq!(
+            run(&mut session, "CAS stock 3 1"),
+            Response::Integer { value: 0 }
+        );
+        assert_eq!(
+            run(&mut session, "CAS missing 3 1"),
+            Response::Integer { value: 0 }
+        );
+        assert_eq!(ctx.db.get("stock").as_deref(), Some("2"));
+        assert_eq!(ctx.db.get("missing"), None);
+
+        // A successful CAS is a write to whoever watches the key, a failed
+        // one isn't.
+        run(&mut session, "WATCH stock");
+        run(&mut other, "CAS stock 3 1");
+        run(&mut session, "MULTI");
+        assert!(matches!(
+            run(&mut session, "EXEC"),
+            Response::Exec { results: Some(_) }
+        ));
+        run(&mut session, "WATCH stock");
+        run(&mut other, "CAS stock 2 1");
+        run(&mut session, "MULTI");
+        assert_eq!(run(&mut session, "EXEC"), Response::Exec { results: None });
+    }
+}
@@ -0,0 +1,577 @@
+//! 限制并发数的异步任务执行器。
+//!
+//! [`run_bounded`] 从一个任务流里不断取任务，同时最多执行 `concurrency` 个，
+//! 结果也是一个流，可以按完成的顺序或者按提交的顺序取：
+//!
+//! ```
+//! use std::time::Duration;
+//! use futures::StreamExt;
+//! use worker_pool::{run_bounded, Retry};
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let jobs = futures::stream::iter(0..10).map(|i| {
+//!     move || async move {
+//!         tokio::time::sleep(Duration::from_millis(10)).await;
+//!         Ok::<_, std::io::Error>(i * 2)
+//!     }
+//! });
+//!
+//! let results: Vec<_> = run_bounded(jobs, 3)
+//!     .ordered()
+//!     .timeout(Duration::from_secs(1))
+//!     .retry(Retry::times(2))
+//!     .map(|done| done.result.unwrap())
+//!     .collect()
+//!     .await;
+//! assert_eq!(results, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+//! # }
+//! ```
+
+mod retry;
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    stream::{Fuse, Stream, StreamExt},
+    FutureExt,
+};
+use tokio::task::JoinSet;
+
+pub use retry::Retry;
+
+/// 可以执行（和重试）的任务，每次调用 [`Job::run`] 执行一次。
+///
+/// 返回 `Future` 的 `FnMut` 闭包都实现了这个 trait。
+pub trait Job: Send + 'static {
+    type Output: Send + 'static;
+    type Error: Send + 'static;
+    type Future: Future<Output = Result<Self::Output, Self::Error>> + Send + 'static;
+
+    fn run(&mut self) -> Self::Future;
+}
+
+impl<F, Fut, T, E> Job for F
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    type Output = T;
+    type Error = E;
+    type Future = Fut;
+
+    fn run(&mut self) -> Fut {
+        self()
+    }
+}
+
+/// 一个任务的最终结果。
+#[derive(Debug)]
+pub struct Completed<T, E> {
+    /// 任务在任务流里的序号，从 0 开始。
+    pub index: usize,
+    /// 一共执行了几次，重试过的话大于 1。
+    pub attempts: u32,
+    pub result: Result<T, JobError<E>>,
+}
+
+/// 任务重试完以后还是失败了。
+#[derive(Debug)]
+pub enum JobError<E> {
+    /// 任务自己返回的错误。
+    Failed(E),
+    /// 最后一次执行超时了。
+    TimedOut(Duration),
+    /// 任务 panic 了，里面是 panic 的消息。
+    Panicked(String),
+}
+
+/// 传给进度回调的统计信息。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// 已经开始的任务数。
+    pub started: usize,
+    /// 正在执行（包括等待重试）的任务数。
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// 一共重试了多少次。
+    pub retries: usize,
+    /// 因为别的任务失败而被取消的任务数。
+    pub cancelled: usize,
+}
+
+type Callback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// 进度统计和回调，任务在 tokio 的线程上重试时也要更新。
+#[derive(Default)]
+struct Tracker {
+    progress: Mutex<Progress>,
+    callback: Option<Callback>,
+}
+
+impl Tracker {
+    fn update(&self, f: impl FnOnce(&mut Progress)) {
+        let snapshot = {
+            let mut progress = self.progress.lock().unwrap();
+            f(&mut progress);
+            progress.clone()
+        };
+        if let Some(callback) = &self.callback {
+            callback(&snapshot);
+        }
+    }
+}
+
+/// 从 `jobs` 里取任务，同时最多执行 `concurrency` 个，返回结果流。
+///
+/// 每个任务都用 [`tokio::spawn`] 在当前的运行时上执行，所以必须在 tokio
+/// 运行时里使用返回的流。默认按完成的顺序返回结果，不超时、不重试，一个
+/// 任务失败不影响其他任务；可以用 [`RunBounded`] 的方法修改。丢弃结果流会
+/// 取消所有正在执行的任务。
+///
+/// # Panics
+///
+/// `concurrency` 为 0 时会 panic。
+pub fn run_bounded<S>(jobs: S, concurrency: usize) -> RunBounded<S>
+where
+    S: Stream,
+    S::Item: Job,
+{
+    assert!(concurrency > 0, "concurrency must be at least 1");
+    RunBounded {
+        jobs: Box::pin(jobs.fuse()),
+        concurrency,
+        ordered: false,
+        timeout: None,
+        retry: Retry::none(),
+        fail_fast: false,
+        tracker: Arc::default(),
+        tasks: JoinSet::new(),
+        exhausted: false,
+        next_index: 0,
+        buffered: BTreeMap::new(),
+        next_output: 0,
+        failure: None,
+        finished: false,
+    }
+}
+
+type Output<J> = Completed<<J as Job>::Output, <J as Job>::Error>;
+
+/// [`run_bounded`] 返回的结果流。
+pub struct RunBounded<S>
+where
+    S: Stream,
+    S::Item: Job,
+{
+    jobs: Pin<Box<Fuse<S>>>,
+    concurrency: usize,
+    ordered: bool,
+    timeout: Option<Duration>,
+    retry: Retry,
+    fail_fast: bool,
+    tracker: Arc<Tracker>,
+    tasks: JoinSet<Output<S::Item>>,
+    /// 任务流结束了，或者因为 `fail_fast` 不再开始新任务了。
+    exhausted: bool,
+    /// 下一个任务的序号。
+    next_index: usize,
+    /// 按顺序返回时，已经完成但还不能返回的结果。
+    buffered: BTreeMap<usize, Output<S::Item>>,
+    /// 按顺序返回时，下一个要返回的序号。
+    next_output: usize,
+    /// 打开了 `fail_fast` 并且有任务失败了，先把它前面的结果返回完再返回它。
+    failure: Option<Output<S::Item>>,
+    finished: bool,
+}
+
+impl<S> RunBounded<S>
+where
+    S: Stream,
+    S::Item: Job,
+{
+    /// 按任务在任务流里的顺序返回结果。
+    ///
+    /// 已经完成但前面还有任务没完成的结果要先存着，这些结果也算在并发数里，
+    /// 所以一个很慢的任务会让后面的任务等着。
+    pub fn ordered(mut self) -> Self {
+        self.ordered = true;
+        self
+    }
+
+    /// 每次执行最多等 `timeout`，超时算失败，可以重试。
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// 有任务（重试完以后）失败时取消其他正在执行的任务，不再开始新任务，
+    /// 返回这个失败的结果以后结果流就结束了。
+    ///
+    /// 按顺序返回时，失败的任务前面已经完成的结果会先返回，还没完成的就被
+    /// 取消了。
+    pub fn fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    /// 任务开始、结束、重试或者被取消时调用 `callback`。
+    ///
+    /// 回调可能在 tokio 的工作线程上调用，不要在里面阻塞。
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        Arc::get_mut(&mut self.tracker)
+            .expect("on_progress must be called before polling")
+            .callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// 当前的统计信息。
+    pub fn progress(&self) -> Progress {
+        self.tracker.progress.lock().unwrap().clone()
+    }
+
+    fn spawn(&mut self, job: S::Item) {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.tracker.update(|p| {
+            p.started += 1;
+            p.running += 1;
+        });
+
+        let task = attempt(job, self.timeout, self.retry, Arc::clone(&self.tracker));
+        self.tasks.spawn(async move {
+            let (attempts, result) = task.await;
+            Completed {
+                index,
+                attempts,
+                result,
+            }
+        });
+    }
+
+    /// 取消所有正在执行的任务，不再开始新任务。
+    fn cancel(&mut self) {
+        let cancelled = self.tasks.len();
+        self.tasks.abort_all();
+        self.tasks.detach_all();
+        self.exhausted = true;
+        if cancelled > 0 {
+            self.tracker.update(|p| {
+                p.running -= cancelled;
+                p.cancelled += cancelled;
+            });
+        }
+    }
+
+    /// 按顺序返回时，取下一个可以返回的结果。
+    fn next_buffered(&mut self) -> Option<Output<S::Item>> {
+        if let Some(done) = self.buffered.remove(&self.next_output) {
+            self.next_output += 1;
+            return Some(done);
+        }
+        let failure = self.failure.take()?;
+        self.buffered.clear();
+        self.finished = true;
+        Some(failure)
+    }
+}
+
+// 结果只会被移动，从来不会被固定（pin），`jobs` 自己在 `Pin<Box<_>>` 里。
+impl<S> Unpin for RunBounded<S>
+where
+    S: Stream,
+    S::Item: Job,
+{
+}
+
+impl<S> Stream for RunBounded<S>
+where
+    S: Stream,
+    S::Item: Job,
+{
+    type Item = Output<S::Item>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.finished {
+                return Poll::Ready(None);
+            }
+            if this.ordered {
+                if let Some(done) = this.next_buffered() {
+                    return Poll::Ready(Some(done));
+                }
+            }
+
+            while !this.exhausted && this.tasks.len() + this.buffered.len() < this.concurrency {
+                match this.jobs.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(job)) => this.spawn(job),
+                    Poll::Ready(None) => this.exhausted = true,
+                    Poll::Pending => break,
+                }
+            }
+
+            let done = match this.tasks.poll_join_next(cx) {
+                Poll::Ready(Some(Ok(done))) => done,
+                // `attempt` 会接住 panic，只有被取消的任务才会走到这里。
+                Poll::Ready(Some(Err(_))) => continue,
+                // 没有正在执行的任务了；任务流还没结束的话它会叫醒我们。
+                Poll::Ready(None) if this.exhausted => {
+                    this.finished = true;
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            };
+
+            let failed = done.result.is_err();
+            this.tracker.update(|p| {
+                p.running -= 1;
+                if failed {
+                    p.failed += 1;
+                } else {
+                    p.succeeded += 1;
+                }
+            });
+
+            if failed && this.fail_fast {
+                this.cancel();
+                if this.ordered {
+                    this.failure = Some(done);
+                    continue;
+                }
+                this.finished = true;
+                return Poll::Ready(Some(done));
+            }
+            if !this.ordered {
+                return Poll::Ready(Some(done));
+            }
+            this.buffered.insert(done.index, done);
+        }
+    }
+}
+
+/// 执行一个任务，超时或者失败的话按 `retry` 重试。返回执行次数和最终结果。
+async fn attempt<J: Job>(
+    mut job: J,
+    timeout: Option<Duration>,
+    retry: Retry,
+    tracker: Arc<Tracker>,
+) -> (u32, Result<J::Output, JobError<J::Error>>) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let run = AssertUnwindSafe(job.run())
+            .catch_unwind()
+            .map(|result| match result {
+                Ok(Ok(value)) => Ok(value),
+                Ok(Err(e)) => Err(JobError::Failed(e)),
+                Err(payload) => Err(JobError::Panicked(panic_message(payload))),
+            });
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, run)
+                .await
+                .unwrap_or(Err(JobError::TimedOut(timeout))),
+            None => run.await,
+        };
+
+        let error = match result {
+            Ok(value) => return (attempts, Ok(value)),
+            Err(error @ JobError::Panicked(_)) => return (attempts, Err(error)),
+            Err(error) => error,
+        };
+        if attempts > retry.max_retries() {
+            return (attempts, Err(error));
+        }
+        tracker.update(|p| p.retries += 1);
+        tokio::time::sleep(retry.delay(attempts)).await;
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "Box<dyn Any>".to_string(),
+        },
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for JobError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Failed(e) => write!(f, "job failed: {e}"),
+            JobError::TimedOut(timeout) => write!(f, "job timed out after {timeout:?}"),
+            JobError::Panicked(message) => write!(f, "job panicked: {message}"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for JobError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            JobError::Failed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// `i` 号任务睡 `delays[i]` 毫秒后返回 `i`。
+    fn sleepers(
+        delays: &'static [u64],
+    ) -> impl Stream<Item = impl Job<Output = usize, Error = String>> {
+        stream::iter(delays.iter().enumerate()).map(|(i, &ms)| {
+            move || async move {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                Ok(i)
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn orders_results_on_request() {
+        const DELAYS: &[u64] = &[60, 10, 30, 0];
+
+        let unordered: Vec<_> = run_bounded(sleepers(DELAYS), 4)
+            .map(|done| done.result.unwrap())
+            .collect()
+            .await;
+        assert_eq!(unordered, [3, 1, 2, 0]);
+
+        let ordered: Vec<_> = run_bounded(sleepers(DELAYS), 4)
+            .ordered()
+            .map(|done| done.result.unwrap())
+            .collect()
+            .await;
+        assert_eq!(ordered, [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn never_exceeds_concurrency() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let jobs = stream::iter(0..20).map(|_| {
+            let (running, peak) = (Arc::clone(&running), Arc::clone(&peak));
+            move || {
+                let (running, peak) = (Arc::clone(&running), Arc::clone(&peak));
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok::<_, ()>(())
+                }
+            }
+        });
+
+        let last = Arc::new(Mutex::new(Progress::default()));
+        let seen = Arc::clone(&last);
+        let count = run_bounded(jobs, 3)
+            .on_progress(move |p| {
+                assert!(p.running <= 3);
+                *seen.lock().unwrap() = p.clone();
+            })
+            .count()
+            .await;
+
+        assert_eq!(count, 20);
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        let last = last.lock().unwrap();
+        assert_eq!((last.started, last.succeeded, last.running), (20, 20, 0));
+    }
+
+    #[tokio::test]
+    async fn retries_timeouts_and_failures() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        // 第一次超时，第二次失败，第三次成功。
+        let flaky = move || {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match call {
+                    0 => tokio::time::sleep(Duration::from_secs(10)).await,
+                    1 => return Err("nope"),
+                    _ => {}
+                }
+                Ok(call)
+            }
+        };
+        let retry = Retry::times(2).backoff(Duration::from_millis(1));
+
+        let done = run_bounded(stream::iter([flaky]), 1)
+            .timeout(Duration::from_millis(50))
+            .retry(retry)
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(done.attempts, 3);
+        assert_eq!(done.result.unwrap(), 2);
+
+        let broken = || async { Err::<(), _>("broken") };
+        let done = run_bounded(stream::iter([broken]), 1)
+            .retry(retry)
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(done.attempts, 3);
+        assert!(matches!(done.result, Err(JobError::Failed("broken"))));
+
+        // panic 不重试。
+        let panics = || async { panic!("boom") as Result<(), String> };
+        let done = run_bounded(stream::iter([panics]), 1)
+            .retry(retry)
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(done.attempts, 1);
+        assert_eq!(done.result.unwrap_err().to_string(), "job panicked: boom");
+    }
+
+    #[tokio::test]
+    async fn fail_fast_cancels_the_rest() {
+        let jobs = stream::iter(0..10u64).map(|i| {
+            move || async move {
+                tokio::time::sleep(Duration::from_millis(20 * i)).await;
+                if i == 2 {
+                    Err(i)
+                } else {
+                    Ok(i)
+                }
+            }
+        });
+
+        let results = run_bounded(jobs, 4).ordered().fail_fast();
+        let results: Vec<_> = results.collect().await;
+        let indices: Vec<_> = results.iter().map(|done| done.index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+        assert!(matches!(results[2].result, Err(JobError::Failed(2))));
+    }
+}
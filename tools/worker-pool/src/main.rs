@@ -1,6 +1,8 @@
 use std::time::Duration;
+
+use futures::{stream, StreamExt};
 use tokio::runtime;
-use tokio::task::JoinSet;
+use worker_pool::{run_bounded, JobError, Retry};
 
 fn main() {
     let max_task = 5;
@@ -10,46 +12,38 @@ fn main() {
         .build()
         .unwrap();
 
-    let mut set = JoinSet::new();
-
     rt.block_on(async {
         println!("tokio_multi_thread");
-        for i in 0..20 {
-            while set.len() >= max_task {
-                if let Some(res) = set.join_next().await {
-                    let t = match res {
-                        Err(e) => {
-                            println!("err {}", e);
-                            continue;
-                        }
-                        Ok(v) => v,
-                    };
-                    println!("t {}", t);
-                };
-            }
 
-            set.spawn(async move {
+        // 任务是返回 future 的闭包，重试的时候会再调用一次。
+        let jobs = stream::iter(0..20).map(|i| {
+            move || async move {
                 tokio::time::sleep(Duration::from_secs(1)).await;
                 println!("spawn {}", i);
-                i
+                if i == 13 {
+                    return Err(format!("job {i} is unlucky"));
+                }
+                Ok(i)
+            }
+        });
+
+        // 同时最多执行 max_task 个任务，结果按完成的顺序返回。
+        let mut results = run_bounded(jobs, max_task)
+            .timeout(Duration::from_secs(5))
+            .retry(Retry::times(1).backoff(Duration::from_millis(200)))
+            .on_progress(|p| {
+                println!(
+                    "progress: {} running, {} ok, {} failed, {} retries",
+                    p.running, p.succeeded, p.failed, p.retries
+                )
             });
-        }
 
-        // 如果 JoinSet 为空，则 join_next 方法返回 None
-        while let Some(res) = set.join_next().await {
-            let t = match res {
-                Err(e) => {
-                    println!("err {}", e);
-                    continue;
-                }
-                Ok(v) => v,
-            };
-            println!("t {}", t);
+        while let Some(done) = results.next().await {
+            match done.result {
+                Ok(t) => println!("t {}", t),
+                Err(JobError::Failed(e)) => println!("err {} after {} attempts", e, done.attempts),
+                Err(e) => println!("err {}", e),
+            }
         }
-
-        // 下面这种方法也可以等待任务结束
-        // while set.len() > 0 {
-        //     set.join_next().await;
-        // }
     });
 }
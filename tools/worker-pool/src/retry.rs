@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+/// 任务失败或者超时以后的重试策略，默认不重试。
+///
+/// 每次重试前等一会儿，等的时间从 `backoff` 开始每次翻倍，最多
+/// `max_backoff`。panic 的任务不会重试。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    max_retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Retry {
+    /// 不重试。
+    pub fn none() -> Retry {
+        Retry::times(0)
+    }
+
+    /// 最多重试 `max_retries` 次，也就是最多执行 `max_retries + 1` 次。
+    pub fn times(max_retries: u32) -> Retry {
+        Retry {
+            max_retries,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+
+    /// 第一次重试前等多久，默认 100 毫秒。
+    pub fn backoff(mut self, backoff: Duration) -> Retry {
+        self.backoff = backoff;
+        self
+    }
+
+    /// 两次重试之间最多等多久，默认 10 秒。
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Retry {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// 第 `retry` 次重试（从 1 开始）前等多久。
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Retry {
+        Retry::none()
+    }
+}